@@ -0,0 +1,185 @@
+// Reading heights through the chunk HashMap on every access, against the chunk-caching cursor and
+// dense regions, for the access patterns of meshing, the brushes and erosion. Run with
+// `cargo bench --bench sampling`.
+use bevy::math::{vec2, Vec2};
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use petra::petra::cursor::Region;
+use petra::petra::generate;
+use petra::petra::mesh;
+use petra::petra::sampler::{Interpolation, Sampler};
+use petra::petra::terrain::{Bounds, Terrain};
+use petra::petra::tools::{erode, raise, smooth};
+
+fn terrain() -> Terrain {
+    let mut terrain = Terrain::default();
+    generate::fbm(&mut terrain, (-2, -2), (1, 1), 0);
+    terrain
+}
+
+fn copy(terrain: &Terrain) -> Terrain {
+    Terrain {
+        data: terrain.data.clone(),
+        ..Default::default()
+    }
+}
+
+// Heights and six-tap normals for every vertex of a chunk, like mesh_from_sampler.
+fn chunk_reads(get: impl Fn((i32, i32)) -> f32, chunk_size: i32) -> f32 {
+    let mut sum = 0.0;
+    for z in 0..=chunk_size {
+        for x in 0..=chunk_size {
+            sum += get((x, z));
+            for (dx, dz) in [(0, -1), (1, -1), (1, 0), (0, 1), (-1, 1), (-1, 0)] {
+                sum += get((x + dx, z + dz));
+            }
+        }
+    }
+    sum
+}
+
+fn meshing(c: &mut Criterion) {
+    let terrain = terrain();
+    let size = terrain.data.chunk_size as i32;
+    let mut group = c.benchmark_group("meshing");
+    group.bench_function("hashmap", |b| {
+        b.iter(|| chunk_reads(|cell| terrain.data[cell], black_box(size)))
+    });
+    group.bench_function("cursor", |b| {
+        b.iter(|| {
+            let sampler = Sampler::for_terrain(&terrain);
+            chunk_reads(|cell| sampler.get_or(cell, 0.0), black_box(size))
+        })
+    });
+    group.bench_function("generate_mesh", |b| {
+        b.iter(|| mesh::generate_mesh(&terrain, black_box((0, 0))))
+    });
+    group.finish();
+}
+
+fn brushes(c: &mut Criterion) {
+    let base = terrain();
+    let xy = vec2(10.3, 20.7);
+    let radius = 10i64;
+    let mut group = c.benchmark_group("brushes");
+    // How raise worked before regions, every tap of every dab going through the chunk map.
+    group.bench_function("raise/hashmap", |b| {
+        b.iter_batched_ref(
+            || copy(&base),
+            |terrain| {
+                for x in -radius..radius {
+                    for y in -radius..radius {
+                        let offset = vec2(x as f32, y as f32);
+                        let strength = (radius as f32 - offset.length()) / radius as f32;
+                        if strength > 0.0 {
+                            terrain.data.modify_within(
+                                xy + offset,
+                                strength,
+                                terrain.interpolation,
+                                terrain.edge_policy,
+                                None,
+                            );
+                        }
+                    }
+                }
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.bench_function("raise/region", |b| {
+        b.iter_batched_ref(
+            || copy(&base),
+            |terrain| raise::trigger(xy, radius, terrain, None),
+            BatchSize::SmallInput,
+        )
+    });
+    // Smoothing with the 3x3 neighbourhood read through the chunk map.
+    group.bench_function("smooth/hashmap", |b| {
+        b.iter_batched_ref(
+            || copy(&base),
+            |terrain| {
+                let r = radius as i32;
+                let center = (xy.x.floor() as i32, xy.y.floor() as i32);
+                let mut smoothed = Vec::new();
+                for y in center.1 - r..=center.1 + r {
+                    for x in center.0 - r..=center.0 + r {
+                        let mut sum = 0.0;
+                        for dy in -1..=1 {
+                            for dx in -1..=1 {
+                                sum += terrain.data[(x + dx, y + dy)];
+                            }
+                        }
+                        smoothed.push(((x, y), sum / 9.0));
+                    }
+                }
+                for (cell, value) in smoothed {
+                    terrain.data[cell] += (value - terrain.data[cell]) * 0.5;
+                }
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.bench_function("smooth/region", |b| {
+        b.iter_batched_ref(
+            || copy(&base),
+            |terrain| smooth::trigger(xy, radius, terrain, None),
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+// Droplets running downhill from a row of starting points, reading through get.
+fn droplet_reads(get: impl Fn((i32, i32)) -> Option<f32> + Copy) -> Vec2 {
+    let interpolation = Interpolation::Bilinear;
+    let mut end = Vec2::ZERO;
+    for start in 0..16 {
+        let mut xy = vec2(start as f32 * 4.0 + 0.5, 0.5);
+        for _ in 0..256 {
+            let next = match interpolation.gradient(xy, get) {
+                Some(gradient) => xy + (-gradient).normalize_or_zero(),
+                None => break,
+            };
+            if interpolation.sample(next, get).is_none() {
+                break;
+            }
+            xy = next;
+        }
+        end += xy;
+    }
+    end
+}
+
+fn erosion(c: &mut Criterion) {
+    let base = terrain();
+    let mut group = c.benchmark_group("erosion");
+    group.bench_function("hashmap", |b| {
+        b.iter(|| droplet_reads(|cell| Some(base.data[cell])))
+    });
+    group.bench_function("cursor", |b| {
+        b.iter(|| {
+            let sampler = Sampler::for_terrain(&base);
+            droplet_reads(|cell| sampler.get(cell))
+        })
+    });
+    // What erosion tiles do: copy the window once, then read the dense copy.
+    group.bench_function("region", |b| {
+        b.iter(|| {
+            let region: Region = base.data.region(Bounds {
+                min: (-128, -128),
+                max: (128, 128),
+            });
+            droplet_reads(|cell| region.get(cell))
+        })
+    });
+    group.bench_function("trigger", |b| {
+        b.iter_batched_ref(
+            || copy(&base),
+            |terrain| erode::trigger(vec2(30.5, 40.5), 25, 0, terrain, None),
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, meshing, brushes, erosion);
+criterion_main!(benches);
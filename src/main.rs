@@ -1,5 +1,11 @@
-mod petra;
+use ::petra::petra;
 
 fn main() {
-    petra::setup::setup(); // Welcome to Petra!
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.is_empty() {
+        petra::setup::setup(); // Welcome to Petra!
+    } else if let Err(error) = petra::cli::run(&args) {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    }
 }
@@ -1,8 +1,26 @@
+pub mod analysis;
+pub mod atlas;
+pub mod bake;
 pub mod camera;
+pub mod cli;
+pub mod coast;
+pub mod contours;
+pub mod cursor;
+pub mod export;
+pub mod generate;
+pub mod grid;
+pub mod hydrology;
+pub mod map;
 pub mod material;
 pub mod mesh;
 pub mod modify;
+pub mod remap;
+pub mod sampler;
 pub mod setup;
+pub mod simplify;
+pub mod stats;
 pub mod terrain;
 pub mod tools;
+pub mod transform;
 pub mod ui;
+pub mod vector;
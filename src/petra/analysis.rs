@@ -0,0 +1,219 @@
+use crate::petra::cursor::Region;
+use crate::petra::sampler::Sampler;
+use crate::petra::terrain::{Bounds, Terrain};
+use image::{GrayImage, ImageFormat::OpenExr, ImageResult, Luma, Rgba, Rgba32FImage};
+use rayon::prelude::*;
+use std::path::Path;
+
+// Per-cell measurements of the terrain's shape, from the 3x3 neighbourhood of each cell with the
+// terrain's edge policy. Neighbours past the edge of the world read as flat, like normals do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layer {
+    // Steepness in degrees, 0 to 90.
+    Slope,
+    // Compass direction the slope faces, in degrees clockwise from -z (north) with +x as east.
+    // Flat cells are -1.
+    Aspect,
+    // Curvature of the contour line, per world unit. Positive on ridges, negative in valleys.
+    PlanCurvature,
+    // Curvature along the slope, per world unit. Positive where the slope steepens going
+    // downhill (convex shoulders), negative where it levels out (concave footslopes).
+    ProfileCurvature,
+    // Largest height difference between the cell and its neighbours.
+    Roughness,
+}
+
+impl Layer {
+    pub const ALL: [Layer; 5] = [
+        Layer::Slope,
+        Layer::Aspect,
+        Layer::PlanCurvature,
+        Layer::ProfileCurvature,
+        Layer::Roughness,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Layer::Slope => "Slope",
+            Layer::Aspect => "Aspect",
+            Layer::PlanCurvature => "Plan curvature",
+            Layer::ProfileCurvature => "Profile curvature",
+            Layer::Roughness => "Roughness",
+        }
+    }
+
+    // Value of the layer at a cell, given the heights around it (row by row, from -x -z to +x +z)
+    // and the distance between cells.
+    fn measure(&self, heights: &[f32; 9], cell_size: f32) -> f32 {
+        let [z1, z2, z3, z4, z5, z6, z7, z8, z9] = *heights;
+        if *self == Layer::Roughness {
+            return heights
+                .iter()
+                .fold(0.0f32, |largest, height| largest.max((height - z5).abs()));
+        }
+        // First and second derivatives, after Zevenbergen and Thorne.
+        let h = cell_size;
+        let p = (z6 - z4) / (2.0 * h);
+        let q = (z8 - z2) / (2.0 * h);
+        let r = (z4 - 2.0 * z5 + z6) / (h * h);
+        let t = (z2 - 2.0 * z5 + z8) / (h * h);
+        let s = (z9 - z7 - z3 + z1) / (4.0 * h * h);
+        let gradient = p * p + q * q;
+        match self {
+            Layer::Slope => gradient.sqrt().atan().to_degrees(),
+            Layer::Aspect => {
+                if gradient < 1e-12 {
+                    return -1.0;
+                }
+                // Downhill is -(p, q), and north is -z.
+                (-p).atan2(q).to_degrees().rem_euclid(360.0)
+            }
+            Layer::PlanCurvature => {
+                if gradient < 1e-12 {
+                    return 0.0;
+                }
+                -(q * q * r - 2.0 * p * q * s + p * p * t) / gradient.powf(1.5)
+            }
+            Layer::ProfileCurvature => {
+                if gradient < 1e-12 {
+                    return 0.0;
+                }
+                -(p * p * r + 2.0 * p * q * s + q * q * t) / (gradient * (1.0 + gradient).powf(1.5))
+            }
+            Layer::Roughness => unreachable!(),
+        }
+    }
+}
+
+// The layer over a rectangle of cells.
+pub fn compute(terrain: &Terrain, layer: Layer, bounds: Bounds) -> Region {
+    let mut region = Region::new(bounds);
+    let world = terrain.data.bounds();
+    region
+        .data
+        .par_chunks_mut(bounds.width() as usize)
+        .enumerate()
+        .for_each(|(row, values)| {
+            let sampler = Sampler::with_bounds(
+                &terrain.data,
+                terrain.edge_policy,
+                terrain.interpolation,
+                world,
+            );
+            let y = bounds.min.1 + row as i32;
+            for (i, value) in values.iter_mut().enumerate() {
+                let x = bounds.min.0 + i as i32;
+                let center = sampler.get_or((x, y), 0.0);
+                let mut heights = [0.0; 9];
+                for (j, height) in heights.iter_mut().enumerate() {
+                    let neighbour = (x + j as i32 % 3 - 1, y + j as i32 / 3 - 1);
+                    *height = sampler.get_or(neighbour, center);
+                }
+                *value = layer.measure(&heights, terrain.cell_size);
+            }
+        });
+    region
+}
+
+// Where a layer is within a range of values, as weights from 0 to 1 that fade out over falloff
+// past either end of the range. Aspect ranges go clockwise from min to max, so 315 to 45 is
+// everything facing roughly north.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LayerMask {
+    pub layer: Layer,
+    pub min: f32,
+    pub max: f32,
+    pub falloff: f32,
+}
+
+impl LayerMask {
+    pub fn new(layer: Layer) -> Self {
+        let (min, max, falloff) = match layer {
+            Layer::Slope => (0.0, 30.0, 5.0),
+            Layer::Aspect => (315.0, 45.0, 15.0),
+            Layer::PlanCurvature | Layer::ProfileCurvature => (0.05, 10.0, 0.05),
+            Layer::Roughness => (0.0, 1.0, 0.5),
+        };
+        Self {
+            layer,
+            min,
+            max,
+            falloff,
+        }
+    }
+
+    // How far the value is outside of the range.
+    fn distance(&self, value: f32) -> f32 {
+        if self.layer == Layer::Aspect {
+            if value < 0.0 {
+                return f32::INFINITY;
+            }
+            let span = (self.max - self.min).rem_euclid(360.0);
+            let offset = (value - self.min).rem_euclid(360.0);
+            if offset <= span {
+                0.0
+            } else {
+                (offset - span).min(360.0 - offset)
+            }
+        } else {
+            (self.min - value).max(value - self.max).max(0.0)
+        }
+    }
+
+    pub fn weight(&self, value: f32) -> f32 {
+        let distance = self.distance(value);
+        if distance <= 0.0 {
+            1.0
+        } else if self.falloff > 0.0 {
+            (1.0 - distance / self.falloff).max(0.0)
+        } else {
+            0.0
+        }
+    }
+
+    pub fn weights(&self, terrain: &Terrain, bounds: Bounds) -> Region {
+        let mut weights = compute(terrain, self.layer, bounds);
+        weights
+            .data
+            .par_iter_mut()
+            .for_each(|value| *value = self.weight(*value));
+        weights
+    }
+}
+
+// Moves every cell of after back towards before, keeping only the weighted part of the change.
+// Cells outside of the weights keep the change.
+pub fn blend(before: &Region, after: &mut Region, weights: &Region) {
+    let bounds = after.bounds;
+    for y in bounds.min.1..bounds.max.1 {
+        for x in bounds.min.0..bounds.max.0 {
+            if let (Some(old), Some(weight)) = (before.get((x, y)), weights.get((x, y))) {
+                let new = after.get_mut((x, y)).unwrap();
+                *new = old + (*new - old) * weight;
+            }
+        }
+    }
+}
+
+// Float image of a layer, with the value in the colour channels. Row 0 is the smallest z.
+pub fn save_to_exr<P: AsRef<Path>>(region: &Region, path: P) -> ImageResult<()> {
+    let (width, height) = (region.bounds.width() as u32, region.bounds.height() as u32);
+    let img = Rgba32FImage::from_fn(width, height, |x, y| {
+        let value = region.data[(y * width + x) as usize];
+        Rgba([value, value, value, 1.0])
+    });
+    img.save_with_format(path, OpenExr)
+}
+
+// Greyscale image of weights from 0 to 1, for splat maps in other tools.
+pub fn save_weights_png<P: AsRef<Path>>(weights: &Region, path: P) -> ImageResult<()> {
+    let (width, height) = (
+        weights.bounds.width() as u32,
+        weights.bounds.height() as u32,
+    );
+    let img = GrayImage::from_fn(width, height, |x, y| {
+        let weight = weights.data[(y * width + x) as usize];
+        Luma([(weight.clamp(0.0, 1.0) * 255.0).round() as u8])
+    });
+    img.save(path)
+}
@@ -0,0 +1,355 @@
+use crate::petra::analysis::{self, Layer};
+use crate::petra::coast;
+use crate::petra::hydrology::{self, Flow, FlowMethod};
+use crate::petra::terrain::{Bounds, Terrain};
+use crate::petra::vector;
+use bevy::math::{vec2, Vec2};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+const SEA: &str = "#e4dcc3";
+const LAND: &str = "#f4ecd6";
+const INK: &str = "#3a2f25";
+const WATER_INK: &str = "#4f6d8a";
+
+// Hand-drawn style map. Distances are in cells, heights are fractions of the way from sea level
+// to the highest point.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AtlasSettings {
+    // Distance between the places glyphs can go.
+    pub glyph_spacing: u32,
+    // Higher than this is mountains, then hills down to hill_height, where the ground is at least
+    // min_slope degrees steep. Flat high ground is left as plains.
+    pub mountain_height: f32,
+    pub hill_height: f32,
+    pub min_slope: f32,
+    // Cells that have to drain through a river.
+    pub river_threshold: f32,
+    pub hatch_spacing: f32,
+    pub hatch_length: f32,
+}
+
+impl Default for AtlasSettings {
+    fn default() -> Self {
+        Self {
+            glyph_spacing: 8,
+            mountain_height: 0.45,
+            hill_height: 0.25,
+            min_slope: 10.0,
+            river_threshold: 500.0,
+            hatch_spacing: 1.5,
+            hatch_length: 3.0,
+        }
+    }
+}
+
+// Same scattering on every export, from 0 to 1.
+fn jitter(x: i32, y: i32, salt: u32) -> f32 {
+    let mut hash = (x as u32)
+        .wrapping_mul(0x9e37_79b1)
+        .wrapping_add((y as u32).wrapping_mul(0x85eb_ca77))
+        .wrapping_add(salt.wrapping_mul(0xc2b2_ae3d));
+    hash ^= hash >> 15;
+    hash = hash.wrapping_mul(0x2c1b_3c6d);
+    hash ^= hash >> 12;
+    (hash & 0xffff) as f32 / 65535.0
+}
+
+// Chaikin corner cutting, to take the 45° steps out of rivers.
+fn smooth(points: &[Vec2]) -> Vec<Vec2> {
+    if points.len() < 3 {
+        return points.to_vec();
+    }
+    let mut smoothed = vec![points[0]];
+    for pair in points.windows(2) {
+        smoothed.push(pair[0].lerp(pair[1], 0.25));
+        smoothed.push(pair[0].lerp(pair[1], 0.75));
+    }
+    smoothed.push(points[points.len() - 1]);
+    smoothed
+}
+
+// Round length for a scale bar about a quarter of the way across, in kilometres.
+fn scale_length(map_kilometres: f32) -> f32 {
+    let target = map_kilometres / 4.0;
+    let magnitude = 10f32.powf(target.log10().floor());
+    [5.0, 2.0, 1.0]
+        .into_iter()
+        .map(|step| step * magnitude)
+        .find(|length| *length <= target)
+        .unwrap_or(magnitude)
+}
+
+// Writes the map of the bounds, or of the whole terrain, with north at the top.
+pub fn write_atlas(
+    terrain: &Terrain,
+    settings: &AtlasSettings,
+    bounds: Option<Bounds>,
+    path: &str,
+) -> io::Result<()> {
+    let bounds = match bounds.or_else(|| terrain.data.bounds()) {
+        Some(bounds) => bounds,
+        None => return Ok(()),
+    };
+    let cell_size = terrain.cell_size;
+    // Edges of the outermost cells, in world units.
+    let min = (vec2(bounds.min.0 as f32, bounds.min.1 as f32) - 0.5) * cell_size;
+    let size = vec2(bounds.width() as f32, bounds.height() as f32) * cell_size;
+    let stroke = size.x.max(size.y) / 1000.0;
+
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(
+        writer,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"{} {} {} {}\" width=\"{}\" height=\"{}\">",
+        min.x, min.y, size.x, size.y, size.x, size.y
+    )?;
+    writeln!(
+        writer,
+        "<defs><clipPath id=\"map\"><rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\"/></clipPath></defs>",
+        min.x, min.y, size.x, size.y
+    )?;
+    writeln!(
+        writer,
+        "<g clip-path=\"url(#map)\" stroke-linecap=\"round\" stroke-linejoin=\"round\">"
+    )?;
+    writeln!(
+        writer,
+        "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\"/>",
+        min.x, min.y, size.x, size.y, SEA
+    )?;
+
+    // Short strokes out into the water all along the shores, then the land over them. Water is
+    // on the left of every ring.
+    let landmasses = coast::landmasses(terrain, Some(bounds));
+    let mut hatching = String::new();
+    for (i, ring) in landmasses
+        .iter()
+        .flat_map(|landmass| &landmass.rings)
+        .enumerate()
+    {
+        let spacing = settings.hatch_spacing * cell_size;
+        let mut travelled = 0.0;
+        for pair in ring.windows(2) {
+            let length = pair[0].distance(pair[1]);
+            if length == 0.0 {
+                continue;
+            }
+            let direction = (pair[1] - pair[0]) / length;
+            let water = vec2(direction.y, -direction.x);
+            let mut along = (spacing - travelled % spacing) % spacing;
+            while along < length {
+                let start = pair[0] + direction * along;
+                let stroke_length = settings.hatch_length
+                    * cell_size
+                    * (0.4 + 0.6 * jitter(i as i32, (travelled + along) as i32, 1));
+                let end = start + water * stroke_length;
+                hatching += &format!("M{} {} L{} {} ", start.x, start.y, end.x, end.y);
+                along += spacing;
+            }
+            travelled += length;
+        }
+    }
+    writeln!(
+        writer,
+        "<path d=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"{}\" opacity=\"0.6\"/>",
+        hatching,
+        INK,
+        stroke * 0.8
+    )?;
+    for landmass in &landmasses {
+        let rings: Vec<String> = landmass
+            .rings
+            .iter()
+            .map(|ring| {
+                format!(
+                    "{}Z",
+                    vector::svg_path(&vector::simplify_line(ring, cell_size * 0.2))
+                )
+            })
+            .collect();
+        writeln!(
+            writer,
+            "<path d=\"{}\" fill=\"{}\" fill-rule=\"evenodd\" stroke=\"{}\" stroke-width=\"{}\"/>",
+            rings.join(" "),
+            LAND,
+            INK,
+            stroke * 2.0
+        )?;
+    }
+
+    // Rivers over land, heavier as they grow, ending where they reach the sea.
+    let flow = Flow::for_terrain(terrain, FlowMethod::D8, Some(hydrology::FILL_EPSILON));
+    let accumulation = flow.as_ref().map(|flow| flow.accumulation());
+    if let (Some(flow), Some(accumulation)) = (&flow, &accumulation) {
+        for river in hydrology::rivers(terrain, flow, accumulation, settings.river_threshold) {
+            let points = smooth(&smooth(&vector::simplify_line(
+                &river.points,
+                cell_size * 0.5,
+            )));
+            if points.len() < 2 {
+                continue;
+            }
+            writeln!(
+                writer,
+                "<path d=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"{}\"/>",
+                vector::svg_path(&points),
+                WATER_INK,
+                stroke * (0.8 + 0.7 * river.order as f32)
+            )?;
+        }
+    }
+
+    // Mountains and hills on a jittered grid, drawn from the back (north) to the front, each
+    // filled so it hides the ones behind it. Rivers keep their valleys clear.
+    let heights = terrain.data.region(bounds);
+    let slopes = analysis::compute(terrain, Layer::Slope, bounds);
+    let highest = heights
+        .data
+        .iter()
+        .cloned()
+        .fold(terrain.sea_level, f32::max);
+    let spacing = settings.glyph_spacing.max(1) as i32;
+    let mut glyphs = Vec::new();
+    for y in (bounds.min.1..bounds.max.1).step_by(spacing as usize) {
+        for x in (bounds.min.0..bounds.max.0).step_by(spacing as usize) {
+            let offset = vec2(jitter(x, y, 2) - 0.5, jitter(x, y, 3) - 0.5) * spacing as f32 * 0.6;
+            let cell = (x + offset.x.round() as i32, y + offset.y.round() as i32);
+            let (height, slope) = match (heights.get(cell), slopes.get(cell)) {
+                (Some(height), Some(slope)) if height > terrain.sea_level => (height, slope),
+                _ => continue,
+            };
+            let on_river = matches!(
+                accumulation.as_ref().and_then(|accumulation| accumulation.get(cell)),
+                Some(cells) if cells >= settings.river_threshold
+            );
+            if on_river {
+                continue;
+            }
+            let relative = (height - terrain.sea_level) / (highest - terrain.sea_level).max(1e-6);
+            if slope < settings.min_slope || relative < settings.hill_height {
+                continue;
+            }
+            let mountain = relative >= settings.mountain_height;
+            let base = vec2(cell.0 as f32, cell.1 as f32) * cell_size;
+            // Mountains grow with the height.
+            let size = if mountain { 0.8 + 0.6 * relative } else { 0.7 };
+            let width = spacing as f32 * cell_size * size * (0.85 + 0.3 * jitter(x, y, 4));
+            glyphs.push((base, width, mountain));
+        }
+    }
+    glyphs.sort_by(|a, b| a.0.y.total_cmp(&b.0.y));
+    for (base, width, mountain) in glyphs {
+        let (left, right) = (base.x - width / 2.0, base.x + width / 2.0);
+        if mountain {
+            let peak = vec2(base.x - width * 0.08, base.y - width * 0.75);
+            // Shade lines down the east side, away from a sun in the north-west.
+            let mut shading = String::new();
+            for k in 1..4 {
+                let from = peak.lerp(vec2(right, base.y), k as f32 / 4.0);
+                shading += &format!(
+                    "M{} {} L{} {} ",
+                    from.x,
+                    from.y,
+                    from.x - width * 0.1,
+                    base.y
+                );
+            }
+            writeln!(
+                writer,
+                "<path d=\"M{} {} L{} {} L{} {}\" fill=\"{}\" stroke=\"{}\" stroke-width=\"{}\"/><path d=\"{}\" stroke=\"{}\" stroke-width=\"{}\"/>",
+                left, base.y, peak.x, peak.y, right, base.y, LAND, INK, stroke * 1.5,
+                shading, INK, stroke * 0.7
+            )?;
+        } else {
+            writeln!(
+                writer,
+                "<path d=\"M{} {} Q{} {} {} {}\" fill=\"{}\" stroke=\"{}\" stroke-width=\"{}\"/>",
+                left,
+                base.y,
+                base.x,
+                base.y - width * 0.5,
+                right,
+                base.y,
+                LAND,
+                INK,
+                stroke * 1.2
+            )?;
+        }
+    }
+    writeln!(writer, "</g>")?;
+
+    // Scale bar in the bottom left corner, in alternating blocks, from the terrain's world scale.
+    if terrain.worldscale > 0.0 {
+        let kilometres = scale_length(size.x / terrain.worldscale);
+        let length = kilometres * terrain.worldscale;
+        let bar = size.y / 80.0;
+        let origin = vec2(min.x + size.x / 20.0, min.y + size.y - size.y / 20.0);
+        for block in 0..4 {
+            writeln!(
+                writer,
+                "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\" stroke=\"{}\" stroke-width=\"{}\"/>",
+                origin.x + length * block as f32 / 4.0,
+                origin.y - bar,
+                length / 4.0,
+                bar,
+                if block % 2 == 0 { INK } else { LAND },
+                INK,
+                stroke
+            )?;
+        }
+        let label = if kilometres < 1.0 {
+            format!("{} m", kilometres * 1000.0)
+        } else {
+            format!("{} km", kilometres)
+        };
+        for (x, text) in [(origin.x, "0".to_string()), (origin.x + length, label)] {
+            writeln!(
+                writer,
+                "<text x=\"{}\" y=\"{}\" font-size=\"{}\" font-family=\"serif\" text-anchor=\"middle\" fill=\"{}\">{}</text>",
+                x,
+                origin.y - bar * 1.5,
+                bar * 1.6,
+                INK,
+                text
+            )?;
+        }
+    }
+    writeln!(
+        writer,
+        "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"{}\"/>",
+        min.x, min.y, size.x, size.y, INK, stroke * 4.0
+    )?;
+    writeln!(writer, "</svg>")?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scale_bar_is_round_and_fits() {
+        for map_kilometres in [0.013, 0.4, 1.0, 3.9, 4.0, 7.5, 19.99, 20.0, 64.0, 1234.0] {
+            let length = scale_length(map_kilometres);
+            let quarter = map_kilometres / 4.0;
+            assert!(
+                length <= quarter * 1.0001,
+                "{} for {}",
+                length,
+                map_kilometres
+            );
+            // Never less than the next round length down would give.
+            assert!(length >= quarter * 0.4, "{} for {}", length, map_kilometres);
+            let magnitude = 10f32.powf(length.log10().floor());
+            let leading = length / magnitude;
+            assert!(
+                [1.0, 2.0, 5.0]
+                    .iter()
+                    .any(|step| (leading - step).abs() < 1e-4),
+                "{} for {}",
+                length,
+                map_kilometres
+            );
+        }
+    }
+}
@@ -0,0 +1,333 @@
+use crate::petra::mesh::terrain_normal;
+use crate::petra::sampler::{Interpolation, Sampler};
+use crate::petra::terrain::{Bounds, Terrain};
+use bevy::math::{vec2, Vec3};
+use image::{ImageFormat::OpenExr, ImageResult, Rgb, RgbImage, Rgba, Rgba32FImage};
+use rayon::prelude::*;
+use std::f32::consts::TAU;
+
+// Texture bakes for game engines, one texel per cell with row 0 at the smallest z (north).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bake {
+    // Tangent-space normals with green pointing north, the same ones the chunk meshes use, packed
+    // from -1..1 into 0..1.
+    Normal,
+    // Horizon-based ambient occlusion, 1 where the sky is fully visible.
+    AmbientOcclusion,
+    // Convexity in red, concavity in green and both in blue, with flat ground at 0.5.
+    Curvature,
+}
+
+impl Bake {
+    pub const ALL: [Bake; 3] = [Bake::Normal, Bake::AmbientOcclusion, Bake::Curvature];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Bake::Normal => "Normal",
+            Bake::AmbientOcclusion => "Ambient occlusion",
+            Bake::Curvature => "Curvature",
+        }
+    }
+
+    // Used in file names.
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            Bake::Normal => "normal",
+            Bake::AmbientOcclusion => "ao",
+            Bake::Curvature => "curvature",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BakeFormat {
+    Png,
+    Exr,
+}
+
+impl BakeFormat {
+    pub const ALL: [BakeFormat; 2] = [BakeFormat::Png, BakeFormat::Exr];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BakeFormat::Png => "PNG",
+            BakeFormat::Exr => "EXR",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            BakeFormat::Png => "png",
+            BakeFormat::Exr => "exr",
+        }
+    }
+}
+
+// Ambient occlusion looks for horizons up to occlusion_radius world units away, in
+// occlusion_directions directions with occlusion_steps samples each. Curvature compares each cell
+// with the ground curvature_radius cells around it, and curvature_range per world unit maps to
+// full convexity or concavity.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BakeSettings {
+    pub occlusion_radius: f32,
+    pub occlusion_directions: u32,
+    pub occlusion_steps: u32,
+    pub curvature_radius: f32,
+    pub curvature_range: f32,
+}
+
+impl Default for BakeSettings {
+    fn default() -> Self {
+        Self {
+            occlusion_radius: 16.0,
+            occlusion_directions: 8,
+            occlusion_steps: 12,
+            curvature_radius: 2.0,
+            curvature_range: 0.1,
+        }
+    }
+}
+
+// Ambient occlusion at a cell with the given normal, after Bavoil and Sainz: along each direction
+// the horizon starts at the surface's own slope and rises as higher ground comes into view, and
+// every rise occludes the sine of the angle it adds, fading out towards the radius so the edge of
+// the search doesn't show.
+fn occlusion(
+    sampler: &Sampler,
+    cell: (i32, i32),
+    normal: Vec3,
+    cell_size: f32,
+    settings: &BakeSettings,
+) -> f32 {
+    let height = sampler.get_or(cell, 0.0);
+    let origin = vec2(cell.0 as f32, cell.1 as f32);
+    let directions = settings.occlusion_directions.max(1);
+    let steps = settings.occlusion_steps.max(1);
+    let sine = |tangent: f32| tangent / (1.0 + tangent * tangent).sqrt();
+    let mut occluded = 0.0;
+    for direction in 0..directions {
+        // Half a step off the axes, so the grid doesn't line up with the directions.
+        let angle = (direction as f32 + 0.5) / directions as f32 * TAU;
+        let towards = vec2(angle.cos(), angle.sin());
+        // Rise of the surface per world unit that way. The normal's y points towards -z.
+        let mut horizon = (-normal.x * towards.x + normal.y * towards.y) / normal.z;
+        let mut previous = sine(horizon);
+        for step in 1..=steps {
+            let distance = settings.occlusion_radius * step as f32 / steps as f32;
+            // Past the edge of the world reads as flat.
+            let ground = sampler
+                .sample(origin + towards * distance / cell_size)
+                .unwrap_or(height);
+            let tangent = (ground - height) / distance;
+            if tangent > horizon {
+                horizon = tangent;
+                let falloff = 1.0 - (distance / settings.occlusion_radius).powi(2);
+                occluded += falloff * (sine(tangent) - previous);
+                previous = sine(tangent);
+            }
+        }
+    }
+    (1.0 - occluded / directions as f32).clamp(0.0, 1.0)
+}
+
+// Mean curvature at a cell, per world unit, from how far it stands above or below the ring of
+// ground around it. Positive on ridges and peaks, negative in valleys and pits.
+fn curvature(sampler: &Sampler, cell: (i32, i32), cell_size: f32, radius: f32) -> f32 {
+    let height = sampler.get_or(cell, 0.0);
+    let origin = vec2(cell.0 as f32, cell.1 as f32);
+    let around = (0..8)
+        .map(|i| {
+            let angle = i as f32 / 8.0 * TAU;
+            sampler
+                .sample(origin + vec2(angle.cos(), angle.sin()) * radius)
+                .unwrap_or(height)
+        })
+        .sum::<f32>()
+        / 8.0;
+    let distance = radius * cell_size;
+    2.0 * (height - around) / (distance * distance)
+}
+
+// Bake of the bounds, with every channel in 0..1.
+pub fn bake(
+    terrain: &Terrain,
+    bake: Bake,
+    settings: &BakeSettings,
+    bounds: Bounds,
+) -> Rgba32FImage {
+    let (width, height) = (bounds.width() as u32, bounds.height() as u32);
+    let mut img = Rgba32FImage::new(width, height);
+    img.par_chunks_mut(width as usize * 4)
+        .enumerate()
+        .for_each(|(row, pixels)| {
+            let sampler = Sampler::for_terrain(terrain);
+            // Horizons and rings fall between cells, where bilinear is plenty and much cheaper.
+            let between = Sampler::new(&terrain.data, terrain.edge_policy, Interpolation::Bilinear);
+            let y = bounds.min.1 + row as i32;
+            for (column, pixel) in pixels.chunks_mut(4).enumerate() {
+                let cell = (bounds.min.0 + column as i32, y);
+                let colour = match bake {
+                    Bake::Normal => {
+                        let normal = terrain_normal(&sampler, cell, terrain.cell_size);
+                        (normal * 0.5 + 0.5).to_array()
+                    }
+                    Bake::AmbientOcclusion => {
+                        let normal = terrain_normal(&sampler, cell, terrain.cell_size);
+                        [occlusion(&between, cell, normal, terrain.cell_size, settings); 3]
+                    }
+                    Bake::Curvature => {
+                        let curvature = curvature(
+                            &between,
+                            cell,
+                            terrain.cell_size,
+                            settings.curvature_radius.max(1.0),
+                        );
+                        let t = (curvature / settings.curvature_range.max(1e-6)).clamp(-1.0, 1.0);
+                        [t.max(0.0), t.min(0.0).abs(), 0.5 + 0.5 * t]
+                    }
+                };
+                pixel[..3].copy_from_slice(&colour);
+                pixel[3] = 1.0;
+            }
+        });
+    img
+}
+
+pub fn save(img: &Rgba32FImage, format: BakeFormat, path: &str) -> ImageResult<()> {
+    match format {
+        BakeFormat::Exr => img.save_with_format(path, OpenExr),
+        BakeFormat::Png => {
+            let img = RgbImage::from_fn(img.width(), img.height(), |x, y| {
+                let Rgba(colour) = img.get_pixel(x, y);
+                Rgb([0, 1, 2].map(|c| (colour[c].clamp(0.0, 1.0) * 255.0).round() as u8))
+            });
+            img.save(path)
+        }
+    }
+}
+
+// Bakes the bounds into one image at the path. With tiles, every chunk the bounds touch goes to
+// its own file instead, named like mesh tiles, e.g. terrain_normal_0_-1.png. Tiles are one texel
+// wider and taller than a chunk, so they cover the vertices of its mesh and share their edges with
+// their neighbours.
+pub fn export_bake(
+    terrain: &Terrain,
+    bake: Bake,
+    settings: &BakeSettings,
+    bounds: Bounds,
+    tiles: bool,
+    format: BakeFormat,
+    path: &str,
+) -> ImageResult<()> {
+    if !tiles {
+        return save(&self::bake(terrain, bake, settings, bounds), format, path);
+    }
+    let stem = path
+        .strip_suffix(&format!(".{}", format.extension()))
+        .unwrap_or(path);
+    for (x, z) in bounds.chunk_coordinates(terrain.data.chunk_size) {
+        let chunk = Bounds::of_chunk((x, z), terrain.data.chunk_size);
+        let tile = Bounds {
+            min: chunk.min,
+            max: (chunk.max.0 + 1, chunk.max.1 + 1),
+        };
+        save(
+            &self::bake(terrain, bake, settings, tile),
+            format,
+            &format!("{}_{}_{}.{}", stem, x, z, format.extension()),
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::petra::terrain::TerrainData;
+
+    const BOUNDS: Bounds = Bounds {
+        min: (0, 0),
+        max: (32, 32),
+    };
+
+    fn hills() -> Terrain {
+        let mut terrain = Terrain {
+            data: TerrainData::zeros(16),
+            ..Default::default()
+        };
+        for y in 0..32 {
+            for x in 0..32 {
+                terrain.data[(x, y)] = (x as f32 * 0.4).sin() * 3.0 + (y as f32 * 0.3).cos() * 4.0;
+            }
+        }
+        terrain
+    }
+
+    #[test]
+    fn tiles_share_their_edges() {
+        let terrain = hills();
+        let directory = std::env::temp_dir().join(format!("petra_bake_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let stem = directory.join("terrain");
+        let stem = stem.to_str().unwrap();
+        for bake in Bake::ALL {
+            let path = format!("{}_{}.exr", stem, bake.suffix());
+            let settings = BakeSettings::default();
+            export_bake(
+                &terrain,
+                bake,
+                &settings,
+                BOUNDS,
+                true,
+                BakeFormat::Exr,
+                &path,
+            )
+            .unwrap();
+            let tile = |x: i32, z: i32| {
+                let path = format!("{}_{}_{}_{}.exr", stem, bake.suffix(), x, z);
+                image::open(path).unwrap().into_rgba32f()
+            };
+            let (corner, east, south) = (tile(0, 0), tile(1, 0), tile(0, 1));
+            assert_eq!(corner.dimensions(), (17, 17));
+            for i in 0..17 {
+                assert_eq!(corner.get_pixel(16, i), east.get_pixel(0, i), "{:?}", bake);
+                assert_eq!(corner.get_pixel(i, 16), south.get_pixel(i, 0), "{:?}", bake);
+            }
+        }
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn flat_ground_bakes_to_neutral_values() {
+        let mut terrain = hills();
+        for y in 0..32 {
+            for x in 0..32 {
+                terrain.data[(x, y)] = 3.0;
+            }
+        }
+        let settings = BakeSettings::default();
+        let inside = Bounds {
+            min: (4, 4),
+            max: (28, 28),
+        };
+        let expected = [
+            (Bake::Normal, [0.5, 0.5, 1.0]),
+            (Bake::AmbientOcclusion, [1.0; 3]),
+            (Bake::Curvature, [0.0, 0.0, 0.5]),
+        ];
+        for (bake, colour) in expected {
+            let img = self::bake(&terrain, bake, &settings, inside);
+            for Rgba(pixel) in img.pixels() {
+                for c in 0..3 {
+                    assert!(
+                        (pixel[c] - colour[c]).abs() < 1e-5,
+                        "{:?}: {:?}",
+                        bake,
+                        pixel
+                    );
+                }
+                assert_eq!(pixel[3], 1.0);
+            }
+        }
+    }
+}
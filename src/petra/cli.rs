@@ -0,0 +1,224 @@
+use crate::petra::bake::{self, Bake, BakeFormat, BakeSettings};
+use crate::petra::contours::ContourSettings;
+use crate::petra::generate;
+use crate::petra::map::{self, MapStyle};
+use crate::petra::stats::StatsCache;
+use crate::petra::terrain::{Bounds, Terrain, TerrainData, CHUNK_SIZES};
+use std::collections::HashMap;
+use std::str::FromStr;
+
+// Headless commands, for scripts and batch jobs. Running petra without arguments opens the editor.
+const USAGE: &str = "usage: petra <command> [--option value]...
+
+commands:
+  stats    print height, slope, area and volume statistics as JSON
+           --bin-width <height>  height histogram bin width (default 1)
+           --selection <min x>,<min z>,<max x>,<max z>  only count these cells
+  render   draw a shaded top-down map as a PNG
+           --output <png>             (default map.png)
+           --azimuth <degrees>        sun direction clockwise from north (default 315)
+           --altitude <degrees>       sun height above the horizon (default 45)
+           --multidirectional <bool>  light from four directions (default false)
+           --exaggeration <factor>    vertical exaggeration of the shading (default 1)
+           --shading <0 to 1>         strength of the shading (default 0.8)
+           --hypsometric <bool>       colour by height, otherwise grey (default true)
+           --sea-tint <bool>          colour the sea by depth (default true)
+           --contours <height>        contour interval, 0 for none (default 0)
+           --index-every <n>          heavier contour every n lines (default 5)
+           --scale <pixels>           pixels per cell (default 1)
+           --selection <min x>,<min z>,<max x>,<max z>  only draw these cells
+  bake     bake a texture for game engines as a PNG or EXR, picked by the extension
+           --map <name>                normal, ao or curvature (default normal)
+           --output <png or exr>       (default terrain_<map>.png)
+           --tiles <bool>              one image per chunk, named <output>_<x>_<z> (default false)
+           --radius <units>            ambient occlusion search distance (default 16)
+           --directions <n>            ambient occlusion directions (default 8)
+           --steps <n>                 ambient occlusion samples per direction (default 12)
+           --curvature-radius <cells>  distance curvature is measured over (default 2)
+           --curvature-range <value>   curvature per unit that maps to full (default 0.1)
+           --selection <min x>,<min z>,<max x>,<max z>  only bake these cells
+
+terrain options, for every command:
+  --input <image>      heightmap to load, e.g. one saved from the editor
+  --seed <n>           otherwise generate the same terrain as the editor (default 0)
+  --chunk-size <n>     16, 32, 64, 128 or 256 (default 64)
+  --cell-size <units>  world units between cells (default 1)
+  --sea-level <height> (default 0)";
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let (command, options) = args.split_first().ok_or(USAGE)?;
+    let options = Options::parse(options)?;
+    match command.as_str() {
+        "stats" => stats(&options),
+        "render" => render(&options),
+        "bake" => bake(&options),
+        "help" | "--help" | "-h" => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        _ => Err(format!("unknown command {}\n\n{}", command, USAGE)),
+    }
+}
+
+fn stats(options: &Options) -> Result<(), String> {
+    let terrain = load_terrain(options)?;
+    let bin_width: f32 = options.get("bin-width", 1.0)?;
+    if bin_width <= 0.0 {
+        return Err("--bin-width has to be positive".to_string());
+    }
+    let selection = options.bounds("selection")?;
+    let stats = StatsCache::default().stats(&terrain, bin_width, selection);
+    println!("{}", stats.to_json());
+    Ok(())
+}
+
+fn render(options: &Options) -> Result<(), String> {
+    let terrain = load_terrain(options)?;
+    let defaults = MapStyle::default();
+    let interval: f32 = options.get("contours", 0.0)?;
+    let style = MapStyle {
+        azimuth: options.get("azimuth", defaults.azimuth)?,
+        altitude: options.get("altitude", defaults.altitude)?,
+        multidirectional: options.get("multidirectional", defaults.multidirectional)?,
+        exaggeration: options.get("exaggeration", defaults.exaggeration)?,
+        shading: options.get("shading", defaults.shading)?,
+        hypsometric: options.get("hypsometric", defaults.hypsometric)?,
+        sea_tint: options.get("sea-tint", defaults.sea_tint)?,
+        contours: Some(ContourSettings {
+            interval,
+            index_every: options.get("index-every", 5)?,
+            ..Default::default()
+        })
+        .filter(|_| interval > 0.0),
+        scale: options.get("scale", defaults.scale)?,
+    };
+    if style.scale == 0 || style.scale > 16 {
+        return Err("--scale has to be from 1 to 16".to_string());
+    }
+    let output = options.0.get("output").map_or("map.png", String::as_str);
+    let img = map::render(&terrain, &style, options.bounds("selection")?)
+        .ok_or("the terrain is empty")?;
+    img.save(output)
+        .map_err(|error| format!("couldn't save {}: {}", output, error))
+}
+
+fn bake(options: &Options) -> Result<(), String> {
+    let terrain = load_terrain(options)?;
+    let name = options.0.get("map").map_or("normal", String::as_str);
+    let map = Bake::ALL
+        .into_iter()
+        .find(|bake| bake.suffix() == name)
+        .ok_or("--map has to be normal, ao or curvature")?;
+    let defaults = BakeSettings::default();
+    let settings = BakeSettings {
+        occlusion_radius: options.get("radius", defaults.occlusion_radius)?,
+        occlusion_directions: options.get("directions", defaults.occlusion_directions)?,
+        occlusion_steps: options.get("steps", defaults.occlusion_steps)?,
+        curvature_radius: options.get("curvature-radius", defaults.curvature_radius)?,
+        curvature_range: options.get("curvature-range", defaults.curvature_range)?,
+    };
+    if settings.occlusion_radius <= 0.0 || settings.curvature_range <= 0.0 {
+        return Err("--radius and --curvature-range have to be positive".to_string());
+    }
+    let output = options
+        .0
+        .get("output")
+        .cloned()
+        .unwrap_or_else(|| format!("terrain_{}.png", map.suffix()));
+    let format = if output.ends_with(".exr") {
+        BakeFormat::Exr
+    } else {
+        BakeFormat::Png
+    };
+    let bounds = options
+        .bounds("selection")?
+        .or_else(|| terrain.data.bounds())
+        .ok_or("the terrain is empty")?;
+    bake::export_bake(
+        &terrain,
+        map,
+        &settings,
+        bounds,
+        options.get("tiles", false)?,
+        format,
+        &output,
+    )
+    .map_err(|error| format!("couldn't save {}: {}", output, error))
+}
+
+fn load_terrain(options: &Options) -> Result<Terrain, String> {
+    let chunk_size: usize = options.get("chunk-size", 64)?;
+    if !CHUNK_SIZES.contains(&chunk_size) {
+        return Err(format!("--chunk-size has to be one of {:?}", CHUNK_SIZES));
+    }
+    let mut terrain = Terrain {
+        data: TerrainData::zeros(chunk_size),
+        cell_size: options.get("cell-size", 1.0)?,
+        sea_level: options.get("sea-level", 0.0)?,
+        seed: options.get("seed", 0)?,
+        ..Default::default()
+    };
+    match options.0.get("input") {
+        Some(path) => {
+            terrain.data = TerrainData::load_from_image(path, chunk_size)
+                .map_err(|error| format!("couldn't load {}: {}", path, error))?;
+        }
+        None => {
+            // Same as Terrain > Generate in the editor.
+            let seed = terrain.seed as u32;
+            generate::fbm(&mut terrain, (-4, -4), (3, 3), seed);
+        }
+    }
+    Ok(terrain)
+}
+
+// --name value pairs.
+struct Options(HashMap<String, String>);
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Options, String> {
+        let mut options = HashMap::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let name = arg
+                .strip_prefix("--")
+                .ok_or_else(|| format!("expected an --option, got {}", arg))?;
+            let value = args
+                .next()
+                .ok_or_else(|| format!("--{} needs a value", name))?;
+            options.insert(name.to_string(), value.clone());
+        }
+        Ok(Options(options))
+    }
+
+    fn get<T: FromStr>(&self, name: &str, default: T) -> Result<T, String> {
+        match self.0.get(name) {
+            Some(value) => value
+                .parse()
+                .map_err(|_| format!("couldn't read --{} {}", name, value)),
+            None => Ok(default),
+        }
+    }
+
+    fn bounds(&self, name: &str) -> Result<Option<Bounds>, String> {
+        let value = match self.0.get(name) {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        let numbers = value
+            .split(',')
+            .map(|number| number.trim().parse::<i32>())
+            .collect::<Result<Vec<i32>, _>>()
+            .ok()
+            .filter(|numbers| numbers.len() == 4)
+            .ok_or_else(|| format!("--{} takes min x,min z,max x,max z", name))?;
+        let bounds = Bounds {
+            min: (numbers[0], numbers[1]),
+            max: (numbers[2], numbers[3]),
+        };
+        if bounds.width() <= 0 || bounds.height() <= 0 {
+            return Err(format!("--{} is empty", name));
+        }
+        Ok(Some(bounds))
+    }
+}
@@ -0,0 +1,84 @@
+use crate::petra::terrain::{Bounds, Terrain};
+use crate::petra::vector::{self, Feature, Geometry};
+use bevy::math::Vec2;
+
+// Land above the terrain's sea level, outlined with marching squares.
+pub struct Landmass {
+    // Coastline first, then the shores of the lakes inside it, closed and in world units.
+    pub rings: Vec<Vec<Vec2>>,
+    // In square world units, without the lakes.
+    pub area: f32,
+    pub coastline: f32,
+    pub lake_area: f32,
+}
+
+fn length(ring: &[Vec2]) -> f32 {
+    ring.windows(2).map(|pair| pair[0].distance(pair[1])).sum()
+}
+
+// Landmasses in the bounds, or in the whole terrain, from largest to smallest. Islands in lakes
+// are landmasses of their own.
+pub fn landmasses(terrain: &Terrain, bounds: Option<Bounds>) -> Vec<Landmass> {
+    let bounds = match bounds.or_else(|| terrain.data.bounds()) {
+        Some(bounds) => bounds,
+        None => return Vec::new(),
+    };
+    let heights = terrain.data.region(bounds);
+    let rings = vector::marching_squares(&heights, terrain.sea_level, terrain.cell_size);
+    let mut landmasses: Vec<Landmass> = vector::polygons(rings)
+        .into_iter()
+        .map(|rings| {
+            let lake_area = -rings[1..]
+                .iter()
+                .map(|ring| vector::signed_area(ring) / 2.0)
+                .sum::<f32>();
+            Landmass {
+                area: vector::signed_area(&rings[0]) / 2.0 - lake_area,
+                coastline: length(&rings[0]),
+                lake_area,
+                rings,
+            }
+        })
+        .collect();
+    landmasses.sort_by(|a, b| b.area.total_cmp(&a.area));
+    landmasses
+}
+
+// A polygon for every landmass numbered from 1, and one for every lake with the number of the
+// landmass around it.
+pub fn features(landmasses: &[Landmass]) -> Vec<Feature> {
+    let mut features = Vec::new();
+    for (i, landmass) in landmasses.iter().enumerate() {
+        let number = (i + 1) as f64;
+        features.push(
+            Feature::new(Geometry::Polygon(landmass.rings.clone()))
+                .with("landmass", number)
+                .with("area", landmass.area as f64)
+                .with("coastline", landmass.coastline as f64)
+                .with("lakes", (landmass.rings.len() - 1) as f64)
+                .with("lake_area", landmass.lake_area as f64),
+        );
+        for shore in &landmass.rings[1..] {
+            // Turned around, so the lake is an outline of its own.
+            let mut ring = shore.clone();
+            ring.reverse();
+            features.push(
+                Feature::new(Geometry::Polygon(vec![ring]))
+                    .with("lake", number)
+                    .with("area", (-vector::signed_area(shore) / 2.0) as f64)
+                    .with("shoreline", length(shore) as f64),
+            );
+        }
+    }
+    features
+}
+
+// Sandy land and blue lakes, with dark outlines.
+pub fn svg_style(feature: &Feature) -> String {
+    let fill = if feature.properties[0].0 == "lake" {
+        "#9cc3e6"
+    } else {
+        "#e8dcb5"
+    };
+    format!("fill=\"{}\" stroke=\"#3b4a5a\" stroke-width=\"1\"", fill)
+}
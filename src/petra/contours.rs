@@ -0,0 +1,159 @@
+use crate::petra::terrain::{Bounds, Terrain};
+use crate::petra::vector::{self, Feature, Geometry};
+use bevy::math::Vec2;
+use rayon::prelude::*;
+
+// Contours every interval in height, with every index_every-th one drawn heavier and labelled.
+// Lines are simplified until they're within tolerance world units of the exact contour.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ContourSettings {
+    pub interval: f32,
+    pub index_every: u32,
+    pub tolerance: f32,
+}
+
+impl Default for ContourSettings {
+    fn default() -> Self {
+        Self {
+            interval: 5.0,
+            index_every: 5,
+            tolerance: 0.25,
+        }
+    }
+}
+
+pub struct Contour {
+    pub elevation: f32,
+    pub index: bool,
+    pub points: Vec<Vec2>,
+}
+
+// Contours over the bounds, or over the whole terrain. The heights are read across chunks in one
+// piece, so lines carry on over chunk borders.
+pub fn contours(
+    terrain: &Terrain,
+    settings: &ContourSettings,
+    bounds: Option<Bounds>,
+) -> Vec<Contour> {
+    let bounds = match bounds.or_else(|| terrain.data.bounds()) {
+        Some(bounds) => bounds,
+        None => return Vec::new(),
+    };
+    if settings.interval <= 0.0 {
+        return Vec::new();
+    }
+    let heights = terrain.data.region(bounds);
+    let (min, max) = heights
+        .data
+        .iter()
+        .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), height| {
+            (min.min(*height), max.max(*height))
+        });
+    let (first, last) = (
+        (min / settings.interval).ceil() as i64,
+        (max / settings.interval).floor() as i64,
+    );
+    (first..=last)
+        .into_par_iter()
+        .flat_map_iter(|step| {
+            let elevation = step as f32 * settings.interval;
+            let index = settings.index_every > 0 && step % settings.index_every as i64 == 0;
+            vector::contour_lines(&heights, elevation, terrain.cell_size)
+                .into_iter()
+                .map(move |line| Contour {
+                    elevation,
+                    index,
+                    points: vector::simplify_line(&line, settings.tolerance),
+                })
+        })
+        .filter(|contour| contour.points.len() > 1)
+        .collect()
+}
+
+// Lines with their elevation and whether they're index contours, labelled with the elevation.
+pub fn features(contours: &[Contour]) -> Vec<Feature> {
+    contours
+        .iter()
+        .map(|contour| {
+            let feature = Feature::new(Geometry::LineString(contour.points.clone()))
+                .with("elevation", contour.elevation as f64)
+                .with("index", contour.index as u8 as f64);
+            if contour.index {
+                feature.labelled(format!("{}", contour.elevation))
+            } else {
+                feature
+            }
+        })
+        .collect()
+}
+
+// Brown lines, heavier for index contours.
+pub fn svg_style(feature: &Feature) -> String {
+    let width = if feature.label.is_some() { 1.5 } else { 0.6 };
+    format!(
+        concat!(
+            "fill=\"none\" stroke=\"#8b5a2b\" stroke-width=\"{}\" stroke-linejoin=\"round\" ",
+            "vector-effect=\"non-scaling-stroke\""
+        ),
+        width
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::petra::terrain::TerrainData;
+
+    #[test]
+    fn lines_carry_on_over_chunk_borders() {
+        // Four chunks with a ramp rising diagonally across both borders, from 0.1 to 15.6.
+        let mut terrain = Terrain {
+            data: TerrainData::zeros(16),
+            cell_size: 2.0,
+            ..Default::default()
+        };
+        for y in 0..32 {
+            for x in 0..32 {
+                terrain.data[(x, y)] = (x + y) as f32 * 0.25 + 0.1;
+            }
+        }
+        let settings = ContourSettings {
+            interval: 1.0,
+            index_every: 5,
+            tolerance: 0.1,
+        };
+        let contours = contours(&terrain, &settings, None);
+        assert_eq!(contours.len(), 15);
+        let height = |point: Vec2| (point.x + point.y) / 2.0 * 0.25 + 0.1;
+        for (level, contour) in (1..=15).zip(&contours) {
+            assert_eq!(contour.elevation, level as f32);
+            assert_eq!(contour.index, level % 5 == 0);
+            // One line from one edge of the terrain to another, on the level all the way.
+            let (start, end) = (contour.points[0], *contour.points.last().unwrap());
+            let on_edge = |point: Vec2| {
+                [point.x, point.y]
+                    .iter()
+                    .any(|v| v.abs() < 1e-3 || (v - 62.0).abs() < 1e-3)
+            };
+            assert!(on_edge(start) && on_edge(end));
+            if level == 8 {
+                // Crosses both chunk borders.
+                assert!(start.x.min(end.x) < 32.0 && start.x.max(end.x) > 32.0);
+                assert!(start.y.min(end.y) < 32.0 && start.y.max(end.y) > 32.0);
+            }
+            for point in &contour.points {
+                assert!((height(*point) - contour.elevation).abs() < 1e-3);
+            }
+        }
+        let features = features(&contours);
+        for (feature, contour) in features.iter().zip(&contours) {
+            assert!(feature
+                .properties
+                .contains(&("elevation", contour.elevation as f64)));
+            assert!(feature
+                .properties
+                .contains(&("index", contour.index as u8 as f64)));
+            assert_eq!(feature.label.is_some(), contour.index);
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use crate::petra::sampler::Interpolation;
+use crate::petra::terrain::{Bounds, TerrainData, TerrainDataChunk};
+use bevy::math::Vec2;
+use std::cell::Cell;
+
+// None means the slot hasn't been looked up yet, Some(None) means there's no chunk there.
+type Slot<'a> = Option<Option<&'a TerrainDataChunk>>;
+
+// Read-only cursor that remembers the chunk it last read from and its eight neighbours.
+// Reads that stay close to each other (kernels, normals, droplets, row-by-row loops) only hash a
+// chunk coordinate when they wander more than a chunk away from where the cursor was centered.
+pub struct ChunkCursor<'a> {
+    data: &'a TerrainData,
+    center: Cell<Option<(i32, i32)>>,
+    slots: [Cell<Slot<'a>>; 9],
+}
+
+impl<'a> ChunkCursor<'a> {
+    pub fn new(data: &'a TerrainData) -> Self {
+        Self {
+            data,
+            center: Cell::new(None),
+            slots: Default::default(),
+        }
+    }
+
+    pub fn chunk(&self, chunk_coordinates: (i32, i32)) -> Option<&'a TerrainDataChunk> {
+        let center = match self.center.get() {
+            Some(center)
+                if (chunk_coordinates.0 - center.0).abs() <= 1
+                    && (chunk_coordinates.1 - center.1).abs() <= 1 =>
+            {
+                center
+            }
+            _ => {
+                self.center.set(Some(chunk_coordinates));
+                self.slots.iter().for_each(|slot| slot.set(None));
+                chunk_coordinates
+            }
+        };
+        let index = ((chunk_coordinates.1 - center.1 + 1) * 3
+            + (chunk_coordinates.0 - center.0 + 1)) as usize;
+        if let Some(chunk) = self.slots[index].get() {
+            return chunk;
+        }
+        let chunk = self.data.chunks.get(&chunk_coordinates);
+        self.slots[index].set(Some(chunk));
+        chunk
+    }
+
+    // Same as indexing the TerrainData, so missing chunks read as 0.
+    pub fn get(&self, coordinates: (i32, i32)) -> f32 {
+        let size = self.data.chunk_size as i32;
+        match self.chunk(self.data.get_terrain_chunk_coordinates(coordinates)) {
+            Some(chunk) => {
+                chunk.data[(coordinates.1.rem_euclid(size) * size + coordinates.0.rem_euclid(size))
+                    as usize]
+            }
+            None => 0.0,
+        }
+    }
+}
+
+// Dense copy of a rectangle of cells, for loops that want to work on plain slices instead of
+// going through chunks. Read one with TerrainData::region and write it back with apply_region.
+#[derive(Debug, Clone)]
+pub struct Region {
+    pub bounds: Bounds,
+    pub data: Vec<f32>,
+}
+
+impl Region {
+    pub fn new(bounds: Bounds) -> Self {
+        Self {
+            bounds,
+            data: vec![0.0; (bounds.width() * bounds.height()) as usize],
+        }
+    }
+
+    fn index(&self, cell: (i32, i32)) -> Option<usize> {
+        if !self.bounds.contains(cell) {
+            return None;
+        }
+        Some(
+            ((cell.1 - self.bounds.min.1) * self.bounds.width() + (cell.0 - self.bounds.min.0))
+                as usize,
+        )
+    }
+
+    pub fn get(&self, cell: (i32, i32)) -> Option<f32> {
+        Some(self.data[self.index(cell)?])
+    }
+
+    pub fn get_mut(&mut self, cell: (i32, i32)) -> Option<&mut f32> {
+        let index = self.index(cell)?;
+        Some(&mut self.data[index])
+    }
+
+    // Row y of the region, in world cell coordinates.
+    pub fn row(&self, y: i32) -> &[f32] {
+        let start = ((y - self.bounds.min.1) * self.bounds.width()) as usize;
+        &self.data[start..start + self.bounds.width() as usize]
+    }
+
+    pub fn row_mut(&mut self, y: i32) -> &mut [f32] {
+        let start = ((y - self.bounds.min.1) * self.bounds.width()) as usize;
+        let width = self.bounds.width() as usize;
+        &mut self.data[start..start + width]
+    }
+
+    // Same as TerrainData::modify, except that whatever falls outside of the region is dropped.
+    pub fn modify(&mut self, xy: Vec2, change: f32, interpolation: Interpolation) {
+        for (cell, weight) in interpolation.taps(xy) {
+            if let Some(value) = self.get_mut(cell) {
+                *value += weight * change;
+            }
+        }
+    }
+}
@@ -0,0 +1,599 @@
+use crate::petra::mesh::{
+    generate_mesh, generate_seamless_mesh, grid_indices, terrain_normal, ATTRIBUTE_REAL_POSITION,
+};
+use crate::petra::sampler::Sampler;
+use crate::petra::terrain::{Bounds, Terrain};
+use bevy::render::mesh::{Indices, Mesh, VertexAttributeValues};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MeshFormat {
+    Obj,
+    Gltf, // Binary glTF (.glb).
+    Stl,  // Binary STL.
+}
+
+impl MeshFormat {
+    pub const ALL: [MeshFormat; 3] = [MeshFormat::Obj, MeshFormat::Gltf, MeshFormat::Stl];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MeshFormat::Obj => "Wavefront OBJ",
+            MeshFormat::Gltf => "glTF",
+            MeshFormat::Stl => "STL",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            MeshFormat::Obj => "obj",
+            MeshFormat::Gltf => "glb",
+            MeshFormat::Stl => "stl",
+        }
+    }
+
+    pub fn write(&self, mesh: &ExportMesh, path: &str) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        match self {
+            MeshFormat::Obj => mesh.write_obj(&mut writer)?,
+            MeshFormat::Gltf => mesh.write_glb(&mut writer)?,
+            MeshFormat::Stl => mesh.write_stl(&mut writer)?,
+        }
+        writer.flush()
+    }
+}
+
+// Triangle mesh in world space, y up, ready to be written out.
+#[derive(Debug, Clone, Default)]
+pub struct ExportMesh {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub uvs: Vec<[f32; 2]>,
+    pub indices: Vec<u32>,
+}
+
+impl ExportMesh {
+    // Mesh of the part of one chunk within the bounds, built by the same code as the chunks on
+    // screen and reaching the first cell past the bounds like them. Simplified and cut chunks keep
+    // every cell along their border instead of hanging a skirt, so tiles meet edge to edge. UVs
+    // run from 0 to 1 over the world from uv_min to uv_max, so tiles of one export line up in
+    // texture space.
+    pub fn from_chunk(
+        terrain: &Terrain,
+        chunk_coordinates: (i32, i32),
+        bounds: Bounds,
+        max_error: Option<f32>,
+        uv_min: (f32, f32),
+        uv_max: (f32, f32),
+    ) -> Self {
+        let chunk = Bounds::of_chunk(chunk_coordinates, terrain.data.chunk_size);
+        let mesh = match max_error {
+            None if bounds.intersection(&chunk) == Some(chunk) => {
+                generate_mesh(terrain, chunk_coordinates)
+            }
+            // Without an error, simplifying only merges triangles that are exactly flat.
+            _ => {
+                generate_seamless_mesh(terrain, chunk_coordinates, max_error.unwrap_or(0.0), bounds)
+            }
+        };
+        let uv_size = (
+            (uv_max.0 - uv_min.0).max(f32::EPSILON),
+            (uv_max.1 - uv_min.1).max(f32::EPSILON),
+        );
+
+        let real_positions = match mesh.attribute(ATTRIBUTE_REAL_POSITION) {
+            Some(VertexAttributeValues::Float32x2(real_positions)) => real_positions,
+            _ => unreachable!(),
+        };
+        // Real positions come straight from the cell, so neighbouring chunks agree on their
+        // shared border to the bit, which weld relies on.
+        let positions = match mesh.attribute(Mesh::ATTRIBUTE_POSITION) {
+            Some(VertexAttributeValues::Float32x3(positions)) => positions
+                .iter()
+                .zip(real_positions)
+                .map(|([_, y, _], [x, z])| [*x, *y, *z])
+                .collect(),
+            _ => unreachable!(),
+        };
+        // Mesh normals have z up and y towards -z, see mesh::terrain_normal.
+        let normals = match mesh.attribute(Mesh::ATTRIBUTE_NORMAL) {
+            Some(VertexAttributeValues::Float32x3(normals)) => {
+                normals.iter().map(|[x, y, z]| [*x, *z, -y]).collect()
+            }
+            _ => unreachable!(),
+        };
+        let uvs = real_positions
+            .iter()
+            .map(|[x, z]| [(x - uv_min.0) / uv_size.0, (z - uv_min.1) / uv_size.1])
+            .collect();
+        let indices = match mesh.indices() {
+            Some(Indices::U32(indices)) => indices.clone(),
+            _ => unreachable!(),
+        };
+        Self {
+            positions,
+            normals,
+            uvs,
+            indices,
+        }
+    }
+
+    pub fn append(&mut self, other: &ExportMesh) {
+        let start = self.positions.len() as u32;
+        self.positions.extend_from_slice(&other.positions);
+        self.normals.extend_from_slice(&other.normals);
+        self.uvs.extend_from_slice(&other.uvs);
+        self.indices
+            .extend(other.indices.iter().map(|index| index + start));
+    }
+
+    // Merges vertices at the same position, such as the shared borders of appended chunks, into
+    // the first of them.
+    pub fn weld(&mut self) {
+        let mut welded = ExportMesh::default();
+        let mut index_of: HashMap<[u32; 3], u32> = HashMap::new();
+        let remap: Vec<u32> = (0..self.positions.len())
+            .map(|i| {
+                *index_of
+                    .entry(self.positions[i].map(f32::to_bits))
+                    .or_insert_with(|| {
+                        welded.positions.push(self.positions[i]);
+                        welded.normals.push(self.normals[i]);
+                        welded.uvs.push(self.uvs[i]);
+                        welded.positions.len() as u32 - 1
+                    })
+            })
+            .collect();
+        welded.indices = self
+            .indices
+            .iter()
+            .map(|index| remap[*index as usize])
+            .collect();
+        *self = welded;
+    }
+
+    pub fn triangles(&self) -> impl Iterator<Item = [[f32; 3]; 3]> + '_ {
+        self.indices.chunks(3).map(move |triangle| {
+            [
+                self.positions[triangle[0] as usize],
+                self.positions[triangle[1] as usize],
+                self.positions[triangle[2] as usize],
+            ]
+        })
+    }
+
+    pub fn write_obj(&self, writer: &mut impl Write) -> io::Result<()> {
+        writeln!(writer, "# Exported from Petra")?;
+        for [x, y, z] in &self.positions {
+            writeln!(writer, "v {} {} {}", x, y, z)?;
+        }
+        for [x, y, z] in &self.normals {
+            writeln!(writer, "vn {} {} {}", x, y, z)?;
+        }
+        for [u, v] in &self.uvs {
+            writeln!(writer, "vt {} {}", u, 1.0 - v)?;
+        }
+        for triangle in self.indices.chunks(3) {
+            // OBJ indices start at 1.
+            let [a, b, c] = [triangle[0] + 1, triangle[1] + 1, triangle[2] + 1];
+            writeln!(writer, "f {a}/{a}/{a} {b}/{b}/{b} {c}/{c}/{c}")?;
+        }
+        Ok(())
+    }
+
+    // Binary STL is z up, so the terrain is turned on its back. Facet normals come from the
+    // triangles themselves, since STL has no vertex normals.
+    pub fn write_stl(&self, writer: &mut impl Write) -> io::Result<()> {
+        let z_up = |[x, y, z]: [f32; 3]| [x, -z, y];
+        writer.write_all(&[0; 80])?;
+        writer.write_all(&((self.indices.len() / 3) as u32).to_le_bytes())?;
+        for triangle in self.triangles() {
+            let [a, b, c] = triangle.map(z_up);
+            let normal = cross(sub(b, a), sub(c, a));
+            let length = dot(normal, normal).sqrt();
+            let normal = if length > 0.0 {
+                normal.map(|n| n / length)
+            } else {
+                [0.0; 3]
+            };
+            for vector in [normal, a, b, c] {
+                for value in vector {
+                    writer.write_all(&value.to_le_bytes())?;
+                }
+            }
+            writer.write_all(&[0; 2])?;
+        }
+        Ok(())
+    }
+
+    // One mesh with one primitive, everything in a single buffer.
+    pub fn write_glb(&self, writer: &mut impl Write) -> io::Result<()> {
+        let mut buffer: Vec<u8> = Vec::new();
+        let mut views = Vec::new();
+        let mut add_view = |bytes: Vec<u8>, target: u32| {
+            views.push(format!(
+                r#"{{"buffer":0,"byteOffset":{},"byteLength":{},"target":{}}}"#,
+                buffer.len(),
+                bytes.len(),
+                target
+            ));
+            buffer.extend_from_slice(&bytes);
+        };
+        add_view(bytes(self.positions.iter().flatten()), 34962);
+        add_view(bytes(self.normals.iter().flatten()), 34962);
+        add_view(bytes(self.uvs.iter().flatten()), 34962);
+        add_view(
+            self.indices
+                .iter()
+                .flat_map(|index| index.to_le_bytes())
+                .collect(),
+            34963,
+        );
+
+        let (min, max) =
+            self.positions
+                .iter()
+                .fold(([f32::MAX; 3], [f32::MIN; 3]), |(min, max), position| {
+                    (
+                        [0, 1, 2].map(|i| min[i].min(position[i])),
+                        [0, 1, 2].map(|i| max[i].max(position[i])),
+                    )
+                });
+        let json = format!(
+            concat!(
+                r#"{{"asset":{{"version":"2.0","generator":"Petra"}},"#,
+                r#""scene":0,"scenes":[{{"nodes":[0]}}],"nodes":[{{"mesh":0,"name":"Terrain"}}],"#,
+                r#""meshes":[{{"primitives":[{{"attributes":{{"POSITION":0,"NORMAL":1,"TEXCOORD_0":2}},"indices":3}}]}}],"#,
+                r#""accessors":["#,
+                r#"{{"bufferView":0,"componentType":5126,"count":{vertices},"type":"VEC3","min":[{},{},{}],"max":[{},{},{}]}},"#,
+                r#"{{"bufferView":1,"componentType":5126,"count":{vertices},"type":"VEC3"}},"#,
+                r#"{{"bufferView":2,"componentType":5126,"count":{vertices},"type":"VEC2"}},"#,
+                r#"{{"bufferView":3,"componentType":5125,"count":{indices},"type":"SCALAR"}}],"#,
+                r#""bufferViews":[{views}],"buffers":[{{"byteLength":{length}}}]}}"#
+            ),
+            min[0],
+            min[1],
+            min[2],
+            max[0],
+            max[1],
+            max[2],
+            vertices = self.positions.len(),
+            indices = self.indices.len(),
+            views = views.join(","),
+            length = buffer.len(),
+        );
+
+        // Both chunks have to be padded to 4 bytes, the JSON with spaces and the buffer with zeros.
+        let mut json = json.into_bytes();
+        json.resize((json.len() + 3) / 4 * 4, b' ');
+        buffer.resize((buffer.len() + 3) / 4 * 4, 0);
+        let length = 12 + 8 + json.len() + 8 + buffer.len();
+
+        writer.write_all(b"glTF")?;
+        writer.write_all(&2u32.to_le_bytes())?;
+        writer.write_all(&(length as u32).to_le_bytes())?;
+        writer.write_all(&(json.len() as u32).to_le_bytes())?;
+        writer.write_all(b"JSON")?;
+        writer.write_all(&json)?;
+        writer.write_all(&(buffer.len() as u32).to_le_bytes())?;
+        writer.write_all(b"BIN\0")?;
+        writer.write_all(&buffer)
+    }
+}
+
+fn bytes<'a>(values: impl Iterator<Item = &'a f32>) -> Vec<u8> {
+    values.flat_map(|value| value.to_le_bytes()).collect()
+}
+
+fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+// Exports the bounds, reaching the first cell past them, welded into one mesh. With tiles, the part
+// in every chunk goes to its own file instead, named after the path with the chunk coordinates
+// added, e.g. terrain_0_-1.obj.
+pub fn export_mesh(
+    terrain: &Terrain,
+    bounds: Bounds,
+    max_error: Option<f32>,
+    tiles: bool,
+    format: MeshFormat,
+    path: &str,
+) -> io::Result<()> {
+    let chunk_size = terrain.data.chunk_size;
+    let chunks: Vec<(i32, i32)> = bounds.chunk_coordinates(chunk_size).collect();
+    if chunks.is_empty() {
+        return Ok(());
+    }
+    let world = |cell: (i32, i32)| {
+        (
+            cell.0 as f32 * terrain.cell_size,
+            cell.1 as f32 * terrain.cell_size,
+        )
+    };
+    let (uv_min, uv_max) = (world(bounds.min), world(bounds.max));
+    let mut combined = ExportMesh::default();
+    for (x, z) in chunks {
+        let mesh = ExportMesh::from_chunk(terrain, (x, z), bounds, max_error, uv_min, uv_max);
+        if tiles {
+            let stem = path
+                .strip_suffix(&format!(".{}", format.extension()))
+                .unwrap_or(path);
+            format.write(
+                &mesh,
+                &format!("{}_{}_{}.{}", stem, x, z, format.extension()),
+            )?;
+        } else {
+            combined.append(&mesh);
+        }
+    }
+    if tiles {
+        Ok(())
+    } else {
+        combined.weld();
+        format.write(&combined, path)
+    }
+}
+
+// Settings for solid, 3D-printable exports.
+#[derive(Debug, Clone, Copy)]
+pub struct SolidSettings {
+    pub size: f32,         // Length of the longest side of the print, in millimetres.
+    pub base: f32,         // Thickness of the base below the lowest possible point, in millimetres.
+    pub exaggeration: f32, // Vertical scale relative to the horizontal scale.
+}
+
+impl Default for SolidSettings {
+    fn default() -> Self {
+        Self {
+            size: 150.0,
+            base: 3.0,
+            exaggeration: 1.0,
+        }
+    }
+}
+
+// Closed, watertight solid of the bounds, in millimetres: the terrain on top, side walls and a
+// flat bottom. Like a chunk mesh, the top reaches the first cell past the bounds, so prints of
+// neighbouring bounds share their edge. Heights are measured from -Terrain::height, the lowest the
+// generator goes, so prints of neighbouring regions line up unless something was carved deeper
+// than that.
+pub fn solid_mesh(terrain: &Terrain, bounds: Bounds, settings: &SolidSettings) -> ExportMesh {
+    let origin = bounds.min;
+    let columns = bounds.width() as usize + 1;
+    let rows = bounds.height() as usize + 1;
+    let sampler = Sampler::for_terrain(terrain);
+
+    let mut heights = Vec::with_capacity(columns * rows);
+    for x in 0..columns {
+        for z in 0..rows {
+            heights.push(sampler.get_or((origin.0 + x as i32, origin.1 + z as i32), 0.0));
+        }
+    }
+    let floor = heights.iter().copied().fold(-terrain.height, f32::min);
+    // Millimetres per world unit, and between neighbouring cells.
+    let scale = settings.size / ((columns.max(rows) - 1) as f32 * terrain.cell_size);
+    let spacing = scale * terrain.cell_size;
+    let top = |x: usize, z: usize| {
+        [
+            x as f32 * spacing,
+            settings.base + (heights[x * rows + z] - floor) * scale * settings.exaggeration,
+            z as f32 * spacing,
+        ]
+    };
+
+    // The top is the same grid as the chunk meshes, only welded across chunk borders.
+    let mut mesh = ExportMesh::default();
+    for x in 0..columns {
+        for z in 0..rows {
+            let normal = terrain_normal(
+                &sampler,
+                (origin.0 + x as i32, origin.1 + z as i32),
+                terrain.cell_size,
+            );
+            mesh.positions.push(top(x, z));
+            mesh.normals.push([normal.x, normal.z, -normal.y]);
+            mesh.uvs.push([
+                x as f32 / (columns - 1) as f32,
+                z as f32 / (rows - 1) as f32,
+            ]);
+        }
+    }
+    mesh.indices = grid_indices(columns, rows);
+
+    // Walk around the border, then hang a wall from every step and close the bottom with a fan
+    // around its centre. Walls and bottom get their own vertices for their own normals, at exactly
+    // the same positions as the top's border, which keeps the solid watertight.
+    let (last_x, last_z) = (columns - 1, rows - 1);
+    let border: Vec<(usize, usize)> = (0..last_x)
+        .map(|x| (x, 0))
+        .chain((0..last_z).map(|z| (last_x, z)))
+        .chain((0..last_x).map(|x| (last_x - x, last_z)))
+        .chain((0..last_z).map(|z| (0, last_z - z)))
+        .chain(std::iter::once((0, 0)))
+        .collect();
+    for pair in border.windows(2) {
+        let [(ax, az), (bx, bz)] = [pair[0], pair[1]];
+        let (a, b) = (top(ax, az), top(bx, bz));
+        let (a_bottom, b_bottom) = ([a[0], 0.0, a[2]], [b[0], 0.0, b[2]]);
+        let outward = cross(sub(b, a), [0.0, -1.0, 0.0]);
+        let length = dot(outward, outward).sqrt();
+        let start = mesh.positions.len() as u32;
+        for position in [a, a_bottom, b, b_bottom] {
+            mesh.positions.push(position);
+            mesh.normals.push(outward.map(|n| n / length));
+            mesh.uvs.push([0.0, 0.0]);
+        }
+        mesh.indices.extend_from_slice(&[
+            start,
+            start + 2,
+            start + 1,
+            start + 2,
+            start + 3,
+            start + 1,
+        ]);
+    }
+
+    let center = mesh.positions.len() as u32;
+    mesh.positions.push([
+        last_x as f32 * spacing / 2.0,
+        0.0,
+        last_z as f32 * spacing / 2.0,
+    ]);
+    for &(x, z) in &border {
+        mesh.positions
+            .push([x as f32 * spacing, 0.0, z as f32 * spacing]);
+    }
+    mesh.normals.resize(mesh.positions.len(), [0.0, -1.0, 0.0]);
+    mesh.uvs.resize(mesh.positions.len(), [0.0, 0.0]);
+    for i in 1..border.len() as u32 {
+        mesh.indices
+            .extend_from_slice(&[center, center + i, center + i + 1]);
+    }
+    mesh
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::petra::generate;
+
+    // Every edge between two triangles is shared by exactly two of them, running opposite ways,
+    // and only the outside of the mesh has edges with one triangle.
+    #[test]
+    fn simplified_chunks_weld_into_a_manifold() {
+        let mut terrain = Terrain::default();
+        terrain.data = terrain.data.rechunk(16);
+        generate::fbm(&mut terrain, (0, 0), (1, 1), 0);
+        let mut mesh = ExportMesh::default();
+        for chunk in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+            mesh.append(&ExportMesh::from_chunk(
+                &terrain,
+                chunk,
+                Bounds::of_chunk(chunk, 16),
+                Some(0.5),
+                (0.0, 0.0),
+                (32.0, 32.0),
+            ));
+        }
+        let unwelded = mesh.positions.len();
+        mesh.weld();
+        assert!(mesh.positions.len() < unwelded);
+
+        let mut edges: HashMap<(u32, u32), usize> = HashMap::new();
+        for triangle in mesh.indices.chunks(3) {
+            for i in 0..3 {
+                *edges
+                    .entry((triangle[i], triangle[(i + 1) % 3]))
+                    .or_default() += 1;
+            }
+        }
+        for (&(a, b), &count) in &edges {
+            assert_eq!(count, 1, "edge used twice the same way");
+            if !edges.contains_key(&(b, a)) {
+                let [a, b] = [a, b].map(|i| mesh.positions[i as usize]);
+                let outside =
+                    |p: [f32; 3]| p[0] == 0.0 || p[2] == 0.0 || p[0] == 32.0 || p[2] == 32.0;
+                assert!(
+                    outside(a) && outside(b),
+                    "crack between {:?} and {:?}",
+                    a,
+                    b
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn selection_is_cut_out_of_its_chunks() {
+        let mut terrain = Terrain::default();
+        terrain.data = terrain.data.rechunk(16);
+        generate::fbm(&mut terrain, (0, 0), (1, 1), 0);
+        let bounds = Bounds {
+            min: (5, 3),
+            max: (27, 20),
+        };
+        for max_error in [None, Some(0.5)] {
+            let mut mesh = ExportMesh::default();
+            for chunk in bounds.chunk_coordinates(16) {
+                let part = ExportMesh::from_chunk(
+                    &terrain,
+                    chunk,
+                    bounds,
+                    max_error,
+                    (5.0, 3.0),
+                    (27.0, 20.0),
+                );
+                mesh.append(&part);
+            }
+            mesh.weld();
+            assert!(!mesh.indices.is_empty());
+            for [x, _, z] in &mesh.positions {
+                assert!((5.0..=27.0).contains(x) && (3.0..=20.0).contains(z));
+            }
+            for [u, v] in &mesh.uvs {
+                assert!((0.0..=1.0).contains(u) && (0.0..=1.0).contains(v));
+            }
+            // The corners of the selection are all there.
+            for corner in [[5.0, 3.0], [27.0, 3.0], [5.0, 20.0], [27.0, 20.0]] {
+                assert!(mesh.positions.iter().any(|[x, _, z]| [*x, *z] == corner));
+            }
+        }
+    }
+
+    #[test]
+    fn solid_is_watertight() {
+        let mut terrain = Terrain::default();
+        generate::fbm(&mut terrain, (0, 0), (0, 0), 0);
+        let bounds = Bounds {
+            min: (3, 5),
+            max: (13, 11),
+        };
+        let settings = SolidSettings::default();
+        let mut mesh = solid_mesh(&terrain, bounds, &settings);
+        let heights: Vec<f32> = mesh.positions.iter().map(|[_, y, _]| *y).collect();
+        assert!(heights.iter().any(|y| *y != heights[0] && *y > 0.0));
+
+        // Walls and bottom have their own vertices, so weld them to the top to compare edges.
+        mesh.weld();
+        let mut edges: HashMap<(u32, u32), usize> = HashMap::new();
+        for triangle in mesh.indices.chunks(3) {
+            for i in 0..3 {
+                *edges
+                    .entry((triangle[i], triangle[(i + 1) % 3]))
+                    .or_default() += 1;
+            }
+        }
+        for (&(a, b), &count) in &edges {
+            assert_eq!(count, 1, "edge used twice the same way");
+            assert_eq!(edges.get(&(b, a)), Some(&1), "open edge");
+        }
+
+        // Flat bottom at 0, with the terrain at least the base's thickness above it.
+        let (bottom, top): (Vec<[f32; 3]>, Vec<[f32; 3]>) =
+            mesh.positions.iter().partition(|[_, y, _]| *y == 0.0);
+        assert!(!bottom.is_empty());
+        assert!(top.iter().all(|[_, y, _]| *y >= settings.base));
+        // The longest side is the size asked for, the other one in proportion.
+        let extent = |axis: usize| {
+            let values = mesh.positions.iter().map(|position| position[axis]);
+            values.clone().fold(f32::MIN, f32::max) - values.fold(f32::MAX, f32::min)
+        };
+        assert!((extent(0) - settings.size).abs() < 1e-3);
+        assert!((extent(2) - settings.size * 6.0 / 10.0).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,36 @@
+use crate::petra::terrain::{Terrain, TerrainDataChunk};
+use noise::{Fbm, MultiFractal, NoiseFn, Seedable};
+use rayon::prelude::*;
+
+// Replaces the chunks between min and max (inclusive, in chunk coordinates) with fractal noise,
+// scaled by the terrain's noisescale and height. The noise is laid out in world units, so the cell
+// size changes the resolution of the terrain, not the size of its features. Chunks are generated
+// in parallel, and the result only depends on the seed.
+pub fn fbm(terrain: &mut Terrain, min: (i32, i32), max: (i32, i32), seed: u32) {
+    let noise = Fbm::new().set_seed(seed).set_octaves(6);
+    let noisescale = terrain.noisescale as f64 * terrain.cell_size as f64;
+    let height = terrain.height;
+    let chunk_size = terrain.data.chunk_size;
+
+    let coordinates: Vec<(i32, i32)> = (min.1..=max.1)
+        .flat_map(|y| (min.0..=max.0).map(move |x| (x, y)))
+        .collect();
+    let chunks: Vec<TerrainDataChunk> = coordinates
+        .par_iter()
+        .map(|chunk_coordinates| {
+            let mut chunk = TerrainDataChunk::new(*chunk_coordinates, chunk_size);
+            let size = chunk_size as i32;
+            for (i, value) in chunk.data.iter_mut().enumerate() {
+                let x = chunk_coordinates.0 * size + i as i32 % size;
+                let y = chunk_coordinates.1 * size + i as i32 / size;
+                *value = noise.get([x as f64 * noisescale, y as f64 * noisescale]) as f32 * height;
+            }
+            chunk.touch();
+            chunk
+        })
+        .collect();
+
+    for chunk in chunks {
+        terrain.data.chunks.insert(chunk.coords, chunk);
+    }
+}
@@ -0,0 +1,402 @@
+use crate::petra::analysis::{self, Layer};
+use crate::petra::terrain::{Bounds, Terrain};
+use crate::petra::vector::{Feature, Geometry};
+use bevy::math::{vec2, Vec2};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GridShape {
+    // Pointy-topped hexes in offset rows, every odd row pushed half a hex to the east.
+    Hex,
+    Square,
+}
+
+impl GridShape {
+    pub const ALL: [GridShape; 2] = [GridShape::Hex, GridShape::Square];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            GridShape::Hex => "Hex",
+            GridShape::Square => "Square",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Class {
+    Water,
+    Plains,
+    Forest,
+    Hills,
+    Mountains,
+}
+
+impl Class {
+    pub const ALL: [Class; 5] = [
+        Class::Water,
+        Class::Plains,
+        Class::Forest,
+        Class::Hills,
+        Class::Mountains,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Class::Water => "water",
+            Class::Plains => "plains",
+            Class::Forest => "forest",
+            Class::Hills => "hills",
+            Class::Mountains => "mountains",
+        }
+    }
+
+    fn colour(&self) -> &'static str {
+        match self {
+            Class::Water => "#8fb8de",
+            Class::Plains => "#d8e3a0",
+            Class::Forest => "#7fa868",
+            Class::Hills => "#c9a66b",
+            Class::Mountains => "#9a8f86",
+        }
+    }
+}
+
+// Tiles size world units across, flat side to flat side for hexes. Heights are fractions of the
+// way from sea level to the highest point, like the atlas. Slopes are ranked against the rest of
+// the land from 0 to 1, so the same settings work for gentle and rugged terrain.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GridSettings {
+    pub shape: GridShape,
+    pub size: f32,
+    // More of the tile than this under the sea is water.
+    pub water: f32,
+    // Tiles this high on average are mountains.
+    pub mountain_height: f32,
+    // Lower tiles this high, or at least this rugged, are hills.
+    pub hill_height: f32,
+    pub hill_slope: f32,
+    // Land between flat and rugged, which could carry forest.
+    pub forest_slope: f32,
+}
+
+impl Default for GridSettings {
+    fn default() -> Self {
+        Self {
+            shape: GridShape::Hex,
+            size: 16.0,
+            water: 0.5,
+            mountain_height: 0.45,
+            hill_height: 0.25,
+            hill_slope: 0.8,
+            forest_slope: 0.4,
+        }
+    }
+}
+
+pub struct Tile {
+    pub column: i32,
+    pub row: i32,
+    // In world x and z.
+    pub center: Vec2,
+    pub class: Class,
+    pub cells: usize,
+    pub mean_height: f32,
+    pub max_height: f32,
+    // In degrees.
+    pub mean_slope: f32,
+    // Share of the cells under the sea.
+    pub water: f32,
+}
+
+impl GridSettings {
+    // Hex radius, from the center to a corner.
+    fn radius(&self) -> f32 {
+        self.size / 3f32.sqrt()
+    }
+
+    // Column and row of the tile a point is in, relative to the top left of the grid.
+    fn tile_at(&self, point: Vec2) -> (i32, i32) {
+        match self.shape {
+            GridShape::Square => (
+                (point.x / self.size).floor() as i32,
+                (point.y / self.size).floor() as i32,
+            ),
+            GridShape::Hex => {
+                // Axial coordinates, rounded through cube coordinates.
+                let radius = self.radius();
+                let q = (3f32.sqrt() / 3.0 * point.x - point.y / 3.0) / radius;
+                let r = 2.0 / 3.0 * point.y / radius;
+                let s = -q - r;
+                let (mut rq, mut rr, rs) = (q.round(), r.round(), s.round());
+                let (dq, dr, ds) = ((rq - q).abs(), (rr - r).abs(), (rs - s).abs());
+                if dq > dr && dq > ds {
+                    rq = -rr - rs;
+                } else if dr > ds {
+                    rr = -rq - rs;
+                }
+                let (q, r) = (rq as i32, rr as i32);
+                (q + (r - (r & 1)) / 2, r)
+            }
+        }
+    }
+
+    fn center(&self, (column, row): (i32, i32)) -> Vec2 {
+        match self.shape {
+            GridShape::Square => vec2(column as f32 + 0.5, row as f32 + 0.5) * self.size,
+            GridShape::Hex => vec2(
+                self.size * (column as f32 + 0.5 * (row & 1) as f32),
+                self.radius() * 1.5 * row as f32,
+            ),
+        }
+    }
+
+    // Corners clockwise on screen, closed.
+    fn outline(&self, center: Vec2) -> Vec<Vec2> {
+        let mut corners: Vec<Vec2> = match self.shape {
+            GridShape::Square => [(-0.5, -0.5), (0.5, -0.5), (0.5, 0.5), (-0.5, 0.5)]
+                .iter()
+                .map(|(x, y)| center + vec2(*x, *y) * self.size)
+                .collect(),
+            GridShape::Hex => (0..6)
+                .map(|i| {
+                    let angle = (60.0 * i as f32 - 90.0).to_radians();
+                    center + vec2(angle.cos(), angle.sin()) * self.radius()
+                })
+                .collect(),
+        };
+        corners.push(corners[0]);
+        corners
+    }
+}
+
+// Every tile over the bounds, or over the whole terrain, row by row. The grid starts at the top
+// left corner of the first cell.
+pub fn tiles(terrain: &Terrain, settings: &GridSettings, bounds: Option<Bounds>) -> Vec<Tile> {
+    let bounds = match bounds.or_else(|| terrain.data.bounds()) {
+        Some(bounds) => bounds,
+        None => return Vec::new(),
+    };
+    if settings.size <= 0.0 {
+        return Vec::new();
+    }
+    let heights = terrain.data.region(bounds);
+    let slopes = analysis::compute(terrain, Layer::Slope, bounds);
+    let sea_level = terrain.sea_level;
+    let origin = (vec2(bounds.min.0 as f32, bounds.min.1 as f32) - 0.5) * terrain.cell_size;
+
+    // Cells, sum of heights, highest height, sum of slopes and cells under the sea.
+    type Sums = (usize, f32, f32, f32, usize);
+    let mut sums: HashMap<(i32, i32), Sums> = HashMap::new();
+    let mut land_slopes = Vec::new();
+    let mut highest = sea_level;
+    for (i, (height, slope)) in heights.data.iter().zip(&slopes.data).enumerate() {
+        let cell = (
+            bounds.min.0 + i as i32 % bounds.width(),
+            bounds.min.1 + i as i32 / bounds.width(),
+        );
+        let point = vec2(cell.0 as f32, cell.1 as f32) * terrain.cell_size - origin;
+        let sum =
+            sums.entry(settings.tile_at(point))
+                .or_insert((0, 0.0, f32::NEG_INFINITY, 0.0, 0));
+        sum.0 += 1;
+        sum.1 += height;
+        sum.2 = sum.2.max(*height);
+        sum.3 += slope;
+        if *height <= sea_level {
+            sum.4 += 1;
+        } else {
+            land_slopes.push(*slope);
+        }
+        highest = highest.max(*height);
+    }
+    land_slopes.sort_by(|a, b| a.total_cmp(b));
+    let rank = |slope: f32| {
+        land_slopes.partition_point(|other| *other < slope) as f32 / land_slopes.len().max(1) as f32
+    };
+    let relative = |height: f32| (height - sea_level) / (highest - sea_level).max(1e-6);
+
+    let mut tiles: Vec<Tile> = sums
+        .into_iter()
+        .map(
+            |((column, row), (cells, height_sum, max_height, slope_sum, wet))| {
+                let mean_height = height_sum / cells as f32;
+                let mean_slope = slope_sum / cells as f32;
+                let water = wet as f32 / cells as f32;
+                let class = if water > settings.water {
+                    Class::Water
+                } else if relative(mean_height) >= settings.mountain_height {
+                    Class::Mountains
+                } else if relative(mean_height) >= settings.hill_height
+                    || rank(mean_slope) >= settings.hill_slope
+                {
+                    Class::Hills
+                } else if rank(mean_slope) >= settings.forest_slope {
+                    Class::Forest
+                } else {
+                    Class::Plains
+                };
+                Tile {
+                    column,
+                    row,
+                    center: settings.center((column, row)) + origin,
+                    class,
+                    cells,
+                    mean_height,
+                    max_height,
+                    mean_slope,
+                    water,
+                }
+            },
+        )
+        .collect();
+    tiles.sort_by_key(|tile| (tile.row, tile.column));
+    tiles
+}
+
+pub fn to_csv(tiles: &[Tile]) -> String {
+    let mut csv =
+        String::from("column,row,x,z,class,cells,mean_height,max_height,mean_slope,water\n");
+    for tile in tiles {
+        csv += &format!(
+            "{},{},{},{},{},{},{},{},{},{}\n",
+            tile.column,
+            tile.row,
+            tile.center.x,
+            tile.center.y,
+            tile.class.name(),
+            tile.cells,
+            tile.mean_height,
+            tile.max_height,
+            tile.mean_slope,
+            tile.water
+        );
+    }
+    csv
+}
+
+pub fn to_json(tiles: &[Tile], settings: &GridSettings) -> String {
+    let tiles: Vec<String> = tiles
+        .iter()
+        .map(|tile| {
+            format!(
+                concat!(
+                    "{{\"column\":{},\"row\":{},\"x\":{},\"z\":{},\"class\":\"{}\",\"cells\":{},",
+                    "\"mean_height\":{},\"max_height\":{},\"mean_slope\":{},\"water\":{}}}"
+                ),
+                tile.column,
+                tile.row,
+                tile.center.x,
+                tile.center.y,
+                tile.class.name(),
+                tile.cells,
+                tile.mean_height,
+                tile.max_height,
+                tile.mean_slope,
+                tile.water
+            )
+        })
+        .collect();
+    format!(
+        "{{\"shape\":\"{}\",\"size\":{},\"tiles\":[\n{}\n]}}",
+        settings.shape.name().to_lowercase(),
+        settings.size,
+        tiles.join(",\n")
+    )
+}
+
+// Outlines of the tiles, with their column, row and class as its index in Class::ALL.
+pub fn features(tiles: &[Tile], settings: &GridSettings) -> Vec<Feature> {
+    tiles
+        .iter()
+        .map(|tile| {
+            let class = Class::ALL.iter().position(|class| *class == tile.class);
+            Feature::new(Geometry::Polygon(vec![settings.outline(tile.center)]))
+                .with("column", tile.column as f64)
+                .with("row", tile.row as f64)
+                .with("class", class.unwrap() as f64)
+        })
+        .collect()
+}
+
+// Filled with the colour of the class, with a thin grid line.
+pub fn svg_style(feature: &Feature) -> String {
+    let class = feature
+        .properties
+        .iter()
+        .find(|(name, _)| *name == "class")
+        .map_or(Class::Plains, |(_, index)| Class::ALL[*index as usize]);
+    format!(
+        "fill=\"{}\" stroke=\"#555555\" stroke-width=\"1\" vector-effect=\"non-scaling-stroke\"",
+        class.colour()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn centers_are_in_their_own_tiles() {
+        for shape in GridShape::ALL {
+            let settings = GridSettings {
+                shape,
+                size: 10.0,
+                ..Default::default()
+            };
+            for row in -3..=3 {
+                for column in -3..=3 {
+                    let center = settings.center((column, row));
+                    assert_eq!(settings.tile_at(center), (column, row), "{:?}", shape);
+                    // Anywhere within the inner circle of the tile too.
+                    for i in 0..8 {
+                        let angle = (45.0 * i as f32).to_radians();
+                        let point = center + vec2(angle.cos(), angle.sin()) * 4.5;
+                        assert_eq!(settings.tile_at(point), (column, row), "{:?}", shape);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn tiles_mostly_under_the_sea_are_water() {
+        let mut terrain = Terrain::default();
+        // Sea over the north-west, up to x = 26, and land rising to the east everywhere else.
+        for y in 0..32 {
+            for x in 0..32 {
+                terrain.data[(x, y)] = if x < 26 && y < 16 {
+                    -1.0
+                } else {
+                    5.0 + x as f32 * 0.1
+                };
+            }
+        }
+        let bounds = Bounds {
+            min: (0, 0),
+            max: (32, 32),
+        };
+        let settings = GridSettings {
+            shape: GridShape::Square,
+            ..Default::default()
+        };
+        let tiles = tiles(&terrain, &settings, Some(bounds));
+        let classes: Vec<(i32, i32, Class)> = tiles
+            .iter()
+            .map(|tile| (tile.column, tile.row, tile.class))
+            .collect();
+        assert_eq!(classes.len(), 4);
+        assert_eq!(classes[0], (0, 0, Class::Water));
+        // Under the sea for 10 of its 16 columns.
+        assert_eq!(classes[1], (1, 0, Class::Water));
+        assert!((tiles[1].water - 10.0 / 16.0).abs() < 1e-6);
+        assert!(classes[2..]
+            .iter()
+            .all(|(_, _, class)| *class != Class::Water));
+
+        let hexes = super::tiles(&terrain, &GridSettings::default(), Some(bounds));
+        for tile in &hexes {
+            assert_eq!(tile.class == Class::Water, tile.water > 0.5);
+        }
+        assert!(hexes.iter().any(|tile| tile.class == Class::Water));
+    }
+}
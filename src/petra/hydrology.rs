@@ -0,0 +1,750 @@
+use crate::petra::cursor::Region;
+use crate::petra::sampler::{Interpolation, Sampler};
+use crate::petra::terrain::{Bounds, Terrain, TerrainData};
+use crate::petra::vector::{self, Feature, Geometry};
+use bevy::math::{vec2, Vec2};
+use rayon::prelude::*;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::f32::consts::FRAC_PI_4;
+
+// Where water goes from every cell of the terrain, and how much of it passes through each cell.
+// Water only ever moves to a lower neighbour, so it collects in pits and at the edges of the map,
+// and it stops as soon as it reaches the sea.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlowMethod {
+    // Everything goes to the neighbour with the steepest drop.
+    D8,
+    // Water follows the downhill direction of the surface, split between the two neighbours on
+    // either side of it.
+    DInfinity,
+}
+
+impl FlowMethod {
+    pub const ALL: [FlowMethod; 2] = [FlowMethod::D8, FlowMethod::DInfinity];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FlowMethod::D8 => "D8",
+            FlowMethod::DInfinity => "D-infinity",
+        }
+    }
+}
+
+// Neighbours counterclockwise from +x, in the same order as the angles of directions.
+const NEIGHBOURS: [(i32, i32); 8] = [
+    (1, 0),
+    (1, 1),
+    (0, 1),
+    (-1, 1),
+    (-1, 0),
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+];
+
+// Heights of the terrain with the receivers of every cell: up to two neighbours, each with the
+// share of the water it gets. Cells at or below sea level don't have any.
+pub struct Flow {
+    pub heights: Region,
+    pub sea_level: f32,
+    pub method: FlowMethod,
+    receivers: Vec<[Option<(usize, f32)>; 2]>,
+}
+
+impl Flow {
+    // Flow over the heights, which don't have to be the terrain's own, with the terrain's edge
+    // policy and interpolation.
+    pub fn new(terrain: &Terrain, heights: Region, method: FlowMethod) -> Self {
+        let bounds = heights.bounds;
+        let data = TerrainData::from_region(&heights, terrain.data.chunk_size);
+        // Bilinear gradients are one-sided right at a cell, which would pull all the water to one
+        // side, so D-infinity always uses a smooth kernel.
+        let interpolation = match terrain.interpolation {
+            Interpolation::Nearest | Interpolation::Bilinear => Interpolation::CatmullRom,
+            interpolation => interpolation,
+        };
+        let receivers = (0..heights.data.len())
+            .into_par_iter()
+            .map_init(
+                || Sampler::new(&data, terrain.edge_policy, interpolation),
+                |sampler, i| {
+                    if heights.data[i] <= terrain.sea_level {
+                        return [None, None];
+                    }
+                    let cell = cell_of(bounds, i);
+                    let steepest = steepest_neighbour(&heights, cell);
+                    match method {
+                        FlowMethod::D8 => [steepest.map(|receiver| (receiver, 1.0)), None],
+                        FlowMethod::DInfinity => split_downhill(&heights, sampler, cell)
+                            .unwrap_or([steepest.map(|receiver| (receiver, 1.0)), None]),
+                    }
+                },
+            )
+            .collect();
+        Self {
+            heights,
+            sea_level: terrain.sea_level,
+            method,
+            receivers,
+        }
+    }
+
+    // Flow over the terrain's heights, with its pits filled first when there's a fill epsilon.
+    pub fn for_terrain(
+        terrain: &Terrain,
+        method: FlowMethod,
+        fill_epsilon: Option<f32>,
+    ) -> Option<Self> {
+        let mut heights = terrain.data.region(terrain.data.bounds()?);
+        if let Some(epsilon) = fill_epsilon {
+            heights = fill(&heights, terrain.sea_level, epsilon);
+        }
+        Some(Self::new(terrain, heights, method))
+    }
+
+    pub fn bounds(&self) -> Bounds {
+        self.heights.bounds
+    }
+
+    fn in_sea(&self, i: usize) -> bool {
+        self.heights.data[i] <= self.sea_level
+    }
+
+    // Indices of cells from highest to lowest, so every cell comes after everything upstream of it.
+    fn downstream_order(&self) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..self.heights.data.len()).collect();
+        order.sort_by(|a, b| self.heights.data[*b].total_cmp(&self.heights.data[*a]));
+        order
+    }
+
+    // Neighbour that gets most of the water from a cell, if it has anywhere lower to go.
+    fn main_receiver(&self, i: usize) -> Option<usize> {
+        self.receivers[i]
+            .iter()
+            .flatten()
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(receiver, _)| *receiver)
+    }
+
+    // Cells the water takes from a cell to where it stops, following the main receivers. The path
+    // ends at a pit, the edge of the heights or the first cell in the sea.
+    pub fn path(&self, from: (i32, i32)) -> Vec<(i32, i32)> {
+        let bounds = self.bounds();
+        let mut path = Vec::new();
+        let mut next = index_of(bounds, from);
+        while let Some(i) = next {
+            path.push(cell_of(bounds, i));
+            next = self.main_receiver(i);
+        }
+        path
+    }
+
+    // Compass direction of the flow out of every cell, in degrees clockwise from -z like aspect, or
+    // -1 where the water has nowhere lower to go.
+    pub fn direction(&self) -> Region {
+        let mut direction = Region::new(self.bounds());
+        let width = self.bounds().width() as usize;
+        for (i, value) in direction.data.iter_mut().enumerate() {
+            let mut flow = Vec2::ZERO;
+            for (receiver, share) in self.receivers[i].iter().flatten() {
+                let offset = vec2(
+                    (receiver % width) as f32 - (i % width) as f32,
+                    (receiver / width) as f32 - (i / width) as f32,
+                );
+                flow += offset.normalize() * *share;
+            }
+            *value = if flow == Vec2::ZERO {
+                -1.0
+            } else {
+                flow.x.atan2(-flow.y).to_degrees().rem_euclid(360.0)
+            };
+        }
+        direction
+    }
+
+    // Number of cells that drain through every cell, counting the cell itself. Water adds up in
+    // the first sea cell it reaches and goes no further.
+    pub fn accumulation(&self) -> Region {
+        let mut accumulation = Region::new(self.bounds());
+        accumulation.data.iter_mut().for_each(|value| *value = 1.0);
+        for i in self.downstream_order() {
+            let water = accumulation.data[i];
+            for (receiver, share) in self.receivers[i].iter().flatten() {
+                accumulation.data[*receiver] += water * share;
+            }
+        }
+        accumulation
+    }
+}
+
+fn cell_of(bounds: Bounds, i: usize) -> (i32, i32) {
+    let width = bounds.width() as usize;
+    (
+        bounds.min.0 + (i % width) as i32,
+        bounds.min.1 + (i / width) as i32,
+    )
+}
+
+fn index_of(bounds: Bounds, cell: (i32, i32)) -> Option<usize> {
+    if !bounds.contains(cell) {
+        return None;
+    }
+    Some(((cell.1 - bounds.min.1) * bounds.width() + cell.0 - bounds.min.0) as usize)
+}
+
+fn steepest_neighbour(heights: &Region, cell: (i32, i32)) -> Option<usize> {
+    let height = heights.get(cell)?;
+    let mut steepest = None;
+    let mut steepest_drop = 0.0;
+    for (dx, dy) in NEIGHBOURS {
+        let neighbour = (cell.0 + dx, cell.1 + dy);
+        if let Some(neighbour_height) = heights.get(neighbour) {
+            let distance = if dx != 0 && dy != 0 { 2f32.sqrt() } else { 1.0 };
+            let drop = (height - neighbour_height) / distance;
+            if drop > steepest_drop {
+                steepest_drop = drop;
+                steepest = index_of(heights.bounds, neighbour);
+            }
+        }
+    }
+    steepest
+}
+
+// Splits the water between the two neighbours on either side of the downhill direction, by how
+// close the direction is to each. Neighbours that aren't lower don't get any.
+fn split_downhill(
+    heights: &Region,
+    sampler: &Sampler,
+    cell: (i32, i32),
+) -> Option<[Option<(usize, f32)>; 2]> {
+    let height = heights.get(cell)?;
+    let (direction, drop) = sampler.downhill(vec2(cell.0 as f32, cell.1 as f32))?;
+    if drop <= 0.0 {
+        return None;
+    }
+    let angle = direction
+        .y
+        .atan2(direction.x)
+        .rem_euclid(std::f32::consts::TAU);
+    let sector = (angle / FRAC_PI_4).floor() as usize % 8;
+    let fraction = angle / FRAC_PI_4 - (angle / FRAC_PI_4).floor();
+    let mut receivers = [None, None];
+    let mut total = 0.0;
+    for (slot, (neighbour, share)) in [(sector, 1.0 - fraction), ((sector + 1) % 8, fraction)]
+        .into_iter()
+        .enumerate()
+    {
+        let (dx, dy) = NEIGHBOURS[neighbour];
+        let neighbour = (cell.0 + dx, cell.1 + dy);
+        match heights.get(neighbour) {
+            Some(neighbour_height) if neighbour_height < height && share > 0.0 => {
+                receivers[slot] = Some((index_of(heights.bounds, neighbour)?, share));
+                total += share;
+            }
+            _ => {}
+        }
+    }
+    if total <= 0.0 {
+        return None;
+    }
+    for (_, share) in receivers.iter_mut().flatten() {
+        *share /= total;
+    }
+    Some(receivers)
+}
+
+// A stretch of river between sources, confluences and the sea or a pit, in world units.
+pub struct River {
+    pub points: Vec<Vec2>,
+    // Strahler order: 1 for streams without tributaries, one more where two streams of the same
+    // order meet.
+    pub order: u32,
+    // Cells draining through the end of the stretch.
+    pub accumulation: f32,
+}
+
+// Rivers along every cell of the flow with at least threshold cells draining through it, ending
+// at their mouth in the sea. Rivers always follow the steepest neighbour, whatever method the
+// accumulation was computed with.
+pub fn rivers(terrain: &Terrain, flow: &Flow, accumulation: &Region, threshold: f32) -> Vec<River> {
+    let steepest;
+    let flow = match flow.method {
+        FlowMethod::D8 => flow,
+        FlowMethod::DInfinity => {
+            steepest = Flow::new(terrain, flow.heights.clone(), FlowMethod::D8);
+            &steepest
+        }
+    };
+    let bounds = flow.bounds();
+    let count = accumulation.data.len();
+    let is_river = |i: usize| accumulation.data[i] >= threshold;
+    let receiver = |i: usize| flow.receivers[i][0].map(|(receiver, _)| receiver);
+
+    // Strahler order, from the sources down.
+    let mut order = vec![0u32; count];
+    let mut highest_donor = vec![0u32; count];
+    let mut highest_donors = vec![0u32; count];
+    let mut donors = vec![0u32; count];
+    for i in flow.downstream_order() {
+        if !is_river(i) {
+            continue;
+        }
+        order[i] = match (highest_donor[i], highest_donors[i]) {
+            (0, _) => 1,
+            (highest, 1) => highest,
+            (highest, _) => highest + 1,
+        };
+        if let Some(next) = receiver(i).filter(|next| is_river(*next)) {
+            donors[next] += 1;
+            if order[i] > highest_donor[next] {
+                highest_donor[next] = order[i];
+                highest_donors[next] = 1;
+            } else if order[i] == highest_donor[next] {
+                highest_donors[next] += 1;
+            }
+        }
+    }
+
+    // A stretch starts at a source or where the order goes up, and carries on through confluences
+    // with smaller tributaries.
+    let starts = |i: usize| donors[i] == 0 || highest_donor[i] != order[i];
+    let position = |i: usize| {
+        let cell = cell_of(bounds, i);
+        vec2(cell.0 as f32, cell.1 as f32) * terrain.cell_size
+    };
+    let mut rivers = Vec::new();
+    for start in (0..count).filter(|i| is_river(*i) && starts(*i)) {
+        let mut points = vec![position(start)];
+        let mut current = start;
+        while let Some(next) = receiver(current).filter(|next| is_river(*next)) {
+            points.push(position(next));
+            let joins = order[next] != order[current];
+            current = next;
+            if joins {
+                break;
+            }
+        }
+        if points.len() > 1 {
+            rivers.push(River {
+                points,
+                order: order[start],
+                accumulation: accumulation.data[current],
+            });
+        }
+    }
+    rivers
+}
+
+// Cell in the priority-flood queue, lowest height first.
+#[derive(PartialEq)]
+struct Queued(f32, usize);
+
+impl Eq for Queued {}
+
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Queued {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.0.total_cmp(&self.0).then(other.1.cmp(&self.1))
+    }
+}
+
+// Slope that filled areas get towards their spill point unless asked otherwise: too little to see,
+// enough for water to find its way.
+pub const FILL_EPSILON: f32 = 0.001;
+
+// Raises every pit to the level where it spills over, flooding in from the edges of the heights
+// and from everything at or below sea level (after Barnes et al., Priority-Flood). With an epsilon,
+// filled areas slope down towards the spill point by that much per cell, so water can find its
+// way across them instead of stopping on a flat.
+pub fn fill(heights: &Region, sea_level: f32, epsilon: f32) -> Region {
+    let bounds = heights.bounds;
+    let mut filled = heights.clone();
+    let mut done = vec![false; heights.data.len()];
+    let mut queue = BinaryHeap::new();
+    for (i, height) in heights.data.iter().enumerate() {
+        let cell = cell_of(bounds, i);
+        let edge = cell.0 == bounds.min.0
+            || cell.1 == bounds.min.1
+            || cell.0 == bounds.max.0 - 1
+            || cell.1 == bounds.max.1 - 1;
+        if edge || *height <= sea_level {
+            done[i] = true;
+            queue.push(Queued(*height, i));
+        }
+    }
+    while let Some(Queued(level, i)) = queue.pop() {
+        let cell = cell_of(bounds, i);
+        for (dx, dy) in NEIGHBOURS {
+            if let Some(neighbour) = index_of(bounds, (cell.0 + dx, cell.1 + dy)) {
+                if done[neighbour] {
+                    continue;
+                }
+                done[neighbour] = true;
+                let height = &mut filled.data[neighbour];
+                *height = height.max(level + epsilon);
+                queue.push(Queued(*height, neighbour));
+            }
+        }
+    }
+    filled
+}
+
+pub struct Lake {
+    // Height of the water, where it spills over into the outlet.
+    pub spill_level: f32,
+    // Lowest cell around the lake, where the water leaves it.
+    pub outlet: (i32, i32),
+    pub cells: usize,
+    // In square world units and cubic world units.
+    pub area: f32,
+    pub volume: f32,
+    pub max_depth: f32,
+}
+
+// Standing water left in the terrain's pits.
+pub struct Lakes {
+    // Water depth of every cell, 0 where it's dry.
+    pub depth: Region,
+    pub lakes: Vec<Lake>,
+}
+
+impl Lakes {
+    pub fn to_json(&self) -> String {
+        let lakes: Vec<String> = self
+            .lakes
+            .iter()
+            .enumerate()
+            .map(|(i, lake)| {
+                format!(
+                    concat!(
+                        "{{\"label\":{},\"spill_level\":{},\"outlet\":[{},{}],\"cells\":{},",
+                        "\"area\":{},\"volume\":{},\"max_depth\":{}}}"
+                    ),
+                    i + 1,
+                    lake.spill_level,
+                    lake.outlet.0,
+                    lake.outlet.1,
+                    lake.cells,
+                    lake.area,
+                    lake.volume,
+                    lake.max_depth
+                )
+            })
+            .collect();
+        format!("[{}]", lakes.join(",\n"))
+    }
+
+    pub fn new(terrain: &Terrain, heights: &Region) -> Self {
+        let bounds = heights.bounds;
+        let filled = fill(heights, terrain.sea_level, 0.0);
+        let mut depth = Region::new(bounds);
+        for (i, value) in depth.data.iter_mut().enumerate() {
+            *value = filled.data[i] - heights.data[i];
+        }
+
+        // Wet cells that touch and share a level are the same lake. Labels count lakes from 1.
+        let cell_area = terrain.cell_size * terrain.cell_size;
+        let mut labels = vec![0u32; depth.data.len()];
+        let mut lakes = Vec::new();
+        for start in 0..depth.data.len() {
+            if depth.data[start] <= 0.0 || labels[start] != 0 {
+                continue;
+            }
+            let label = lakes.len() as u32 + 1;
+            let spill_level = filled.data[start];
+            let mut lake = Lake {
+                spill_level,
+                outlet: cell_of(bounds, start),
+                cells: 0,
+                area: 0.0,
+                volume: 0.0,
+                max_depth: 0.0,
+            };
+            let mut outlet_height = f32::INFINITY;
+            labels[start] = label;
+            let mut stack = vec![start];
+            while let Some(i) = stack.pop() {
+                lake.cells += 1;
+                lake.volume += depth.data[i] * cell_area;
+                lake.max_depth = lake.max_depth.max(depth.data[i]);
+                let cell = cell_of(bounds, i);
+                for (dx, dy) in NEIGHBOURS {
+                    let neighbour = (cell.0 + dx, cell.1 + dy);
+                    let j = match index_of(bounds, neighbour) {
+                        Some(j) => j,
+                        None => continue,
+                    };
+                    if depth.data[j] > 0.0 && filled.data[j] == spill_level {
+                        if labels[j] == 0 {
+                            labels[j] = label;
+                            stack.push(j);
+                        }
+                    } else if depth.data[j] <= 0.0 && heights.data[j] < outlet_height {
+                        outlet_height = heights.data[j];
+                        lake.outlet = neighbour;
+                    }
+                }
+            }
+            lake.area = lake.cells as f32 * cell_area;
+            lakes.push(lake);
+        }
+        Self { depth, lakes }
+    }
+}
+
+pub struct Basin {
+    // Cell the basin drains out of, at the edge of the world, into the sea or into a pit. For the
+    // sea itself, its lowest cell.
+    pub outlet: (i32, i32),
+    pub cells: usize,
+    // In square world units.
+    pub area: f32,
+}
+
+// Every cell labelled with the basin it drains into. Labels count basins from 1.
+pub struct Basins {
+    pub bounds: Bounds,
+    pub labels: Vec<u32>,
+    pub basins: Vec<Basin>,
+    // Label every cell at or below sea level shares, if there are any.
+    pub sea: Option<u32>,
+}
+
+impl Basins {
+    // Water follows the receiver that gets most of it, so with D-infinity a cell still belongs to
+    // one basin. Flow over filled heights drains every cell to the edge or the sea, otherwise
+    // every pit has a basin of its own. Each land cell that drains straight into the sea is the
+    // outlet of its own basin, so a smooth coast is split into one basin per coastal cell. The sea
+    // is one more.
+    pub fn new(flow: &Flow, cell_size: f32) -> Self {
+        let bounds = flow.bounds();
+        let mut labels = vec![0u32; flow.heights.data.len()];
+        let mut basins: Vec<Basin> = Vec::new();
+        let mut sea = None;
+        let new_basin = |basins: &mut Vec<Basin>, i: usize| {
+            basins.push(Basin {
+                outlet: cell_of(bounds, i),
+                cells: 0,
+                area: 0.0,
+            });
+            basins.len() as u32
+        };
+        // Lowest first, so a cell's receiver is always labelled before it.
+        for i in flow.downstream_order().into_iter().rev() {
+            let label = match flow.main_receiver(i) {
+                _ if flow.in_sea(i) => *sea.get_or_insert_with(|| new_basin(&mut basins, i)),
+                Some(receiver) if !flow.in_sea(receiver) => labels[receiver],
+                _ => new_basin(&mut basins, i),
+            };
+            labels[i] = label;
+            basins[label as usize - 1].cells += 1;
+        }
+        for basin in basins.iter_mut() {
+            basin.area = basin.cells as f32 * cell_size * cell_size;
+        }
+        Self {
+            bounds,
+            labels,
+            basins,
+            sea,
+        }
+    }
+
+    // Labels as heights, for saving as an image.
+    pub fn label_image(&self) -> Region {
+        let mut region = Region::new(self.bounds);
+        for (value, label) in region.data.iter_mut().zip(&self.labels) {
+            *value = *label as f32;
+        }
+        region
+    }
+
+    // Outlines of the basins with at least min_cells cells, with their label, area and outlet. The
+    // sea isn't a basin of its own here.
+    pub fn polygons(&self, cell_size: f32, min_cells: usize) -> Vec<Feature> {
+        let labels: Vec<u32> = self
+            .labels
+            .iter()
+            .map(|label| {
+                if Some(*label) != self.sea && self.basins[*label as usize - 1].cells >= min_cells {
+                    *label
+                } else {
+                    0
+                }
+            })
+            .collect();
+        let mut features = Vec::new();
+        for (label, polygons) in vector::outline_labels(&labels, self.bounds, cell_size) {
+            let basin = &self.basins[label as usize - 1];
+            for rings in polygons {
+                features.push(
+                    Feature::new(Geometry::Polygon(rings))
+                        .with("label", label as f64)
+                        .with("area", basin.area as f64)
+                        .with("cells", basin.cells as f64)
+                        .with("outlet_x", (basin.outlet.0 as f32 * cell_size) as f64)
+                        .with("outlet_z", (basin.outlet.1 as f32 * cell_size) as f64),
+                );
+            }
+        }
+        features
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Heights from a function of the cell, over the bounds.
+    fn heights(bounds: Bounds, height: impl Fn(i32, i32) -> f32) -> Region {
+        let mut heights = Region::new(bounds);
+        for (i, value) in heights.data.iter_mut().enumerate() {
+            let (x, y) = cell_of(bounds, i);
+            *value = height(x, y);
+        }
+        heights
+    }
+
+    #[test]
+    fn water_stops_at_the_sea() {
+        let terrain = Terrain::default();
+        // Land sloping down to x = 8 along a valley at y = 2, then sea deepening away from it.
+        let bounds = Bounds {
+            min: (0, 0),
+            max: (16, 5),
+        };
+        let valley = heights(bounds, |x, y| 8.0 - x as f32 + (y - 2).abs() as f32 * 0.5);
+        for method in FlowMethod::ALL {
+            let flow = Flow::new(&terrain, valley.clone(), method);
+            let accumulation = flow.accumulation();
+            // Water ends up in the sea cells along the coast, and nothing flows on from them.
+            for x in 10..16 {
+                assert_eq!(accumulation.get((x, 2)), Some(1.0));
+            }
+            assert_eq!(flow.path((0, 2)).last(), Some(&(8, 2)));
+        }
+        let flow = Flow::new(&terrain, valley, FlowMethod::D8);
+        let rivers = rivers(&terrain, &flow, &flow.accumulation(), 5.0);
+        assert_eq!(rivers.len(), 1);
+        assert_eq!(rivers[0].points.last(), Some(&vec2(8.0, 2.0)));
+    }
+
+    #[test]
+    fn sea_is_one_basin() {
+        let terrain = Terrain::default();
+        // Two valleys reaching the sea at x = 8, and a bumpy seabed with pits of its own.
+        let bounds = Bounds {
+            min: (0, 0),
+            max: (16, 9),
+        };
+        let coast = heights(bounds, |x, y| {
+            let across = ((y % 4) as f32 - 2.0).abs() * 0.5;
+            if x < 8 {
+                8.0 - x as f32 + across
+            } else {
+                -1.0 - ((x * 7 + y * 3) % 5) as f32
+            }
+        });
+        let flow = Flow::new(&terrain, coast, FlowMethod::D8);
+        let basins = Basins::new(&flow, 1.0);
+        let sea = basins.sea.unwrap();
+        for (i, label) in basins.labels.iter().enumerate() {
+            let (x, _) = cell_of(bounds, i);
+            assert_eq!(x >= 8, *label == sea);
+        }
+        assert_eq!(basins.basins[sea as usize - 1].cells, 8 * 9);
+        // The valleys drain into the sea at x = 7, each in a basin of its own.
+        assert_ne!(basins.labels[2 * 16], basins.labels[6 * 16]);
+        for basin in basins.basins.iter().filter(|basin| basin.outlet.0 < 8) {
+            assert_eq!(basin.outlet.0, 7);
+        }
+        let polygons = basins.polygons(1.0, 1);
+        assert!(polygons
+            .iter()
+            .all(|polygon| polygon.properties[0] != ("label", sea as f64)));
+    }
+
+    #[test]
+    fn every_coastal_cell_is_an_outlet() {
+        let terrain = Terrain::default();
+        // A plane sloping straight down into the sea at x = 8.
+        let bounds = Bounds {
+            min: (0, 0),
+            max: (16, 5),
+        };
+        let flow = Flow::new(
+            &terrain,
+            heights(bounds, |x, _| 7.5 - x as f32),
+            FlowMethod::D8,
+        );
+        let basins = Basins::new(&flow, 1.0);
+        assert_eq!(basins.basins.len(), 5 + 1);
+        for y in 0..5 {
+            let label = basins.labels[(y * 16) as usize];
+            let basin = &basins.basins[label as usize - 1];
+            assert_eq!(basin.outlet, (7, y));
+            assert_eq!(basin.cells, 8);
+            for x in 0..8 {
+                assert_eq!(basins.labels[(y * 16 + x) as usize], label);
+            }
+        }
+    }
+
+    // Ground at 2 with a deeper cell in the middle, walled in at 5 but for a notch at 3 on the
+    // east edge.
+    fn pit() -> (Terrain, Region) {
+        let terrain = Terrain {
+            sea_level: -10.0,
+            ..Default::default()
+        };
+        let bounds = Bounds {
+            min: (0, 0),
+            max: (7, 7),
+        };
+        let pit = heights(bounds, |x, y| match (x, y) {
+            (6, 3) => 3.0,
+            (3, 3) => 1.0,
+            (0 | 6, _) | (_, 0 | 6) => 5.0,
+            _ => 2.0,
+        });
+        (terrain, pit)
+    }
+
+    #[test]
+    fn fill_slopes_down_to_the_spill_point() {
+        let (terrain, pit) = pit();
+        let filled = fill(&pit, terrain.sea_level, 0.01);
+        for y in 1..6 {
+            for x in 1..6 {
+                let steps = (6 - x).max((3i32 - y).abs()) as f32;
+                let expected = 3.0 + steps * 0.01;
+                assert!((filled.get((x, y)).unwrap() - expected).abs() < 1e-5);
+            }
+        }
+        // The walls and the notch stay as they are.
+        assert_eq!(filled.get((6, 3)), Some(3.0));
+        assert_eq!(filled.get((0, 0)), Some(5.0));
+    }
+
+    #[test]
+    fn lake_fills_the_pit_to_its_outlet() {
+        let (terrain, pit) = pit();
+        let lakes = Lakes::new(&terrain, &pit);
+        assert_eq!(lakes.lakes.len(), 1);
+        let lake = &lakes.lakes[0];
+        assert_eq!(lake.spill_level, 3.0);
+        assert_eq!(lake.outlet, (6, 3));
+        assert_eq!(lake.cells, 25);
+        assert_eq!(lake.max_depth, 2.0);
+        assert_eq!(lake.volume, 26.0);
+        assert_eq!(lakes.depth.get((3, 3)), Some(2.0));
+        assert_eq!(lakes.depth.get((6, 3)), Some(0.0));
+    }
+}
@@ -0,0 +1,256 @@
+use crate::petra::contours::{self, ContourSettings};
+use crate::petra::sampler::{Interpolation, Sampler};
+use crate::petra::terrain::{Bounds, Terrain};
+use bevy::math::{vec2, vec3, Vec2, Vec3};
+use image::{Rgb, RgbImage};
+use rayon::prelude::*;
+
+// Heights above sea level as a fraction of the highest point, and their colours.
+const LAND_RAMP: [(f32, [f32; 3]); 6] = [
+    (0.0, [112.0, 160.0, 96.0]),
+    (0.15, [150.0, 186.0, 112.0]),
+    (0.35, [222.0, 214.0, 140.0]),
+    (0.6, [176.0, 136.0, 96.0]),
+    (0.85, [150.0, 146.0, 140.0]),
+    (1.0, [250.0, 250.0, 250.0]),
+];
+// Depths below sea level as a fraction of the deepest point.
+const SEA_RAMP: [(f32, [f32; 3]); 2] = [(0.0, [150.0, 198.0, 230.0]), (1.0, [30.0, 70.0, 130.0])];
+const GREY: [f32; 3] = [200.0, 200.0, 200.0];
+const CONTOUR: [f32; 3] = [110.0, 70.0, 40.0];
+
+// How the map looks. The sun comes from the azimuth, in compass degrees clockwise from north
+// (-z), at the altitude in degrees above the horizon.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MapStyle {
+    pub azimuth: f32,
+    pub altitude: f32,
+    // Blends four suns 45° apart, from 90° anticlockwise of the azimuth to 45° clockwise of it
+    // like GDAL, each one lighting the slopes that face across it, so no slope is lost in shadow.
+    pub multidirectional: bool,
+    // Vertical exaggeration of the shading.
+    pub exaggeration: f32,
+    // How much the shading darkens and lightens the colours, 0 to 1.
+    pub shading: f32,
+    pub hypsometric: bool,
+    pub sea_tint: bool,
+    pub contours: Option<ContourSettings>,
+    // Pixels per cell.
+    pub scale: u32,
+}
+
+impl Default for MapStyle {
+    fn default() -> Self {
+        Self {
+            azimuth: 315.0,
+            altitude: 45.0,
+            multidirectional: false,
+            exaggeration: 1.0,
+            shading: 0.8,
+            hypsometric: true,
+            sea_tint: true,
+            contours: None,
+            scale: 1,
+        }
+    }
+}
+
+fn ramp<const N: usize>(stops: &[(f32, [f32; 3]); N], t: f32) -> [f32; 3] {
+    let t = t.clamp(0.0, 1.0);
+    let i = stops
+        .iter()
+        .rposition(|(start, _)| *start <= t)
+        .unwrap_or(0);
+    if i + 1 == N {
+        return stops[i].1;
+    }
+    let ((start, from), (end, to)) = (stops[i], stops[i + 1]);
+    let f = (t - start) / (end - start);
+    [0, 1, 2].map(|c| from[c] + (to[c] - from[c]) * f)
+}
+
+// Light from a sun at the azimuth and altitude on a surface with the normal, 0 to 1.
+fn illumination(normal: Vec3, azimuth: f32, altitude: f32) -> f32 {
+    let (azimuth, altitude) = (azimuth.to_radians(), altitude.to_radians());
+    // Normals are x, z and up, and north is -z.
+    let sun = vec3(
+        azimuth.sin() * altitude.cos(),
+        -azimuth.cos() * altitude.cos(),
+        altitude.sin(),
+    );
+    normal.dot(sun).max(0.0)
+}
+
+// The four suns of the multidirectional shading, as (azimuth, weight). After Mark (1992): each sun
+// is weighted by how square it is to the slope, sin² of the angle between the sun and the aspect,
+// and the weights of the four add up to 2.
+fn suns(gradient: Vec2, azimuth: f32) -> [(f32, f32); 4] {
+    let aspect = (-gradient.x).atan2(gradient.y).to_degrees();
+    let flat = gradient.length_squared() < 1e-12;
+    [0, 1, 2, 3].map(|step| {
+        let azimuth = azimuth + 45.0 - 45.0 * step as f32;
+        let weight = if flat {
+            0.5
+        } else {
+            (aspect - azimuth).to_radians().sin().powi(2)
+        };
+        (azimuth, weight)
+    })
+}
+
+fn hillshade(gradient: Vec2, style: &MapStyle) -> f32 {
+    let normal = vec3(-gradient.x, -gradient.y, 1.0).normalize();
+    if !style.multidirectional {
+        return illumination(normal, style.azimuth, style.altitude);
+    }
+    suns(gradient, style.azimuth)
+        .iter()
+        .map(|(azimuth, weight)| weight * illumination(normal, *azimuth, style.altitude))
+        .sum::<f32>()
+        / 2.0
+}
+
+// Top-down map of the bounds, or of the whole terrain, with north at the top. Pixel (0, 0) is the
+// middle of the first cell.
+pub fn render(terrain: &Terrain, style: &MapStyle, bounds: Option<Bounds>) -> Option<RgbImage> {
+    let bounds = bounds.or_else(|| terrain.data.bounds())?;
+    let scale = style.scale.max(1);
+    let (width, height) = (
+        bounds.width() as u32 * scale,
+        bounds.height() as u32 * scale,
+    );
+    // Bilinear gradients are one-sided right at a cell, which shows up as streaks in the shading,
+    // so the map always uses a smooth kernel.
+    let interpolation = match terrain.interpolation {
+        Interpolation::Nearest | Interpolation::Bilinear => Interpolation::CatmullRom,
+        interpolation => interpolation,
+    };
+    let heights = terrain.data.region(bounds);
+    let (lowest, highest) = heights
+        .data
+        .iter()
+        .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), height| {
+            (min.min(*height), max.max(*height))
+        });
+    let sea_level = terrain.sea_level;
+    let flat = hillshade(Vec2::ZERO, style).max(1e-3);
+
+    let mut img = RgbImage::new(width, height);
+    img.par_chunks_mut(width as usize * 3)
+        .enumerate()
+        .for_each(|(row, pixels)| {
+            let sampler = Sampler::new(&terrain.data, terrain.edge_policy, interpolation);
+            let y = bounds.min.1 as f32 + row as f32 / scale as f32;
+            for (column, pixel) in pixels.chunks_mut(3).enumerate() {
+                let position = vec2(bounds.min.0 as f32 + column as f32 / scale as f32, y);
+                let nearest = (position.x.round() as i32, position.y.round() as i32);
+                let height = sampler
+                    .sample(position)
+                    .or_else(|| sampler.get(nearest))
+                    .unwrap_or(0.0);
+                let gradient = sampler.gradient(position).unwrap_or(Vec2::ZERO)
+                    * style.exaggeration
+                    / terrain.cell_size;
+
+                let sea = style.sea_tint && height <= sea_level;
+                let colour = if sea {
+                    ramp(
+                        &SEA_RAMP,
+                        (sea_level - height) / (sea_level - lowest).max(1e-6),
+                    )
+                } else if style.hypsometric {
+                    ramp(
+                        &LAND_RAMP,
+                        (height - sea_level) / (highest - sea_level).max(1e-6),
+                    )
+                } else {
+                    GREY
+                };
+                // Flat ground keeps its colour, and the sea only shows a hint of the seabed.
+                let shading = if sea {
+                    style.shading * 0.25
+                } else {
+                    style.shading
+                };
+                let light = 1.0 + shading * ((hillshade(gradient, style) / flat).min(1.5) - 1.0);
+                for c in 0..3 {
+                    pixel[c] = (colour[c] * light).clamp(0.0, 255.0) as u8;
+                }
+            }
+        });
+
+    if let Some(settings) = &style.contours {
+        for contour in contours::contours(terrain, settings, Some(bounds)) {
+            let opacity = if contour.index { 0.8 } else { 0.45 };
+            // From world units to pixels.
+            let points: Vec<Vec2> = contour
+                .points
+                .iter()
+                .map(|point| {
+                    (*point / terrain.cell_size - vec2(bounds.min.0 as f32, bounds.min.1 as f32))
+                        * scale as f32
+                })
+                .collect();
+            for pair in points.windows(2) {
+                draw_line(&mut img, pair[0], pair[1], opacity);
+            }
+        }
+    }
+    Some(img)
+}
+
+// Blends the contour colour along a line, a pixel wide.
+fn draw_line(img: &mut RgbImage, from: Vec2, to: Vec2, opacity: f32) {
+    let steps = (from.distance(to) * 2.0).ceil().max(1.0) as usize;
+    let mut last = None;
+    for step in 0..=steps {
+        let point = from.lerp(to, step as f32 / steps as f32);
+        let (x, y) = (point.x.round(), point.y.round());
+        if x < 0.0 || y < 0.0 || x >= img.width() as f32 || y >= img.height() as f32 {
+            continue;
+        }
+        let pixel = (x as u32, y as u32);
+        // Half-pixel steps land on the same pixel twice.
+        if last == Some(pixel) {
+            continue;
+        }
+        last = Some(pixel);
+        let Rgb(colour) = img.get_pixel(pixel.0, pixel.1);
+        let blended =
+            [0, 1, 2].map(|c| (colour[c] as f32 + (CONTOUR[c] - colour[c] as f32) * opacity) as u8);
+        img.put_pixel(pixel.0, pixel.1, Rgb(blended));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slopes_facing_the_sun_are_brighter() {
+        for multidirectional in [false, true] {
+            let style = MapStyle {
+                multidirectional,
+                ..Default::default()
+            };
+            // The sun is in the north-west, so this slope falls away towards it.
+            let facing = hillshade(vec2(0.5, 0.5), &style);
+            let away = hillshade(vec2(-0.5, -0.5), &style);
+            let flat = hillshade(Vec2::ZERO, &style);
+            assert!(facing > flat && flat > away, "{}", multidirectional);
+        }
+    }
+
+    #[test]
+    fn sun_weights_add_up_to_two() {
+        for gradient in [Vec2::ZERO, vec2(1.0, 0.0), vec2(0.3, -0.7), vec2(-2.0, 0.5)] {
+            for azimuth in [0.0, 90.0, 200.0, 315.0] {
+                let sum: f32 = suns(gradient, azimuth)
+                    .iter()
+                    .map(|(_, weight)| weight)
+                    .sum();
+                assert!((sum - 2.0).abs() < 1e-5, "{} at {}", gradient, azimuth);
+            }
+        }
+    }
+}
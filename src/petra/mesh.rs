@@ -1,12 +1,15 @@
-use std::borrow::Cow;
-
+use crate::petra::sampler::{EdgePolicy, Interpolation, Sampler};
+use crate::petra::simplify::Rtin;
 use crate::petra::terrain::*;
 use bevy::math::vec3;
 use bevy::prelude::*;
 use bevy::render::mesh::MeshVertexAttribute;
 use bevy::render::render_resource::{PrimitiveTopology, VertexFormat};
+use bevy::tasks::{AsyncComputeTaskPool, Task};
 use bevy::{math::vec2, render::mesh::Mesh};
 use bevy_mod_picking::PickableBundle;
+use futures_lite::future;
+use std::collections::{HashMap, HashSet};
 
 use super::material::TerrainMaterial;
 
@@ -15,70 +18,380 @@ pub const ATTRIBUTE_REAL_POSITION: MeshVertexAttribute =
 
 #[derive(Component)]
 pub struct ChunkComponent((i32, i32));
+
+// Meshes being built in the background for a chunk. Every time the chunk is modified or changes
+// LOD it gets a new revision and a new task, and a finished mesh only replaces the one on screen
+// if it is newer.
+#[derive(Component, Default)]
+struct PendingMeshes {
+    step: usize, // LOD of the newest task.
+    revision: u64,
+    shown: Option<u64>,
+    tasks: Vec<(u64, Task<Mesh>)>,
+    // Whether the terrain had data for the chunk when it was last meshed, so a chunk that
+    // disappears (after a crop, say) gets meshed flat again.
+    had_data: bool,
+}
+
+impl PendingMeshes {
+    fn spawn(&mut self, pool: &AsyncComputeTaskPool, snapshot: ChunkSnapshot) {
+        self.revision += 1;
+        self.step = snapshot.step;
+        let task = pool.spawn(async move { snapshot.generate_mesh() });
+        self.tasks.push((self.revision, task));
+    }
+
+    // Returns the newest mesh that finished since the last call, if it is newer than the one shown.
+    // Tasks older than what is shown are stale and get dropped, which cancels them.
+    fn poll(&mut self) -> Option<Mesh> {
+        let mut newest: Option<(u64, Mesh)> = None;
+        let mut i = 0;
+        while i < self.tasks.len() {
+            if let Some(mesh) = future::block_on(future::poll_once(&mut self.tasks[i].1)) {
+                let revision = self.tasks.remove(i).0;
+                if newest
+                    .as_ref()
+                    .map_or(true, |(newest, _)| revision > *newest)
+                {
+                    newest = Some((revision, mesh));
+                }
+            } else {
+                i += 1;
+            }
+        }
+        let (revision, mesh) = newest.filter(|(revision, _)| Some(*revision) > self.shown)?;
+        self.shown = Some(revision);
+        self.tasks.retain(|(pending, _)| *pending > revision);
+        Some(mesh)
+    }
+}
+
+// Copy of everything generate_mesh reads for a chunk: the chunk and its eight neighbours with the
+// edge policy already applied, so the mesh can be built off the main thread.
+pub struct ChunkSnapshot {
+    data: TerrainData,
+    policy: EdgePolicy,
+    interpolation: Interpolation,
+    bounds: Option<Bounds>,
+    chunk_coordinates: (i32, i32),
+    step: usize,
+    max_error: f32,
+    cell_size: f32,
+}
+
+impl ChunkSnapshot {
+    pub fn new(terrain: &Terrain, chunk_coordinates: (i32, i32), step: usize) -> Self {
+        let sampler = Sampler::for_terrain(terrain);
+        let bounds = terrain.data.bounds();
+        let chunk_size = terrain.data.chunk_size;
+        let mut data = TerrainData::zeros(chunk_size);
+        for y in chunk_coordinates.1 - 1..=chunk_coordinates.1 + 1 {
+            for x in chunk_coordinates.0 - 1..=chunk_coordinates.0 + 1 {
+                let chunk_bounds = Bounds::of_chunk((x, y), chunk_size);
+                let inside = bounds.and_then(|bounds| bounds.intersection(&chunk_bounds))
+                    == Some(chunk_bounds);
+                match terrain.edge_policy {
+                    EdgePolicy::Clamp | EdgePolicy::Wrap if !inside => {
+                        // These read cells from elsewhere in the world, so bake them into the copy.
+                        let mut chunk = TerrainDataChunk::new((x, y), chunk_size);
+                        let size = chunk_size as i32;
+                        for (i, value) in chunk.data.iter_mut().enumerate() {
+                            let cell = (
+                                chunk_bounds.min.0 + i as i32 % size,
+                                chunk_bounds.min.1 + i as i32 / size,
+                            );
+                            *value = sampler.get_or(cell, 0.0);
+                        }
+                        data.chunks.insert((x, y), chunk);
+                    }
+                    _ => {
+                        if let Some(chunk) = terrain.data.chunks.get(&(x, y)) {
+                            data.chunks.insert((x, y), chunk.clone());
+                        }
+                    }
+                }
+            }
+        }
+        Self {
+            data,
+            // Clamped and wrapped cells are already in the copy, and everything else reads as is.
+            // Only EdgePolicy::None still has to know where the world ends.
+            policy: match terrain.edge_policy {
+                EdgePolicy::None => EdgePolicy::None,
+                _ => EdgePolicy::Zero,
+            },
+            interpolation: terrain.interpolation,
+            bounds,
+            chunk_coordinates,
+            step,
+            max_error: terrain.mesh_error,
+            cell_size: terrain.cell_size,
+        }
+    }
+
+    pub fn generate_mesh(&self) -> Mesh {
+        let sampler =
+            Sampler::with_bounds(&self.data, self.policy, self.interpolation, self.bounds);
+        let coarsest = *LOD_STEPS.last().unwrap() as f32;
+        let chunk_size = self.data.chunk_size;
+        if self.max_error > 0.0 {
+            // Distant chunks may be off by more, in proportion to how much a grid would be decimated.
+            // Neighbours are at most as far off as the coarsest level, so that's what the skirt covers.
+            mesh_from_sampler(
+                &sampler,
+                self.chunk_coordinates,
+                (chunk_size, self.cell_size),
+                MeshDetail::Simplified(self.max_error * self.step as f32),
+                Some(2.0 * self.max_error * coarsest + 1.0),
+            )
+        } else {
+            let skirt = if self.step > 1 {
+                Some(skirt_depth(
+                    &sampler,
+                    Bounds::of_chunk(self.chunk_coordinates, chunk_size).min,
+                    chunk_size,
+                ))
+            } else {
+                None
+            };
+            mesh_from_sampler(
+                &sampler,
+                self.chunk_coordinates,
+                (chunk_size, self.cell_size),
+                MeshDetail::Grid(self.step),
+                skirt,
+            )
+        }
+    }
+}
 pub struct TerrainMeshPlugin;
 impl Plugin for TerrainMeshPlugin {
     fn build(&self, app: &mut App) {
+        app.init_resource::<RenderSettings>();
         app.add_system(chunk_mesh_system.system());
     }
 }
 
-const RENDER_DISTANCE: i32 = 3;
+// Which chunks get meshes: the ones the camera can see, plus a margin so panning doesn't show
+// holes, but never more than the render distance away from the camera's focus point.
+pub struct RenderSettings {
+    pub distance: i32, // In chunks.
+    pub margin: i32,   // In chunks.
+}
 
-fn find_ray_ground_intersection(pos: Vec3, dir: Vec3) -> Vec2 {
-    vec2(
-        dir.x * (pos.y / dir.y) + pos.x,
-        dir.z * (pos.y / dir.y) + pos.z,
-    )
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            distance: 16,
+            margin: 1,
+        }
+    }
+}
+
+fn find_ray_ground_intersection(pos: Vec3, dir: Vec3, ground: f32) -> Option<Vec2> {
+    if dir.y >= 0.0 {
+        return None;
+    }
+    let t = (ground - pos.y) / dir.y;
+    Some(vec2(pos.x + dir.x * t, pos.z + dir.z * t))
+}
+
+// Outline of the ground the orthographic camera can see, as a convex polygon in world x and z.
+// The corners of the view are cast onto the lowest and highest ground the generator makes, so
+// terrain that sticks up into the view from outside of the footprint at height 0 still counts.
+// None if part of the view never hits the ground.
+fn visible_footprint(
+    transform: &Transform,
+    projection: &OrthographicProjection,
+    height: f32,
+) -> Option<Vec<Vec2>> {
+    let mut points = Vec::with_capacity(8);
+    for x in [projection.left, projection.right] {
+        for y in [projection.bottom, projection.top] {
+            let origin = transform.translation
+                + transform.right() * x * projection.scale
+                + transform.up() * y * projection.scale;
+            for ground in [-height, height] {
+                points.push(find_ray_ground_intersection(
+                    origin,
+                    transform.forward(),
+                    ground,
+                )?);
+            }
+        }
+    }
+    Some(convex_hull(points))
+}
+
+// Andrew's monotone chain. The hull comes out counter-clockwise in x and z.
+fn convex_hull(mut points: Vec<Vec2>) -> Vec<Vec2> {
+    points.sort_by(|a, b| a.x.total_cmp(&b.x).then(a.y.total_cmp(&b.y)));
+    let turn = |a: Vec2, b: Vec2, c: Vec2| (b - a).perp_dot(c - a);
+    let mut hull: Vec<Vec2> = Vec::with_capacity(points.len() + 1);
+    for pass in 0..2 {
+        let start = hull.len();
+        for &point in points.iter() {
+            while hull.len() >= start + 2
+                && turn(hull[hull.len() - 2], hull[hull.len() - 1], point) <= 0.0
+            {
+                hull.pop();
+            }
+            hull.push(point);
+        }
+        hull.pop();
+        if pass == 0 {
+            points.reverse();
+        }
+    }
+    hull
+}
+
+// Separating axis test between a convex polygon and a rectangle.
+fn polygon_overlaps_rectangle(polygon: &[Vec2], min: Vec2, max: Vec2) -> bool {
+    let corners = [min, vec2(max.x, min.y), max, vec2(min.x, max.y)];
+    let separated = |axis: Vec2| {
+        let range = |points: &mut dyn Iterator<Item = &Vec2>| {
+            points.fold((f32::MAX, f32::MIN), |(low, high), point| {
+                (low.min(point.dot(axis)), high.max(point.dot(axis)))
+            })
+        };
+        let (polygon_low, polygon_high) = range(&mut polygon.iter());
+        let (rectangle_low, rectangle_high) = range(&mut corners.iter());
+        polygon_high < rectangle_low || rectangle_high < polygon_low
+    };
+    let edges = (0..polygon.len()).map(|i| polygon[(i + 1) % polygon.len()] - polygon[i]);
+    !polygon.is_empty()
+        && ![Vec2::X, Vec2::Y]
+            .into_iter()
+            .chain(edges.map(|edge| edge.perp()))
+            .any(separated)
+}
+
+// Steps between the vertices of each LOD, and up to how many chunks away from the camera's
+// focus point each of them is used. Chunks further away than that use the last one.
+const LOD_STEPS: [usize; 4] = [1, 2, 4, 8];
+const LOD_DISTANCES: [i32; 3] = [2, 5, 9];
+
+fn lod_step(chunk_distance: i32) -> usize {
+    LOD_DISTANCES
+        .iter()
+        .zip(LOD_STEPS)
+        .find(|(distance, _)| chunk_distance <= **distance)
+        .map_or(*LOD_STEPS.last().unwrap(), |(_, step)| step)
 }
 
 fn chunk_mesh_system(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
-    chunks_query: Query<(Entity, &ChunkComponent, &Handle<Mesh>, &Transform)>,
-    camera_query: Query<(&Camera, &Transform)>,
+    mut chunks_query: Query<(
+        Entity,
+        &ChunkComponent,
+        Option<&Handle<Mesh>>,
+        &mut PendingMeshes,
+    )>,
+    camera_query: Query<(&Transform, &OrthographicProjection), With<Camera>>,
     mut terrain: ResMut<Terrain>,
+    settings: Res<RenderSettings>,
+    pool: Res<AsyncComputeTaskPool>,
+    mut last_layout: Local<Option<(usize, f32)>>,
 ) {
-    let camera_translation = camera_query.iter().next().unwrap().1.translation;
-    let camera_direction = camera_query
+    let (camera_transform, projection) = camera_query.iter().next().unwrap();
+    let looking_at = find_ray_ground_intersection(
+        camera_transform.translation,
+        camera_transform.forward(),
+        0.0,
+    )
+    .unwrap_or_else(|| {
+        vec2(
+            camera_transform.translation.x,
+            camera_transform.translation.z,
+        )
+    });
+    let looking_at = terrain.world_to_cells(looking_at);
+    let camera_chunk_coordinates = terrain
+        .data
+        .get_terrain_chunk_coordinates((looking_at.x.floor() as i32, looking_at.y.floor() as i32));
+    let footprint = visible_footprint(camera_transform, projection, terrain.height);
+
+    // Changing the chunk or cell size moves every chunk, so start over.
+    let layout = (terrain.data.chunk_size, terrain.cell_size);
+    if last_layout
+        .replace(layout)
+        .map_or(false, |last| last != layout)
+    {
+        for (entity, _, _, _) in chunks_query.iter() {
+            commands.entity(entity).despawn_recursive();
+        }
+        return;
+    }
+
+    let spawned: HashMap<(i32, i32), Entity> = chunks_query
         .iter()
-        .next()
-        .unwrap()
-        .1
-        .rotation
-        .mul_vec3(vec3(0.0, 1.0, 0.0));
-    let looking_at = find_ray_ground_intersection(camera_translation, camera_direction);
-    let camera_chunk_coordinates =
-        TerrainData::get_terrain_chunk_coordinates((looking_at.x as i32, looking_at.y as i32));
-
-    for x in (camera_chunk_coordinates.0 - RENDER_DISTANCE)
-        ..(camera_chunk_coordinates.0 + RENDER_DISTANCE + 1)
+        .map(|(entity, chunk, _, _)| (chunk.0, entity))
+        .collect();
+
+    // Size of a chunk in world units.
+    let size = terrain.data.chunk_size as f32 * terrain.cell_size;
+    let margin = settings.margin as f32 * size;
+    let mut visible = HashSet::new();
+    for x in (camera_chunk_coordinates.0 - settings.distance)
+        ..(camera_chunk_coordinates.0 + settings.distance + 1)
     {
-        for z in (camera_chunk_coordinates.1 - RENDER_DISTANCE)
-            ..(camera_chunk_coordinates.1 + RENDER_DISTANCE + 1)
+        for z in (camera_chunk_coordinates.1 - settings.distance)
+            ..(camera_chunk_coordinates.1 + settings.distance + 1)
         {
-            if let Some(e) = chunks_query.iter().find(|e| e.1 .0 == (x, z)) {
-                if let Some(chunk) = terrain.data.chunks.get(&(x, z)) {
-                    if chunk.modified {
-                        meshes.set(e.2, generate_mesh(&terrain, (x, z)));
+            // Without a footprint the camera sees the horizon, so everything in range is shown.
+            if let Some(footprint) = &footprint {
+                let min = vec2(x as f32 * size - margin, z as f32 * size - margin);
+                let max = vec2(
+                    (x + 1) as f32 * size + margin,
+                    (z + 1) as f32 * size + margin,
+                );
+                if !polygon_overlaps_rectangle(footprint, min, max) {
+                    continue;
+                }
+            }
+            visible.insert((x, z));
+            let step = lod_step(
+                (x - camera_chunk_coordinates.0)
+                    .abs()
+                    .max((z - camera_chunk_coordinates.1).abs()),
+            );
+            if let Some(&entity) = spawned.get(&(x, z)) {
+                let mut pending = chunks_query.get_mut(entity).unwrap().3;
+                let modified = terrain
+                    .data
+                    .chunks
+                    .get(&(x, z))
+                    .map_or(false, |chunk| chunk.modified);
+                let exists = terrain.data.chunks.contains_key(&(x, z));
+                if modified || pending.step != step || pending.had_data != exists {
+                    pending.had_data = exists;
+                    pending.spawn(&pool, ChunkSnapshot::new(&terrain, (x, z), step));
+                    if modified {
                         terrain.data.chunks.get_mut(&(x, z)).unwrap().modified = false;
                     }
                 }
             } else {
                 println!("Spawning mesh! {}, {}", x, z);
-                let terrain_mesh = generate_mesh(&terrain, (x, z));
-                let terrain_mesh_handle = meshes.add(terrain_mesh);
+                // The mesh handle is only added once the first mesh is ready.
+                let mut pending = PendingMeshes {
+                    had_data: terrain.data.chunks.contains_key(&(x, z)),
+                    ..Default::default()
+                };
+                pending.spawn(&pool, ChunkSnapshot::new(&terrain, (x, z), step));
                 commands
                     .spawn_bundle((
-                        terrain_mesh_handle,
                         Transform::from_translation(vec3(
-                            (x * TerrainDataChunk::size as i32) as f32,
+                            x as f32 * size,
                             0.0,
-                            (z * TerrainDataChunk::size as i32) as f32, //inverted
+                            z as f32 * size, //inverted
                         )),
                         GlobalTransform::default(),
                         TerrainMaterial,
                         Visibility::default(),
                         ComputedVisibility::default(),
+                        pending,
                     ))
                     .insert_bundle(PickableBundle::default())
                     .insert(ChunkComponent((x, z)));
@@ -86,149 +399,198 @@ fn chunk_mesh_system(
         }
     }
 
-    for i in chunks_query.iter() {
-        if (i.1 .0 .0 - camera_chunk_coordinates.0).abs() > RENDER_DISTANCE
-            || (i.1 .0 .1 - camera_chunk_coordinates.1).abs() > RENDER_DISTANCE
-        {
+    for (entity, _, handle, mut pending) in chunks_query.iter_mut() {
+        if let Some(mesh) = pending.poll() {
+            match handle {
+                Some(handle) => meshes.set_untracked(handle, mesh),
+                None => {
+                    commands.entity(entity).insert(meshes.add(mesh));
+                }
+            }
+        }
+    }
+
+    for (entity, chunk, _, _) in chunks_query.iter() {
+        if !visible.contains(&chunk.0) {
             println!("Deleting mesh!");
-            commands.entity(i.0).despawn_recursive();
+            commands.entity(entity).despawn_recursive();
         }
     }
 }
 
 pub fn generate_mesh(terrain: &Terrain, chunk_coordinates: (i32, i32)) -> Mesh {
-    let mut positions: Vec<[f32; 3]> =
-        Vec::with_capacity((TerrainDataChunk::size + 1) * (TerrainDataChunk::size + 1));
-    let mut real_positions: Vec<[f32; 2]> =
-        Vec::with_capacity((TerrainDataChunk::size + 1) * (TerrainDataChunk::size + 1));
-    let mut normals: Vec<[f32; 3]> =
-        Vec::with_capacity((TerrainDataChunk::size + 1) * (TerrainDataChunk::size + 1));
-    let mut uvs: Vec<[f32; 2]> =
-        Vec::with_capacity((TerrainDataChunk::size + 1) * (TerrainDataChunk::size + 1));
-    let mut indices: Vec<u32> =
-        Vec::with_capacity((TerrainDataChunk::size + 1) * (TerrainDataChunk::size + 1) * 6);
-    let mut index: usize = 0;
+    mesh_from_sampler(
+        &Sampler::for_terrain(terrain),
+        chunk_coordinates,
+        (terrain.data.chunk_size, terrain.cell_size),
+        MeshDetail::Grid(1),
+        None,
+    )
+}
+
+// Mesh of a chunk simplified to within max_error of the heights. Chunks simplified with the same
+// error can still differ along their shared border, which the skirt covers.
+pub fn generate_simplified_mesh(
+    terrain: &Terrain,
+    chunk_coordinates: (i32, i32),
+    max_error: f32,
+) -> Mesh {
+    mesh_from_sampler(
+        &Sampler::for_terrain(terrain),
+        chunk_coordinates,
+        (terrain.data.chunk_size, terrain.cell_size),
+        MeshDetail::Simplified(max_error),
+        Some(2.0 * max_error + 1.0),
+    )
+}
+
+// Mesh of the part of a chunk within the bounds, simplified to within max_error of the heights and
+// keeping every cell along its border. Like a whole chunk's mesh, it reaches the first cell past
+// the bounds. Meshes made this way share their border vertices, so they join without a skirt.
+pub fn generate_seamless_mesh(
+    terrain: &Terrain,
+    chunk_coordinates: (i32, i32),
+    max_error: f32,
+    bounds: Bounds,
+) -> Mesh {
+    let chunk_size = terrain.data.chunk_size;
+    let origin = Bounds::of_chunk(chunk_coordinates, chunk_size).min;
+    let corner = |cell: (i32, i32)| {
+        (
+            (cell.0 - origin.0).clamp(0, chunk_size as i32) as usize,
+            (cell.1 - origin.1).clamp(0, chunk_size as i32) as usize,
+        )
+    };
+    mesh_from_sampler(
+        &Sampler::for_terrain(terrain),
+        chunk_coordinates,
+        (chunk_size, terrain.cell_size),
+        MeshDetail::Seamless(max_error, [corner(bounds.min), corner(bounds.max)]),
+        None,
+    )
+}
 
+// Which vertices of a chunk end up in its mesh.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MeshDetail {
+    Grid(usize),     // Every n-th cell, so 1 is full resolution.
+    Simplified(f32), // As few as possible while staying within this error, see simplify::Rtin.
+    // Simplified inside, every cell along the border, cut to the rectangle between two corners.
+    Seamless(f32, [(usize, usize); 2]),
+}
+
+// Builds the mesh of a chunk, given the chunk size in cells and the cell size in world units.
+// Positions are relative to the chunk's corner and real positions are in the world, both in world
+// units. With a skirt depth, a skirt hangs down from the border of the mesh to hide the cracks to
+// neighbours with different detail.
+fn mesh_from_sampler(
+    sampler: &Sampler,
+    chunk_coordinates: (i32, i32),
+    (chunk_size, cell_size): (usize, f32),
+    detail: MeshDetail,
+    skirt: Option<f32>,
+) -> Mesh {
+    let side = chunk_size + 1;
     let chunk_real_coordinates = (
-        chunk_coordinates.0 * TerrainDataChunk::size as i32,
-        chunk_coordinates.1 * TerrainDataChunk::size as i32,
+        chunk_coordinates.0 * chunk_size as i32,
+        chunk_coordinates.1 * chunk_size as i32,
     );
 
-    let _empty_chunk = TerrainDataChunk::new(chunk_coordinates);
-    let main_chunk: Cow<TerrainDataChunk> =
-        if let Some(i) = terrain.data.chunks.get(&chunk_coordinates) {
-            Cow::Borrowed(i)
-        } else {
-            Cow::Owned(TerrainDataChunk::new(chunk_coordinates))
-        };
-    let right_chunk: Cow<TerrainDataChunk> = if let Some(i) = terrain
-        .data
-        .chunks
-        .get(&(chunk_coordinates.0 + 1, chunk_coordinates.1))
-    {
-        Cow::Borrowed(i)
-    } else {
-        Cow::Owned(TerrainDataChunk::new((
-            chunk_coordinates.0 + 1,
-            chunk_coordinates.1,
-        )))
-    };
-    let bottom_chunk: Cow<TerrainDataChunk> = if let Some(i) = terrain
-        .data
-        .chunks
-        .get(&(chunk_coordinates.0, chunk_coordinates.1 + 1))
-    {
-        Cow::Borrowed(i)
-    } else {
-        Cow::Owned(TerrainDataChunk::new((
-            chunk_coordinates.0,
-            chunk_coordinates.1 + 1,
-        )))
-    };
-    let bottom_right_value = terrain
-        .data
-        .chunks
-        .get(&(chunk_coordinates.0 + 1, chunk_coordinates.1 + 1))
-        .and_then(|c| Some(c.data[0]))
-        .unwrap_or(0.0);
-
-    for x in 0..TerrainDataChunk::size + 1 {
-        for z in 0..TerrainDataChunk::size + 1 {
-            if x == TerrainDataChunk::size {
-                if z == TerrainDataChunk::size {
-                    positions.push([x as f32, bottom_right_value, z as f32]);
-                } else {
-                    positions.push([
-                        x as f32,
-                        right_chunk.data[z * TerrainDataChunk::size],
-                        z as f32,
-                    ]);
-                }
-            } else {
-                if z == TerrainDataChunk::size {
-                    positions.push([x as f32, bottom_chunk.data[x], z as f32]);
-                } else {
-                    positions.push([
-                        x as f32,
-                        main_chunk.data[z * TerrainDataChunk::size + x],
-                        z as f32,
-                    ]);
+    // Reads past the edge of the world follow the sampler's edge policy. Under EdgePolicy::None
+    // there is nothing there, so positions fall back to 0 and normals to flat.
+    let mut heights = Vec::with_capacity(side * side);
+    for z in 0..side {
+        for x in 0..side {
+            heights.push(sampler.get_or(
+                (
+                    x as i32 + chunk_real_coordinates.0,
+                    z as i32 + chunk_real_coordinates.1,
+                ),
+                0.0,
+            ));
+        }
+    }
+
+    let (cells, mut indices) = match detail {
+        MeshDetail::Grid(step) => {
+            let mut cells = Vec::new();
+            for x in (0..side).step_by(step) {
+                for z in (0..side).step_by(step) {
+                    cells.push((x, z));
                 }
             }
+            let vertices_per_side = chunk_size / step + 1;
+            (cells, grid_indices(vertices_per_side, vertices_per_side))
+        }
+        MeshDetail::Simplified(max_error) => Rtin::new(&heights, side).mesh(max_error),
+        MeshDetail::Seamless(max_error, rectangle) => {
+            Rtin::with_rectangle(&heights, side, rectangle).mesh(max_error)
+        }
+    };
 
-            real_positions.push([
-                (x as i32 + (main_chunk.coords.0 * TerrainDataChunk::size as i32)) as f32,
-                (z as i32 + (main_chunk.coords.1 * TerrainDataChunk::size as i32)) as f32,
-            ]);
+    let mut positions: Vec<[f32; 3]> = Vec::with_capacity(cells.len());
+    let mut real_positions: Vec<[f32; 2]> = Vec::with_capacity(cells.len());
+    let mut normals: Vec<[f32; 3]> = Vec::with_capacity(cells.len());
+    let mut uvs: Vec<[f32; 2]> = Vec::with_capacity(cells.len());
+    for &(x, z) in &cells {
+        let real_x = x as i32 + chunk_real_coordinates.0;
+        let real_z = z as i32 + chunk_real_coordinates.1;
+        positions.push([
+            x as f32 * cell_size,
+            heights[z * side + x],
+            z as f32 * cell_size,
+        ]);
+        real_positions.push([real_x as f32 * cell_size, real_z as f32 * cell_size]);
+        normals.push(terrain_normal(sampler, (real_x, real_z), cell_size).into());
+        uvs.push([x as f32, z as f32]);
+    }
 
-            // Calculate normals
-            let up = terrain.data[(
-                x as i32 + chunk_real_coordinates.0,
-                z as i32 - 1 + chunk_real_coordinates.1,
-            )];
-
-            let upright = terrain.data[(
-                x as i32 + 1 + chunk_real_coordinates.0,
-                z as i32 - 1 + chunk_real_coordinates.1,
-            )];
-            let right = terrain.data[(
-                x as i32 + 1 + chunk_real_coordinates.0,
-                z as i32 + chunk_real_coordinates.1,
-            )];
-            let down = terrain.data[(
-                x as i32 + chunk_real_coordinates.0,
-                z as i32 + 1 + chunk_real_coordinates.1,
-            )];
-            let downleft = terrain.data[(
-                x as i32 - 1 + chunk_real_coordinates.0,
-                z as i32 + 1 + chunk_real_coordinates.1,
-            )];
-            let left = terrain.data[(
-                x as i32 - 1 + chunk_real_coordinates.0,
-                z as i32 + chunk_real_coordinates.1,
-            )];
-
-            let normal = vec3(
-                2.0 * (left - right) - upright + downleft + up - down,
-                2.0 * (down - up) + upright + downleft - up - left,
-                6.0,
-            );
-            let normal_normalized = normal.normalize();
-
-            normals.push(normal_normalized.into());
-            uvs.push([x as f32, z as f32]);
-            if x != TerrainDataChunk::size && z != TerrainDataChunk::size {
-                indices.append(&mut vec![
-                    index as u32,
-                    (index + 1) as u32,
-                    (index + TerrainDataChunk::size + 1) as u32, //First triangle. Bevy expects u32.
-                    (index + TerrainDataChunk::size + 2) as u32,
-                    (index + TerrainDataChunk::size + 1) as u32,
-                    (index + 1) as u32, //Second triangle
-                ]);
-            }
+    if let Some(depth) = skirt {
+        // Walk around the border, so consecutive entries share a skirt quad.
+        let last = chunk_size;
+        let mut border = cells
+            .iter()
+            .enumerate()
+            .filter_map(|(i, &(x, z))| {
+                let around = match (x, z) {
+                    (x, 0) if x < last => x,
+                    (x, z) if x == last && z < last => last + z,
+                    (x, z) if z == last && x > 0 => 3 * last - x,
+                    (0, z) => 4 * last - z,
+                    _ => return None,
+                };
+                Some((around, i))
+            })
+            .collect::<Vec<_>>();
+        border.sort_unstable();
+        border.push(border[0]);
 
-            index += 1;
+        let skirt_start = positions.len();
+        for &(_, top) in &border {
+            let [x, height, z] = positions[top];
+            positions.push([x, height - depth, z]);
+            real_positions.push(real_positions[top]);
+            normals.push(normals[top]);
+            uvs.push(uvs[top]);
+        }
+        for i in 0..border.len() - 1 {
+            let (top, next_top) = (border[i].1 as u32, border[i + 1].1 as u32);
+            let (bottom, next_bottom) = ((skirt_start + i) as u32, (skirt_start + i + 1) as u32);
+            // Both windings, so the skirt shows from whichever side the gap is seen.
+            indices.extend_from_slice(&[
+                top,
+                bottom,
+                next_top,
+                next_top,
+                bottom,
+                next_bottom,
+                top,
+                next_top,
+                bottom,
+                next_top,
+                next_bottom,
+                bottom,
+            ]);
         }
     }
 
@@ -241,3 +603,259 @@ pub fn generate_mesh(terrain: &Terrain, chunk_coordinates: (i32, i32)) -> Mesh {
 
     mesh
 }
+
+// Triangles of a regular grid of vertices, stored column by column: the vertex at (x, z) has index
+// x * rows + z.
+pub fn grid_indices(columns: usize, rows: usize) -> Vec<u32> {
+    let mut indices: Vec<u32> = Vec::with_capacity(columns * rows * 6);
+    for x in 0..columns - 1 {
+        for z in 0..rows - 1 {
+            let index = x * rows + z;
+            indices.append(&mut vec![
+                index as u32,
+                (index + 1) as u32,
+                (index + rows) as u32, //First triangle. Bevy expects u32.
+                (index + rows + 1) as u32,
+                (index + rows) as u32,
+                (index + 1) as u32, //Second triangle
+            ]);
+        }
+    }
+    indices
+}
+
+// How far a skirt has to reach down. Along a shared border, each chunk is at most as far from the
+// real heights as the coarsest LOD would be, so a gap can't be deeper than twice that.
+fn skirt_depth(sampler: &Sampler, chunk_real_coordinates: (i32, i32), chunk_size: usize) -> f32 {
+    let size = chunk_size as i32;
+    let height = |i: i32, edge: usize| {
+        let cell = match edge {
+            0 => (i, 0),
+            1 => (size, i),
+            2 => (i, size),
+            _ => (0, i),
+        };
+        sampler.get_or(
+            (
+                chunk_real_coordinates.0 + cell.0,
+                chunk_real_coordinates.1 + cell.1,
+            ),
+            0.0,
+        )
+    };
+    let mut error: f32 = 0.0;
+    for edge in 0..4 {
+        for step in LOD_STEPS.iter().map(|&step| step as i32) {
+            for start in (0..size).step_by(step as usize) {
+                let (a, b) = (height(start, edge), height(start + step, edge));
+                for i in 1..step {
+                    let t = i as f32 / step as f32;
+                    error = error.max((a + (b - a) * t - height(start + i, edge)).abs());
+                }
+            }
+        }
+    }
+    2.0 * error + 1.0
+}
+
+// Normal of the vertex at a cell, with cells cell_size world units apart. Like a tangent-space
+// normal map, z points up and y points towards -z in the world. The bicubic kernels have a smooth
+// analytic gradient at the cells, the others fall back to a six-tap kernel over the neighbours.
+pub fn terrain_normal(sampler: &Sampler, cell: (i32, i32), cell_size: f32) -> Vec3 {
+    let (real_x, real_z) = cell;
+    let height = sampler.get_or(cell, 0.0);
+    match sampler.interpolation() {
+        Interpolation::CatmullRom | Interpolation::BSpline => {
+            if let Some(gradient) = sampler.gradient(vec2(real_x as f32, real_z as f32)) {
+                return vec3(-gradient.x, gradient.y, cell_size).normalize();
+            }
+        }
+        Interpolation::Nearest | Interpolation::Bilinear => {}
+    }
+
+    // Neighbours past the edge of the world read as flat.
+    let up = sampler.get_or((real_x, real_z - 1), height);
+    let upright = sampler.get_or((real_x + 1, real_z - 1), height);
+    let right = sampler.get_or((real_x + 1, real_z), height);
+    let down = sampler.get_or((real_x, real_z + 1), height);
+    let downleft = sampler.get_or((real_x - 1, real_z + 1), height);
+    let left = sampler.get_or((real_x - 1, real_z), height);
+
+    let normal = vec3(
+        2.0 * (left - right) - upright + downleft + up - down,
+        2.0 * (down - up) + upright + downleft - up - left,
+        6.0 * cell_size,
+    );
+    normal.normalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::render::mesh::VertexAttributeValues;
+
+    // Heights and real positions of the mesh's vertices, the skirt's after the surface's.
+    fn vertices(mesh: &Mesh) -> Vec<(Vec2, f32)> {
+        let positions = match mesh.attribute(Mesh::ATTRIBUTE_POSITION) {
+            Some(VertexAttributeValues::Float32x3(positions)) => positions,
+            _ => panic!("no positions"),
+        };
+        let real_positions = match mesh.attribute(ATTRIBUTE_REAL_POSITION) {
+            Some(VertexAttributeValues::Float32x2(real_positions)) => real_positions,
+            _ => panic!("no real positions"),
+        };
+        real_positions
+            .iter()
+            .zip(positions)
+            .map(|(real, position)| (Vec2::from(*real), position[1]))
+            .collect()
+    }
+
+    #[test]
+    fn snapshots_mesh_like_the_terrain() {
+        for policy in EdgePolicy::ALL {
+            let mut terrain = Terrain {
+                data: TerrainData::zeros(16),
+                edge_policy: policy,
+                ..Default::default()
+            };
+            for z in 0..32 {
+                for x in 0..32 {
+                    terrain.data[(x, z)] = (x * 3 + z * 5 % 7) as f32 * 0.25;
+                }
+            }
+            // Chunks at the edge of the world read their neighbours through the edge policy.
+            for chunk in [(0, 0), (1, 1), (-1, 0)] {
+                let snapshot = ChunkSnapshot::new(&terrain, chunk, 1).generate_mesh();
+                assert_eq!(
+                    vertices(&snapshot),
+                    vertices(&generate_mesh(&terrain, chunk)),
+                    "{:?} at {:?}",
+                    policy,
+                    chunk
+                );
+            }
+        }
+    }
+
+    fn projection() -> OrthographicProjection {
+        OrthographicProjection {
+            left: -5.0,
+            right: 5.0,
+            bottom: -3.0,
+            top: 3.0,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn footprint_straight_down_is_the_view() {
+        let camera =
+            Transform::from_xyz(10.0, 100.0, 20.0).looking_at(vec3(10.0, 0.0, 20.0), -Vec3::Z);
+        let mut footprint = visible_footprint(&camera, &projection(), 64.0).unwrap();
+        footprint.sort_by(|a, b| a.x.total_cmp(&b.x).then(a.y.total_cmp(&b.y)));
+        let expected = [
+            vec2(5.0, 17.0),
+            vec2(5.0, 23.0),
+            vec2(15.0, 17.0),
+            vec2(15.0, 23.0),
+        ];
+        assert_eq!(footprint.len(), 4);
+        for (point, expected) in footprint.iter().zip(expected) {
+            assert!(
+                point.distance(expected) < 1e-3,
+                "{} against {}",
+                point,
+                expected
+            );
+        }
+        let (min, max) = (vec2(14.0, 22.0), vec2(30.0, 30.0));
+        assert!(polygon_overlaps_rectangle(&footprint, min, max));
+        let (min, max) = (vec2(16.0, 0.0), vec2(32.0, 16.0));
+        assert!(!polygon_overlaps_rectangle(&footprint, min, max));
+    }
+
+    #[test]
+    fn footprint_of_a_tilted_camera() {
+        // Rays are parallel, so looking at or above the horizon none of them reach the ground.
+        let level = Transform::from_xyz(0.0, 10.0, 0.0).looking_at(vec3(100.0, 10.0, 0.0), Vec3::Y);
+        assert_eq!(visible_footprint(&level, &projection(), 64.0), None);
+        let above = Transform::from_xyz(0.0, 10.0, 0.0).looking_at(vec3(100.0, 11.0, 0.0), Vec3::Y);
+        assert_eq!(visible_footprint(&above, &projection(), 64.0), None);
+        // Just below it, they reach the ground a long way off.
+        let below = Transform::from_xyz(0.0, 10.0, 0.0).looking_at(vec3(100.0, 9.0, 0.0), Vec3::Y);
+        let footprint = visible_footprint(&below, &projection(), 64.0).unwrap();
+        assert!(footprint.iter().any(|point| point.x > 5000.0));
+
+        // 45° down towards +x, so the view stretches along x by the heights it spans.
+        let camera =
+            Transform::from_xyz(0.0, 100.0, 0.0).looking_at(vec3(100.0, 0.0, 0.0), Vec3::Y);
+        let footprint = visible_footprint(&camera, &projection(), 10.0).unwrap();
+        let (min, max) = footprint.iter().fold(
+            (Vec2::splat(f32::INFINITY), Vec2::splat(f32::NEG_INFINITY)),
+            |(min, max), point| (min.min(*point), max.max(*point)),
+        );
+        let reach = 3.0 * 2f32.sqrt();
+        assert!((min.x - (90.0 - reach)).abs() < 1e-2, "{}", min);
+        assert!((max.x - (110.0 + reach)).abs() < 1e-2, "{}", max);
+        assert!((min.y + 5.0).abs() < 1e-3 && (max.y - 5.0).abs() < 1e-3);
+        assert!(polygon_overlaps_rectangle(
+            &footprint,
+            vec2(96.0, 0.0),
+            vec2(112.0, 16.0)
+        ));
+        assert!(!polygon_overlaps_rectangle(
+            &footprint,
+            vec2(0.0, 0.0),
+            vec2(16.0, 16.0)
+        ));
+    }
+
+    #[test]
+    fn lod_edges_meet_full_resolution_neighbours() {
+        let mut terrain = Terrain {
+            data: TerrainData::zeros(16),
+            mesh_error: 0.0,
+            ..Default::default()
+        };
+        for z in -16..32 {
+            for x in -16..48 {
+                terrain.data[(x, z)] = (x as f32 * 0.7).sin() * 3.0 + (z as f32 * 0.45).cos() * 2.0;
+            }
+        }
+        let coarse = vertices(&ChunkSnapshot::new(&terrain, (0, 0), 4).generate_mesh());
+        let fine = vertices(&ChunkSnapshot::new(&terrain, (1, 0), 1).generate_mesh());
+        let surface = 5 * 5;
+        let (coarse, skirt) = coarse.split_at(surface);
+        let fine = &fine[..17 * 17];
+        let edge = |vertices: &[(Vec2, f32)]| {
+            let mut edge: Vec<(f32, f32)> = vertices
+                .iter()
+                .filter(|(real, _)| real.x == 16.0)
+                .map(|(real, height)| (real.y, *height))
+                .collect();
+            edge.sort_by(|a, b| a.0.total_cmp(&b.0));
+            edge
+        };
+        let (coarse_edge, fine_edge) = (edge(coarse), edge(fine));
+        assert_eq!(coarse_edge.len(), 5);
+        assert_eq!(fine_edge.len(), 17);
+        // Every LOD vertex on the border is a vertex of the neighbour too, at the same height.
+        for (i, vertex) in coarse_edge.iter().enumerate() {
+            assert_eq!(*vertex, fine_edge[i * 4]);
+        }
+        // Between them, the skirt hangs down past the neighbour's finer border.
+        let depth = coarse_edge[0].1
+            - skirt
+                .iter()
+                .find(|(real, _)| *real == vec2(16.0, 0.0))
+                .unwrap()
+                .1;
+        for (i, (_, height)) in fine_edge.iter().enumerate() {
+            let (a, b) = (coarse_edge[i / 4].1, coarse_edge[(i / 4 + 1).min(4)].1);
+            let lod = a + (b - a) * (i % 4) as f32 / 4.0;
+            assert!(lod - depth < *height && *height < lod + depth);
+        }
+        assert!(depth > 1.0);
+    }
+}
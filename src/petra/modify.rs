@@ -1,5 +1,5 @@
 pub struct Modify;
-use super::{terrain, tools};
+use super::{analysis, terrain, tools};
 use bevy::{
     math::{vec2, vec3},
     prelude::*,
@@ -7,12 +7,16 @@ use bevy::{
 };
 use bevy_egui::EguiContext;
 use bevy_mod_picking::{PickingCamera, Primitive3d};
-use tools::{erode, raise};
+use tools::{erode, raise, smooth};
 
 impl Plugin for Modify {
     fn build(&self, app: &mut App) {
         app.insert_resource(terrain::Terrain::default())
             .insert_resource(SelectedTool(Tool::Raise))
+            .insert_resource(Selection(None))
+            .insert_resource(LayerMaskSetting(None))
+            .insert_resource(RiverPoints(Vec::new()))
+            .insert_resource(Previewing(false))
             .insert_resource(CursorPosition {
                 pos: Vec2::new(0.0, 0.0),
                 plane_pos: vec3(0.0, 0.0, 0.0),
@@ -26,9 +30,24 @@ impl Plugin for Modify {
 pub enum Tool {
     Erode,
     Raise,
+    Smooth,
+    River,
 }
 pub struct SelectedTool(pub Tool);
 
+// Rectangle of cells that terrain-wide operations can be limited to.
+pub struct Selection(pub Option<terrain::Bounds>);
+
+// Analysis layer range that tools and height remapping are limited to.
+pub struct LayerMaskSetting(pub Option<analysis::LayerMask>);
+
+// Points clicked with the river tool, in world x and z, waiting to be carved.
+pub struct RiverPoints(pub Vec<Vec2>);
+
+// Whether the terrain shows a preview that puts the old heights back when it ends, such as the
+// Remap heights window's. Tools don't edit the terrain while it does.
+pub struct Previewing(pub bool);
+
 #[derive(Default, TypeUuid, Clone, Copy)]
 #[uuid = "080ca54b-8c80-4aa5-891d-4c0cbcd0937d"]
 #[repr(C)]
@@ -39,6 +58,7 @@ pub struct CursorPosition {
     pub hovering: u32, // bool isn't supported. u8 has an error with "copy buffer alignment"
 }
 
+#[allow(clippy::too_many_arguments)]
 fn modify_system(
     mut terrain: ResMut<terrain::Terrain>,
     camera: Query<&PickingCamera>,
@@ -46,6 +66,10 @@ fn modify_system(
     mut selected_tool: ResMut<SelectedTool>,
     mut cursor_position: ResMut<CursorPosition>,
     mut egui_ctx: ResMut<EguiContext>,
+    mut dabs: Local<u64>,
+    layer_mask: Res<LayerMaskSetting>,
+    mut river_points: ResMut<RiverPoints>,
+    previewing: Res<Previewing>,
 ) {
     if !egui_ctx.ctx_mut().wants_pointer_input() {
         let cast_source = camera.iter().next().unwrap();
@@ -59,10 +83,12 @@ fn modify_system(
         if mouse_input.just_pressed(MouseButton::Right) {
             match selected_tool.0 {
                 Tool::Raise => selected_tool.0 = Tool::Erode,
-                Tool::Erode => selected_tool.0 = Tool::Raise,
+                Tool::Erode => selected_tool.0 = Tool::Smooth,
+                Tool::Smooth => selected_tool.0 = Tool::River,
+                Tool::River => selected_tool.0 = Tool::Raise,
             }
         }
-        if mouse_input.pressed(MouseButton::Left) {
+        if mouse_input.pressed(MouseButton::Left) && !previewing.0 {
             let pick_pos = cast_source
                 .intersect_primitive(Primitive3d::Plane {
                     point: cursor_position.plane_pos,
@@ -71,27 +97,36 @@ fn modify_system(
                 .unwrap()
                 .position();
             cursor_position.pos = vec2(pick_pos.x, pick_pos.z);
+            // The cursor lives in the world, the tools work in cells.
+            let brush_position = terrain.world_to_cells(cursor_position.pos);
+            let brush_radius = (cursor_position.radius / terrain.cell_size).max(1.0) as i64;
+            // Tools weigh their changes by the mask themselves, over just the cells they touch.
+            let mask = layer_mask.0.as_ref();
             match selected_tool.0 {
                 Tool::Raise => {
-                    raise::trigger(
-                        vec2(cursor_position.pos.x, cursor_position.pos.y),
-                        cursor_position.radius as i64,
-                        &mut terrain,
-                    );
+                    raise::trigger(brush_position, brush_radius, &mut terrain, mask);
                 }
                 Tool::Erode => {
-                    erode::trigger(
-                        vec2(cursor_position.pos.x, cursor_position.pos.y),
-                        25,
-                        &mut terrain,
-                    );
+                    // Every dab gets its own seed, but the same session of dabs always erodes the same way.
+                    let seed = terrain.seed.wrapping_add(*dabs);
+                    *dabs += 1;
+                    let radius = (25.0 / terrain.cell_size).max(1.0) as i64;
+                    erode::trigger(brush_position, radius, seed, &mut terrain, mask);
+                }
+                Tool::Smooth => {
+                    smooth::trigger(brush_position, brush_radius, &mut terrain, mask);
+                }
+                // The river tool collects points on click, and the channel is carved from the
+                // side panel.
+                Tool::River => {
+                    if mouse_input.just_pressed(MouseButton::Left) {
+                        river_points.0.push(cursor_position.pos);
+                    }
                 }
             }
-        } else {
-            if let Some(intersection_result) = cast_source.intersect_top() {
-                let intersection_pos = intersection_result.1.position();
-                cursor_position.pos = vec2(intersection_pos.x, intersection_pos.z);
-            }
+        } else if let Some(intersection_result) = cast_source.intersect_top() {
+            let intersection_pos = intersection_result.1.position();
+            cursor_position.pos = vec2(intersection_pos.x, intersection_pos.z);
         }
     }
 }
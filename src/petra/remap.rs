@@ -0,0 +1,352 @@
+use crate::petra::cursor::Region;
+use crate::petra::terrain::{Bounds, TerrainData};
+use rayon::prelude::*;
+
+// Height remapping, over the whole terrain or weighted by a mask. Gamma and curves work on heights
+// scaled to 0..1 over the range of the heights they touch, so they don't depend on sea level.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Remap {
+    // Stretches the current range of heights to min..max.
+    Normalise { min: f32, max: f32 },
+    Clamp { min: f32, max: f32 },
+    // Above 1 flattens the lowlands and sharpens peaks, below 1 the other way round.
+    Gamma(f32),
+    Curve(Curve),
+    // Steps of the given height. Smoothing is the part of each step, 0..1, spent on a smooth ramp
+    // up to the next one; 0 gives sheer cliffs.
+    Terrace { step: f32, smoothing: f32 },
+}
+
+impl Remap {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Remap::Normalise { .. } => "Normalise",
+            Remap::Clamp { .. } => "Clamp",
+            Remap::Gamma(_) => "Gamma",
+            Remap::Curve(_) => "Curve",
+            Remap::Terrace { .. } => "Terrace",
+        }
+    }
+
+    // New height for each height, given the (min, max) of the heights being remapped. Curves are
+    // worked out once here, not for every height.
+    pub fn function(&self, range: (f32, f32)) -> impl Fn(f32) -> f32 + Sync + '_ {
+        let spline = match self {
+            Remap::Curve(curve) => curve.spline(),
+            _ => Spline::default(),
+        };
+        move |height| {
+            let (low, high) = range;
+            let span = high - low;
+            let normalised = if span > 0.0 {
+                (height - low) / span
+            } else {
+                0.0
+            };
+            match self {
+                Remap::Normalise { min, max } => min + normalised * (max - min),
+                Remap::Clamp { min, max } => height.clamp(*min, *max),
+                Remap::Gamma(gamma) => low + normalised.max(0.0).powf(*gamma) * span,
+                Remap::Curve(_) => low + spline.get(normalised) * span,
+                Remap::Terrace { step, smoothing } => {
+                    if *step <= 0.0 {
+                        return height;
+                    }
+                    let steps = height / step;
+                    let floor = steps.floor();
+                    let flat = 1.0 - smoothing.clamp(0.0, 1.0);
+                    let fraction = steps - floor;
+                    let rise = if fraction <= flat {
+                        0.0
+                    } else {
+                        let t = (fraction - flat) / (1.0 - flat);
+                        t * t * (3.0 - 2.0 * t)
+                    };
+                    (floor + rise) * step
+                }
+            }
+        }
+    }
+}
+
+// Monotone cubic through control points (input, output), both 0..1. Outside the first and last
+// point the curve stays flat.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Curve {
+    pub points: Vec<(f32, f32)>,
+}
+
+impl Default for Curve {
+    fn default() -> Self {
+        Self {
+            points: vec![(0.0, 0.0), (0.5, 0.5), (1.0, 1.0)],
+        }
+    }
+}
+
+impl Curve {
+    // The curve ready to evaluate, with its points sorted and its tangents worked out.
+    pub fn spline(&self) -> Spline {
+        let mut points = self.points.clone();
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        points.dedup_by(|a, b| a.0 == b.0);
+        if points.len() < 2 {
+            return Spline {
+                tangents: vec![0.0; points.len()],
+                points,
+            };
+        }
+        let last = points.len() - 1;
+
+        // Fritsch-Carlson tangents, which keep the curve from overshooting between points.
+        let secants: Vec<f32> = points
+            .windows(2)
+            .map(|pair| (pair[1].1 - pair[0].1) / (pair[1].0 - pair[0].0))
+            .collect();
+        let mut tangents = vec![0.0; points.len()];
+        tangents[0] = secants[0];
+        tangents[last] = secants[last - 1];
+        for i in 1..last {
+            tangents[i] = if secants[i - 1] * secants[i] <= 0.0 {
+                0.0
+            } else {
+                (secants[i - 1] + secants[i]) / 2.0
+            };
+        }
+        for i in 0..last {
+            if secants[i] == 0.0 {
+                tangents[i] = 0.0;
+                tangents[i + 1] = 0.0;
+                continue;
+            }
+            let a = tangents[i] / secants[i];
+            let b = tangents[i + 1] / secants[i];
+            let length = (a * a + b * b).sqrt();
+            if length > 3.0 {
+                tangents[i] = 3.0 * a / length * secants[i];
+                tangents[i + 1] = 3.0 * b / length * secants[i];
+            }
+        }
+        Spline { points, tangents }
+    }
+}
+
+// Curve with its points sorted by input, each with the tangent of the curve there.
+#[derive(Debug, Clone, Default)]
+pub struct Spline {
+    points: Vec<(f32, f32)>,
+    tangents: Vec<f32>,
+}
+
+impl Spline {
+    pub fn get(&self, x: f32) -> f32 {
+        let points = &self.points;
+        match points.len() {
+            0 => return x,
+            1 => return points[0].1,
+            _ => {}
+        }
+        let last = points.len() - 1;
+        if x <= points[0].0 {
+            return points[0].1;
+        }
+        if x >= points[last].0 {
+            return points[last].1;
+        }
+
+        let i = points.partition_point(|point| point.0 <= x) - 1;
+        let (x0, y0) = points[i];
+        let (x1, y1) = points[i + 1];
+        let h = x1 - x0;
+        let t = (x - x0) / h;
+        let (t2, t3) = (t * t, t * t * t);
+        (2.0 * t3 - 3.0 * t2 + 1.0) * y0
+            + (t3 - 2.0 * t2 + t) * h * self.tangents[i]
+            + (-2.0 * t3 + 3.0 * t2) * y1
+            + (t3 - t2) * h * self.tangents[i + 1]
+    }
+}
+
+// Weights for a rectangle of cells: 1 inside, fading to 0 over the outermost feather cells.
+pub fn rectangle_mask(bounds: Bounds, feather: i32) -> Region {
+    let mut mask = Region::new(bounds);
+    let feather = feather.max(0) as f32;
+    for y in bounds.min.1..bounds.max.1 {
+        let edge_y = (y - bounds.min.1).min(bounds.max.1 - 1 - y) as f32;
+        for x in bounds.min.0..bounds.max.0 {
+            let edge = edge_y.min((x - bounds.min.0).min(bounds.max.0 - 1 - x) as f32);
+            *mask.get_mut((x, y)).unwrap() = ((edge + 1.0) / (feather + 1.0)).min(1.0);
+        }
+    }
+    mask
+}
+
+impl TerrainData {
+    // Lowest and highest height, of every cell or of the cells the mask touches.
+    pub fn height_range(&self, mask: Option<&Region>) -> Option<(f32, f32)> {
+        let chunk_size = self.chunk_size as i32;
+        self.chunks
+            .par_iter()
+            .filter_map(|(coords, chunk)| {
+                let mut range: Option<(f32, f32)> = None;
+                for (i, &height) in chunk.data.iter().enumerate() {
+                    if let Some(mask) = mask {
+                        let cell = (
+                            coords.0 * chunk_size + i as i32 % chunk_size,
+                            coords.1 * chunk_size + i as i32 / chunk_size,
+                        );
+                        if mask.get(cell).unwrap_or(0.0) <= 0.0 {
+                            continue;
+                        }
+                    }
+                    range = Some(range.map_or((height, height), |(low, high)| {
+                        (low.min(height), high.max(height))
+                    }));
+                }
+                range
+            })
+            .reduce_with(|a, b| (a.0.min(b.0), a.1.max(b.1)))
+    }
+
+    // Remaps heights in place. With a mask, each cell moves towards its new height by its weight
+    // in the mask, and cells outside of the mask stay as they are.
+    pub fn remap(&mut self, remap: &Remap, mask: Option<&Region>) {
+        let range = match self.height_range(mask) {
+            Some(range) => range,
+            None => return,
+        };
+        let function = remap.function(range);
+        let chunk_size = self.chunk_size;
+        self.chunks.par_iter_mut().for_each(|(coords, chunk)| {
+            let chunk_bounds = Bounds::of_chunk(*coords, chunk_size);
+            if let Some(mask) = mask {
+                if mask.bounds.intersection(&chunk_bounds).is_none() {
+                    return;
+                }
+            }
+            let mut changed = false;
+            for (i, height) in chunk.data.iter_mut().enumerate() {
+                let weight = match mask {
+                    Some(mask) => {
+                        let cell = (
+                            chunk_bounds.min.0 + (i % chunk_size) as i32,
+                            chunk_bounds.min.1 + (i / chunk_size) as i32,
+                        );
+                        mask.get(cell).unwrap_or(0.0)
+                    }
+                    None => 1.0,
+                };
+                if weight <= 0.0 {
+                    continue;
+                }
+                let new = *height + (function(*height) - *height) * weight.min(1.0);
+                if new != *height {
+                    *height = new;
+                    changed = true;
+                }
+            }
+            if changed {
+                chunk.touch();
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // One chunk of heights rising from -3 along x and y.
+    fn slope() -> TerrainData {
+        let mut data = TerrainData::zeros(16);
+        for y in 0..16 {
+            for x in 0..16 {
+                data[(x, y)] = (x + y) as f32 * 0.5 - 3.0;
+            }
+        }
+        data
+    }
+
+    fn heights(data: &TerrainData) -> Vec<f32> {
+        data.region(data.bounds().unwrap()).data
+    }
+
+    #[test]
+    fn spline_is_monotone_between_rising_points() {
+        let curve = Curve {
+            points: vec![(0.0, 0.0), (0.1, 0.6), (0.2, 0.65), (0.7, 0.7), (1.0, 1.0)],
+        };
+        let spline = curve.spline();
+        let values: Vec<f32> = (0..=1000).map(|i| spline.get(i as f32 / 1000.0)).collect();
+        assert!(values.windows(2).all(|pair| pair[1] >= pair[0] - 1e-6));
+        for (x, y) in &curve.points {
+            assert!((spline.get(*x) - y).abs() < 1e-6);
+        }
+        assert_eq!(spline.get(-1.0), 0.0);
+        assert_eq!(spline.get(2.0), 1.0);
+    }
+
+    #[test]
+    fn normalise_reaches_the_requested_range() {
+        let mut data = slope();
+        data.remap(
+            &Remap::Normalise {
+                min: 2.0,
+                max: 10.0,
+            },
+            None,
+        );
+        let (low, high) = data.height_range(None).unwrap();
+        assert!((low - 2.0).abs() < 1e-5);
+        assert!((high - 10.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn gamma_of_one_changes_nothing() {
+        let mut data = slope();
+        let before = heights(&data);
+        data.remap(&Remap::Gamma(1.0), None);
+        for (after, before) in heights(&data).iter().zip(&before) {
+            assert!((after - before).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn terraces_without_smoothing_are_exact_steps() {
+        let function = Remap::Terrace {
+            step: 2.0,
+            smoothing: 0.0,
+        }
+        .function((0.0, 10.0));
+        assert_eq!(function(0.0), 0.0);
+        assert_eq!(function(1.99), 0.0);
+        assert_eq!(function(2.0), 2.0);
+        assert_eq!(function(5.5), 4.0);
+        assert_eq!(function(-0.5), -2.0);
+    }
+
+    #[test]
+    fn masked_remap_leaves_unweighted_cells() {
+        let mut data = slope();
+        let before = data.clone();
+        let mut mask = rectangle_mask(
+            Bounds {
+                min: (4, 4),
+                max: (12, 12),
+            },
+            0,
+        );
+        *mask.get_mut((6, 6)).unwrap() = 0.0;
+        data.remap(&Remap::Clamp { min: 0.0, max: 0.0 }, Some(&mask));
+        for y in 0..16 {
+            for x in 0..16 {
+                if mask.get((x, y)).unwrap_or(0.0) > 0.0 {
+                    assert_eq!(data[(x, y)], 0.0);
+                } else {
+                    assert_eq!(data[(x, y)], before[(x, y)]);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,380 @@
+use crate::petra::cursor::ChunkCursor;
+use crate::petra::terrain::{Bounds, Terrain, TerrainData};
+use bevy::math::{vec2, Vec2};
+
+// What reads outside of the terrain's bounds (the box around every allocated chunk) return.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgePolicy {
+    Zero,  // Flat ground at height 0, same as indexing TerrainData directly.
+    Clamp, // The nearest edge cell.
+    Wrap,  // The cell on the opposite side, making the terrain toroidal.
+    None,  // Nothing. Droplets die and slopes stop at the edge.
+}
+
+impl EdgePolicy {
+    pub const ALL: [EdgePolicy; 4] = [
+        EdgePolicy::Zero,
+        EdgePolicy::Clamp,
+        EdgePolicy::Wrap,
+        EdgePolicy::None,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            EdgePolicy::Zero => "Zero",
+            EdgePolicy::Clamp => "Clamp",
+            EdgePolicy::Wrap => "Wrap",
+            EdgePolicy::None => "None",
+        }
+    }
+
+    // Maps a cell to the cell that should actually be read, or None if it reads as nothing.
+    // Cells that are inside the bounds are always returned unchanged.
+    pub fn resolve(&self, bounds: Bounds, cell: (i32, i32)) -> Option<(i32, i32)> {
+        if bounds.contains(cell) {
+            return Some(cell);
+        }
+        match self {
+            EdgePolicy::Zero | EdgePolicy::None => None,
+            EdgePolicy::Clamp => Some((
+                cell.0.clamp(bounds.min.0, bounds.max.0 - 1),
+                cell.1.clamp(bounds.min.1, bounds.max.1 - 1),
+            )),
+            EdgePolicy::Wrap => Some((
+                bounds.min.0 + (cell.0 - bounds.min.0).rem_euclid(bounds.width()),
+                bounds.min.1 + (cell.1 - bounds.min.1).rem_euclid(bounds.height()),
+            )),
+        }
+    }
+
+    // Maps a cell that is about to be written to. Zero lets writes grow the terrain like before,
+    // Wrap writes to the opposite side and the other policies drop writes outside of the bounds.
+    pub fn write_target(&self, bounds: Option<Bounds>, cell: (i32, i32)) -> Option<(i32, i32)> {
+        match (self, bounds) {
+            (EdgePolicy::Zero, _) => Some(cell),
+            (EdgePolicy::Wrap, Some(bounds)) => self.resolve(bounds, cell),
+            (_, Some(bounds)) if bounds.contains(cell) => Some(cell),
+            _ => None,
+        }
+    }
+}
+
+// Kernel used to read between cells, and to spread writes over the cells around a position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interpolation {
+    Nearest,
+    Bilinear,
+    CatmullRom, // Bicubic that passes through every cell.
+    BSpline,    // Cubic B-spline. Smoother than Catmull-Rom, but doesn't pass through the cells.
+}
+
+impl Interpolation {
+    pub const ALL: [Interpolation; 4] = [
+        Interpolation::Nearest,
+        Interpolation::Bilinear,
+        Interpolation::CatmullRom,
+        Interpolation::BSpline,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Interpolation::Nearest => "Nearest",
+            Interpolation::Bilinear => "Bilinear",
+            Interpolation::CatmullRom => "Bicubic (Catmull-Rom)",
+            Interpolation::BSpline => "B-spline",
+        }
+    }
+
+    // Weights for the cells at offsets -1, 0, 1 and 2 from floor(x), where t = x - floor(x),
+    // followed by their derivatives with respect to t. The weights always add up to 1.
+    pub fn weights(&self, t: f32) -> ([f32; 4], [f32; 4]) {
+        let t2 = t * t;
+        let t3 = t2 * t;
+        match self {
+            Interpolation::Nearest => {
+                if t < 0.5 {
+                    ([0.0, 1.0, 0.0, 0.0], [0.0; 4])
+                } else {
+                    ([0.0, 0.0, 1.0, 0.0], [0.0; 4])
+                }
+            }
+            Interpolation::Bilinear => ([0.0, 1.0 - t, t, 0.0], [0.0, -1.0, 1.0, 0.0]),
+            Interpolation::CatmullRom => (
+                [
+                    (-t3 + 2.0 * t2 - t) / 2.0,
+                    (3.0 * t3 - 5.0 * t2 + 2.0) / 2.0,
+                    (-3.0 * t3 + 4.0 * t2 + t) / 2.0,
+                    (t3 - t2) / 2.0,
+                ],
+                [
+                    (-3.0 * t2 + 4.0 * t - 1.0) / 2.0,
+                    (9.0 * t2 - 10.0 * t) / 2.0,
+                    (-9.0 * t2 + 8.0 * t + 1.0) / 2.0,
+                    (3.0 * t2 - 2.0 * t) / 2.0,
+                ],
+            ),
+            Interpolation::BSpline => (
+                [
+                    (1.0 - t) * (1.0 - t) * (1.0 - t) / 6.0,
+                    (3.0 * t3 - 6.0 * t2 + 4.0) / 6.0,
+                    (-3.0 * t3 + 3.0 * t2 + 3.0 * t + 1.0) / 6.0,
+                    t3 / 6.0,
+                ],
+                [
+                    -(1.0 - t) * (1.0 - t) / 2.0,
+                    (3.0 * t2 - 4.0 * t) / 2.0,
+                    (-3.0 * t2 + 2.0 * t + 1.0) / 2.0,
+                    t2 / 2.0,
+                ],
+            ),
+        }
+    }
+
+    // Cells around pos that the kernel gives a non-zero weight, with their weights.
+    pub fn taps(&self, pos: Vec2) -> impl Iterator<Item = ((i32, i32), f32)> {
+        let (x0, y0) = (pos.x.floor() as i32, pos.y.floor() as i32);
+        let (wx, _) = self.weights(pos.x - pos.x.floor());
+        let (wy, _) = self.weights(pos.y - pos.y.floor());
+        (0..16).filter_map(move |i| {
+            let (i, j) = (i % 4, i / 4);
+            let weight = wx[i] * wy[j];
+            if weight != 0.0 {
+                Some(((x0 + i as i32 - 1, y0 + j as i32 - 1), weight))
+            } else {
+                None
+            }
+        })
+    }
+
+    // Value at pos, reading the cells the kernel needs through get. None if any of them is missing.
+    pub fn sample(&self, pos: Vec2, get: impl Fn((i32, i32)) -> Option<f32>) -> Option<f32> {
+        self.taps(pos).try_fold(0.0, |value, (cell, weight)| {
+            Some(value + weight * get(cell)?)
+        })
+    }
+
+    // Analytic gradient of the interpolated surface at pos, pointing uphill.
+    pub fn gradient(&self, pos: Vec2, get: impl Fn((i32, i32)) -> Option<f32>) -> Option<Vec2> {
+        // Nearest neighbour is flat between cells, so it borrows the bilinear gradient instead.
+        let interpolation = match self {
+            Interpolation::Nearest => Interpolation::Bilinear,
+            interpolation => *interpolation,
+        };
+        let (x0, y0) = (pos.x.floor() as i32, pos.y.floor() as i32);
+        let (wx, dwx) = interpolation.weights(pos.x - pos.x.floor());
+        let (wy, dwy) = interpolation.weights(pos.y - pos.y.floor());
+
+        let mut gradient = Vec2::ZERO;
+        for j in 0..4 {
+            for i in 0..4 {
+                let x_weight = dwx[i] * wy[j];
+                let y_weight = wx[i] * dwy[j];
+                if x_weight != 0.0 || y_weight != 0.0 {
+                    let value = get((x0 + i as i32 - 1, y0 + j as i32 - 1))?;
+                    gradient += vec2(x_weight, y_weight) * value;
+                }
+            }
+        }
+        Some(gradient)
+    }
+}
+
+// Read-only view of TerrainData that applies an edge policy to everything it reads.
+// The bounds are computed once on creation, and reads go through a ChunkCursor, so keep a sampler
+// around instead of making one per read.
+pub struct Sampler<'a> {
+    cursor: ChunkCursor<'a>,
+    policy: EdgePolicy,
+    interpolation: Interpolation,
+    bounds: Option<Bounds>,
+}
+
+impl<'a> Sampler<'a> {
+    pub fn new(data: &'a TerrainData, policy: EdgePolicy, interpolation: Interpolation) -> Self {
+        Self::with_bounds(data, policy, interpolation, data.bounds())
+    }
+
+    pub fn with_bounds(
+        data: &'a TerrainData,
+        policy: EdgePolicy,
+        interpolation: Interpolation,
+        bounds: Option<Bounds>,
+    ) -> Self {
+        Self {
+            cursor: ChunkCursor::new(data),
+            policy,
+            interpolation,
+            bounds,
+        }
+    }
+
+    // Sampler using the terrain's own settings.
+    pub fn for_terrain(terrain: &'a Terrain) -> Self {
+        Self::new(&terrain.data, terrain.edge_policy, terrain.interpolation)
+    }
+
+    pub fn interpolation(&self) -> Interpolation {
+        self.interpolation
+    }
+
+    pub fn get(&self, coordinates: (i32, i32)) -> Option<f32> {
+        if self.policy == EdgePolicy::Zero {
+            // Missing chunks already index as 0, inside of the bounds or not.
+            return Some(self.cursor.get(coordinates));
+        }
+        let cell = self.policy.resolve(self.bounds?, coordinates)?;
+        Some(self.cursor.get(cell))
+    }
+
+    // Like get, but falls back to the given value. Useful for normals, where a missing neighbour
+    // should read as flat instead of as a cliff.
+    pub fn get_or(&self, coordinates: (i32, i32), fallback: f32) -> f32 {
+        self.get(coordinates).unwrap_or(fallback)
+    }
+
+    // Moves a position that left the bounds back into them when wrapping, so droplets can travel
+    // around the world. Other policies leave the position alone.
+    pub fn wrap_position(&self, pos: Vec2) -> Vec2 {
+        match (self.policy, self.bounds) {
+            (EdgePolicy::Wrap, Some(bounds)) => vec2(
+                bounds.min.0 as f32
+                    + (pos.x - bounds.min.0 as f32).rem_euclid(bounds.width() as f32),
+                bounds.min.1 as f32
+                    + (pos.y - bounds.min.1 as f32).rem_euclid(bounds.height() as f32),
+            ),
+            _ => pos,
+        }
+    }
+
+    // Returns None if a cell the kernel needs is outside of the bounds under EdgePolicy::None.
+    pub fn sample(&self, pos: Vec2) -> Option<f32> {
+        self.interpolation.sample(pos, |cell| self.get(cell))
+    }
+
+    // Analytic gradient of the interpolated surface, pointing uphill.
+    pub fn gradient(&self, pos: Vec2) -> Option<Vec2> {
+        self.interpolation.gradient(pos, |cell| self.get(cell))
+    }
+
+    // Direction of steepest descent, normalised, and how much the surface drops per cell that way.
+    pub fn downhill(&self, pos: Vec2) -> Option<(Vec2, f32)> {
+        let gradient = self.gradient(pos)?;
+        Some(((-gradient).normalize_or_zero(), gradient.length()))
+    }
+
+    //Takes point and value map, returns downhill vector
+    pub fn get_slope_vector(&self, pos: Vec2) -> Option<Vec2> {
+        Some(self.downhill(pos)?.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOUNDS: Bounds = Bounds {
+        min: (0, 0),
+        max: (32, 16),
+    };
+
+    // Two chunks of smooth, uneven heights.
+    fn hills() -> TerrainData {
+        let mut data = TerrainData::zeros(16);
+        for y in 0..16 {
+            for x in 0..32 {
+                data[(x, y)] = (x as f32 * 0.3).sin() * 4.0 + (y as f32 * 0.2).cos() * 3.0;
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn policies_resolve_cells_past_the_edge() {
+        assert_eq!(EdgePolicy::Wrap.resolve(BOUNDS, (-1, -1)), Some((31, 15)));
+        assert_eq!(EdgePolicy::Wrap.resolve(BOUNDS, (-33, 17)), Some((31, 1)));
+        assert_eq!(EdgePolicy::Wrap.resolve(BOUNDS, (32, 16)), Some((0, 0)));
+        assert_eq!(EdgePolicy::Clamp.resolve(BOUNDS, (-5, -5)), Some((0, 0)));
+        assert_eq!(EdgePolicy::Clamp.resolve(BOUNDS, (40, -2)), Some((31, 0)));
+        assert_eq!(EdgePolicy::Clamp.resolve(BOUNDS, (-1, 99)), Some((0, 15)));
+        assert_eq!(EdgePolicy::Clamp.resolve(BOUNDS, (40, 20)), Some((31, 15)));
+        for policy in [EdgePolicy::Zero, EdgePolicy::None] {
+            assert_eq!(policy.resolve(BOUNDS, (-1, 3)), None);
+        }
+        for policy in EdgePolicy::ALL {
+            assert_eq!(policy.resolve(BOUNDS, (7, 9)), Some((7, 9)));
+        }
+    }
+
+    #[test]
+    fn writes_past_the_edge_follow_the_policy() {
+        let bounds = Some(BOUNDS);
+        assert_eq!(
+            EdgePolicy::Zero.write_target(bounds, (-3, 40)),
+            Some((-3, 40))
+        );
+        assert_eq!(EdgePolicy::Zero.write_target(None, (1, 1)), Some((1, 1)));
+        assert_eq!(
+            EdgePolicy::Wrap.write_target(bounds, (-3, -1)),
+            Some((29, 15))
+        );
+        assert_eq!(EdgePolicy::Wrap.write_target(None, (1, 1)), None);
+        for policy in [EdgePolicy::Clamp, EdgePolicy::None] {
+            assert_eq!(policy.write_target(bounds, (-3, 4)), None);
+            assert_eq!(policy.write_target(bounds, (3, 4)), Some((3, 4)));
+        }
+    }
+
+    #[test]
+    fn weights_add_up_to_one() {
+        for interpolation in Interpolation::ALL {
+            for i in 0..=20 {
+                let t = i as f32 / 20.0;
+                let (weights, derivatives) = interpolation.weights(t);
+                let sum: f32 = weights.iter().sum();
+                assert!((sum - 1.0).abs() < 1e-6, "{:?} at {}", interpolation, t);
+                assert!(derivatives.iter().sum::<f32>().abs() < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn catmull_rom_passes_through_cells() {
+        let data = hills();
+        let sampler = Sampler::new(&data, EdgePolicy::Clamp, Interpolation::CatmullRom);
+        for y in 0..16 {
+            for x in 0..32 {
+                let value = sampler.sample(vec2(x as f32, y as f32)).unwrap();
+                assert_eq!(value, data[(x, y)]);
+            }
+        }
+    }
+
+    #[test]
+    fn gradient_matches_finite_differences() {
+        let data = hills();
+        let h = 1e-2;
+        for interpolation in [
+            Interpolation::Bilinear,
+            Interpolation::CatmullRom,
+            Interpolation::BSpline,
+        ] {
+            let sampler = Sampler::new(&data, EdgePolicy::Clamp, interpolation);
+            for pos in [vec2(5.3, 7.6), vec2(15.7, 2.4), vec2(22.45, 11.55)] {
+                let gradient = sampler.gradient(pos).unwrap();
+                let difference = |offset: Vec2| {
+                    (sampler.sample(pos + offset).unwrap() - sampler.sample(pos - offset).unwrap())
+                        / (2.0 * h)
+                };
+                let expected = vec2(difference(vec2(h, 0.0)), difference(vec2(0.0, h)));
+                assert!(
+                    (gradient - expected).length() < 1e-2,
+                    "{:?} at {}: {} against {}",
+                    interpolation,
+                    pos,
+                    gradient,
+                    expected
+                );
+            }
+        }
+    }
+}
@@ -11,9 +11,174 @@ use bevy_egui::{
     EguiContext, EguiPlugin,
 };
 use bevy_mod_picking::*;
-use petra::modify::{SelectedTool, Tool};
+use petra::modify::{LayerMaskSetting, Previewing, RiverPoints, SelectedTool, Selection, Tool};
 
-use super::{camera::CameraPlugin, terrain::Terrain};
+use super::{
+    analysis::{self, Layer, LayerMask},
+    atlas::{self, AtlasSettings},
+    bake::{self, Bake, BakeFormat, BakeSettings},
+    camera::CameraPlugin,
+    coast,
+    contours::{self, ContourSettings},
+    export::{self, MeshFormat, SolidSettings},
+    generate,
+    grid::{self, GridSettings, GridShape},
+    hydrology::{self, Basins, Flow, FlowMethod, Lakes},
+    map::{self, MapStyle},
+    mesh::RenderSettings,
+    remap::{self, Curve, Remap},
+    sampler::{EdgePolicy, Interpolation},
+    stats::StatsCache,
+    terrain::{Bounds, Terrain, TerrainData, CHUNK_SIZES},
+    tools::river::{self, FlowCache, RiverSettings},
+    transform::Axis,
+    vector::{self, Feature, Geometry},
+};
+
+// Values typed into the Transform menu, kept between frames.
+struct TransformSettings {
+    factor: f32,
+    offset: (i32, i32),
+    crop: Bounds,
+}
+
+impl Default for TransformSettings {
+    fn default() -> Self {
+        Self {
+            factor: 2.0,
+            offset: (0, 0),
+            crop: Bounds {
+                min: (-256, -256),
+                max: (256, 256),
+            },
+        }
+    }
+}
+
+// Values of the Export mesh menu. The mesh error is the export's own, so files don't change with
+// the detail of the chunks on screen.
+#[derive(Default)]
+struct ExportSettings {
+    tiles: bool,
+    mesh_error: f32,
+    solid: SolidSettings,
+}
+
+// State of the Remap heights window. While previewing, the terrain shows the remapped copy of
+// original, and cancelling puts original back.
+struct RemapSettings {
+    open: bool,
+    remap: Remap,
+    masked: bool,
+    feather: i32,
+    layer_masked: bool,
+    preview: bool,
+    original: Option<TerrainData>,
+    previewed: Option<(Remap, Option<(Bounds, i32)>, Option<LayerMask>)>,
+}
+
+impl Default for RemapSettings {
+    fn default() -> Self {
+        Self {
+            open: false,
+            remap: Remap::Gamma(1.0),
+            masked: false,
+            feather: 8,
+            layer_masked: false,
+            preview: true,
+            original: None,
+            previewed: None,
+        }
+    }
+}
+
+// State of the Analysis window.
+struct AnalysisPanel {
+    open: bool,
+    layer: Layer,
+    mask: LayerMask,
+    use_mask: bool,
+    flow_method: FlowMethod,
+    river_threshold: f32,
+    basin_min_cells: usize,
+    fill: bool,
+    fill_epsilon: f32,
+}
+
+impl Default for AnalysisPanel {
+    fn default() -> Self {
+        Self {
+            open: false,
+            layer: Layer::Slope,
+            mask: LayerMask::new(Layer::Slope),
+            use_mask: false,
+            flow_method: FlowMethod::D8,
+            river_threshold: 500.0,
+            basin_min_cells: 100,
+            fill: true,
+            fill_epsilon: hydrology::FILL_EPSILON,
+        }
+    }
+}
+
+// Settings of the river tool, with the flow it follows water downhill on.
+#[derive(Default)]
+struct RiverPanel {
+    settings: RiverSettings,
+    flow: FlowCache,
+}
+
+// State of the Statistics window. Stats are only counted while it is open.
+struct StatsPanel {
+    open: bool,
+    bin_width: f32,
+    selection_only: bool,
+    cache: StatsCache,
+}
+
+impl Default for StatsPanel {
+    fn default() -> Self {
+        Self {
+            open: false,
+            bin_width: 1.0,
+            selection_only: false,
+            cache: StatsCache::default(),
+        }
+    }
+}
+
+// State of the Map export window.
+#[derive(Default)]
+struct MapPanel {
+    open: bool,
+    selection_only: bool,
+    contours: ContourSettings,
+    style: MapStyle,
+    shaded_contours: bool,
+    atlas: AtlasSettings,
+    grid: GridSettings,
+}
+
+// State of the Texture bakes window.
+struct BakePanel {
+    open: bool,
+    bake: Bake,
+    tiles: bool,
+    selection_only: bool,
+    settings: BakeSettings,
+}
+
+impl Default for BakePanel {
+    fn default() -> Self {
+        Self {
+            open: false,
+            bake: Bake::Normal,
+            tiles: false,
+            selection_only: false,
+            settings: BakeSettings::default(),
+        }
+    }
+}
 
 fn setup_scene(
     mut commands: Commands,
@@ -52,12 +217,28 @@ pub fn setup() {
         .insert_resource(UpdateMode::ReactiveLowPower { max_wait: ( Duration::from_secs(10) ) })
         .run();
 }
+#[allow(clippy::too_many_arguments)]
 fn ui_example(
     mut egui_context: ResMut<EguiContext>,
     mut selected_tool: ResMut<SelectedTool>,
-    terrain: Res<Terrain>,
+    mut terrain: ResMut<Terrain>,
+    mut render_settings: ResMut<RenderSettings>,
+    mut export_settings: Local<ExportSettings>,
+    mut transform_settings: Local<TransformSettings>,
+    mut remap_settings: Local<RemapSettings>,
+    mut selection: ResMut<Selection>,
+    mut stats_panel: Local<StatsPanel>,
+    mut analysis_panel: Local<AnalysisPanel>,
+    mut layer_mask: ResMut<LayerMaskSetting>,
+    mut river_points: ResMut<RiverPoints>,
+    mut river: Local<RiverPanel>,
+    mut map_panel: Local<MapPanel>,
+    mut bake_panel: Local<BakePanel>,
+    mut previewing: ResMut<Previewing>,
 ) {
     let ctx = egui_context.ctx_mut();
+    // Edits to the heights would be lost when a preview ends, so they wait until it does.
+    let editable = !previewing.0;
     egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
         // The top panel is often a good place for a menu bar:
         egui::menu::bar(ui, |ui| {
@@ -68,6 +249,159 @@ fn ui_example(
                 if ui.button("Save").clicked() {
                     terrain.data.save_to_exr("test.exr").unwrap();
                 }
+                if ui.button("Map export").clicked() {
+                    map_panel.open = true;
+                }
+                if ui.button("Texture bakes").clicked() {
+                    bake_panel.open = true;
+                }
+                if ui.button("Export coastline (SVG, GeoJSON)").clicked() {
+                    // Outlines the land above the sea level from the side panel.
+                    let features = coast::features(&coast::landmasses(&terrain, None));
+                    vector::write_svg(&features, coast::svg_style, "coastline.svg").unwrap();
+                    vector::write_geojson(&features, "coastline.geojson").unwrap();
+                }
+                egui::menu::menu_button(ui, "Export mesh", |ui| {
+                    // The selection if there is one, or else everything. An empty terrain has
+                    // nothing to export.
+                    let bounds = match selection.0 {
+                        Some(selection) => terrain
+                            .data
+                            .bounds()
+                            .and_then(|bounds| bounds.intersection(&selection)),
+                        None => terrain.data.bounds(),
+                    };
+                    ui.checkbox(&mut export_settings.tiles, "Chunk tiles");
+                    ui.add(
+                        egui::Slider::new(&mut export_settings.mesh_error, 0.0..=2.0)
+                            .text("Mesh error"),
+                    );
+                    for format in MeshFormat::ALL {
+                        if ui.button(format.name()).clicked() {
+                            if let Some(bounds) = bounds {
+                                let max_error =
+                                    Some(export_settings.mesh_error).filter(|error| *error > 0.0);
+                                let path = format!("terrain.{}", format.extension());
+                                export::export_mesh(
+                                    &terrain,
+                                    bounds,
+                                    max_error,
+                                    export_settings.tiles,
+                                    format,
+                                    &path,
+                                )
+                                .unwrap();
+                            }
+                        }
+                    }
+                    ui.separator();
+                    ui.add(
+                        egui::DragValue::new(&mut export_settings.solid.size)
+                            .prefix("Size: ")
+                            .suffix(" mm"),
+                    );
+                    ui.add(
+                        egui::DragValue::new(&mut export_settings.solid.base)
+                            .prefix("Base: ")
+                            .suffix(" mm"),
+                    );
+                    ui.add(
+                        egui::DragValue::new(&mut export_settings.solid.exaggeration)
+                            .prefix("Exaggeration: ")
+                            .speed(0.1),
+                    );
+                    if ui.button("Printable STL").clicked() {
+                        if let Some(bounds) = bounds {
+                            let solid =
+                                export::solid_mesh(&terrain, bounds, &export_settings.solid);
+                            MeshFormat::Stl.write(&solid, "terrain_print.stl").unwrap();
+                        }
+                    }
+                });
+            });
+            egui::menu::menu_button(ui, "Terrain", |ui| {
+                if ui
+                    .add_enabled(editable, egui::Button::new("Generate"))
+                    .clicked()
+                {
+                    let seed = terrain.seed as u32;
+                    generate::fbm(&mut terrain, (-4, -4), (3, 3), seed);
+                }
+                if ui.button("Analysis").clicked() {
+                    analysis_panel.open = true;
+                }
+                if ui.button("Statistics").clicked() {
+                    stats_panel.open = true;
+                }
+                if ui
+                    .add_enabled(editable, egui::Button::new("Fill depressions"))
+                    .clicked()
+                {
+                    // Pits drain through their spill points afterwards, for erosion and flow.
+                    if let Some(bounds) = terrain.data.bounds() {
+                        let heights = terrain.data.region(bounds);
+                        let filled =
+                            hydrology::fill(&heights, terrain.sea_level, hydrology::FILL_EPSILON);
+                        terrain.data.apply_region(&filled);
+                    }
+                }
+                if ui.button("Remap heights").clicked() {
+                    remap_settings.open = true;
+                }
+                ui.add_enabled_ui(editable, |ui| {
+                    egui::menu::menu_button(ui, "Transform", |ui| {
+                        ui.add(
+                            egui::DragValue::new(&mut transform_settings.factor)
+                                .prefix("Factor: ")
+                                .speed(0.05)
+                                .clamp_range(0.125..=8.0),
+                        );
+                        if ui.button("Resample").clicked() {
+                            terrain.data = terrain.data.resample(
+                                transform_settings.factor,
+                                terrain.interpolation,
+                                terrain.edge_policy,
+                            );
+                        }
+                        ui.separator();
+                        if ui.button("Rotate 90°").clicked() {
+                            terrain.data = terrain.data.rotate(1);
+                        }
+                        if ui.button("Flip X").clicked() {
+                            terrain.data = terrain.data.flip(Axis::X);
+                        }
+                        if ui.button("Flip Z").clicked() {
+                            terrain.data = terrain.data.flip(Axis::Y);
+                        }
+                        ui.separator();
+                        ui.horizontal(|ui| {
+                            ui.add(
+                                egui::DragValue::new(&mut transform_settings.offset.0)
+                                    .prefix("x: "),
+                            );
+                            ui.add(
+                                egui::DragValue::new(&mut transform_settings.offset.1)
+                                    .prefix("z: "),
+                            );
+                        });
+                        if ui.button("Translate").clicked() {
+                            terrain.data = terrain.data.translate(transform_settings.offset);
+                        }
+                        ui.separator();
+                        let crop = &mut transform_settings.crop;
+                        ui.horizontal(|ui| {
+                            ui.add(egui::DragValue::new(&mut crop.min.0).prefix("Min x: "));
+                            ui.add(egui::DragValue::new(&mut crop.min.1).prefix("z: "));
+                        });
+                        ui.horizontal(|ui| {
+                            ui.add(egui::DragValue::new(&mut crop.max.0).prefix("Max x: "));
+                            ui.add(egui::DragValue::new(&mut crop.max.1).prefix("z: "));
+                        });
+                        if ui.button("Crop").clicked() {
+                            terrain.data = terrain.data.crop(transform_settings.crop);
+                        }
+                    });
+                });
             });
         });
     });
@@ -79,6 +413,831 @@ fn ui_example(
             if ui.button("Erode").clicked() {
                 selected_tool.0 = Tool::Erode;
             }
+            if ui.button("Smooth").clicked() {
+                selected_tool.0 = Tool::Smooth;
+            }
+            if ui.button("River").clicked() {
+                selected_tool.0 = Tool::River;
+            }
+            if let Tool::River = selected_tool.0 {
+                ui.add_enabled_ui(editable, |ui| {
+                    river_panel(
+                        ui,
+                        &mut terrain,
+                        &mut river_points,
+                        &mut river,
+                        layer_mask.0.as_ref(),
+                    );
+                });
+            }
+            ui.separator();
+            let previous_policy = terrain.edge_policy;
+            let previous_interpolation = terrain.interpolation;
+            let previous_mesh_error = terrain.mesh_error;
+            egui::ComboBox::from_label("Edges")
+                .selected_text(terrain.edge_policy.name())
+                .show_ui(ui, |ui| {
+                    for policy in EdgePolicy::ALL {
+                        ui.selectable_value(&mut terrain.edge_policy, policy, policy.name());
+                    }
+                });
+            egui::ComboBox::from_label("Interpolation")
+                .selected_text(terrain.interpolation.name())
+                .show_ui(ui, |ui| {
+                    for interpolation in Interpolation::ALL {
+                        ui.selectable_value(
+                            &mut terrain.interpolation,
+                            interpolation,
+                            interpolation.name(),
+                        );
+                    }
+                });
+            ui.add(egui::Slider::new(&mut terrain.mesh_error, 0.0..=2.0).text("Mesh error"));
+            if terrain.edge_policy != previous_policy
+                || terrain.interpolation != previous_interpolation
+                || terrain.mesh_error != previous_mesh_error
+            {
+                // The edges and normals of every chunk may look different now.
+                terrain
+                    .data
+                    .chunks
+                    .values_mut()
+                    .for_each(|chunk| chunk.modified = true);
+            }
+            ui.separator();
+            let mut chunk_size = terrain.data.chunk_size;
+            ui.add_enabled_ui(editable, |ui| {
+                egui::ComboBox::from_label("Chunk size")
+                    .selected_text(chunk_size.to_string())
+                    .show_ui(ui, |ui| {
+                        for size in CHUNK_SIZES {
+                            ui.selectable_value(&mut chunk_size, size, size.to_string());
+                        }
+                    });
+            });
+            if chunk_size != terrain.data.chunk_size {
+                terrain.data = terrain.data.rechunk(chunk_size);
+            }
+            ui.add(
+                egui::DragValue::new(&mut terrain.cell_size)
+                    .prefix("Cell size: ")
+                    .speed(0.1)
+                    .clamp_range(0.01..=1000.0),
+            );
+            ui.add(
+                egui::DragValue::new(&mut terrain.sea_level)
+                    .prefix("Sea level: ")
+                    .speed(0.1),
+            );
+            ui.separator();
+            let mut selected = selection.0.is_some();
+            ui.checkbox(&mut selected, "Selection");
+            if selected != selection.0.is_some() {
+                selection.0 = Some(Bounds {
+                    min: (-64, -64),
+                    max: (64, 64),
+                })
+                .filter(|_| selected);
+            }
+            if let Some(bounds) = &mut selection.0 {
+                ui.horizontal(|ui| {
+                    ui.add(egui::DragValue::new(&mut bounds.min.0).prefix("Min x: "));
+                    ui.add(egui::DragValue::new(&mut bounds.min.1).prefix("z: "));
+                });
+                ui.horizontal(|ui| {
+                    ui.add(egui::DragValue::new(&mut bounds.max.0).prefix("Max x: "));
+                    ui.add(egui::DragValue::new(&mut bounds.max.1).prefix("z: "));
+                });
+                bounds.max = (
+                    bounds.max.0.max(bounds.min.0 + 1),
+                    bounds.max.1.max(bounds.min.1 + 1),
+                );
+            }
+            ui.separator();
+            ui.add(
+                egui::Slider::new(&mut render_settings.distance, 1..=64).text("Render distance"),
+            );
+            ui.add(egui::Slider::new(&mut render_settings.margin, 0..=8).text("Margin"));
+        });
+    });
+    analysis_window(ctx, &terrain, &mut analysis_panel, &mut layer_mask);
+    remap_window(
+        ctx,
+        &mut terrain,
+        &mut remap_settings,
+        &selection,
+        &layer_mask,
+    );
+    previewing.0 = remap_settings.original.is_some();
+    stats_window(ctx, &terrain, &mut stats_panel, &selection);
+    map_window(ctx, &mut terrain, &mut map_panel, &selection);
+    bake_window(ctx, &terrain, &mut bake_panel, &selection);
+}
+
+fn bake_window(
+    ctx: &egui::Context,
+    terrain: &Terrain,
+    panel: &mut BakePanel,
+    selection: &Selection,
+) {
+    let mut open = panel.open;
+    egui::Window::new("Texture bakes")
+        .open(&mut open)
+        .show(ctx, |ui| {
+            egui::ComboBox::from_label("Bake")
+                .selected_text(panel.bake.name())
+                .show_ui(ui, |ui| {
+                    for bake in Bake::ALL {
+                        ui.selectable_value(&mut panel.bake, bake, bake.name());
+                    }
+                });
+            let settings = &mut panel.settings;
+            match panel.bake {
+                Bake::Normal => {}
+                Bake::AmbientOcclusion => {
+                    ui.add(
+                        egui::DragValue::new(&mut settings.occlusion_radius)
+                            .prefix("Radius: ")
+                            .suffix(" units")
+                            .speed(0.1)
+                            .clamp_range(0.01..=f32::MAX),
+                    );
+                    ui.add(
+                        egui::DragValue::new(&mut settings.occlusion_directions)
+                            .prefix("Directions: ")
+                            .clamp_range(1..=64),
+                    );
+                    ui.add(
+                        egui::DragValue::new(&mut settings.occlusion_steps)
+                            .prefix("Steps: ")
+                            .clamp_range(1..=256),
+                    );
+                }
+                Bake::Curvature => {
+                    ui.add(
+                        egui::DragValue::new(&mut settings.curvature_radius)
+                            .prefix("Radius: ")
+                            .suffix(" cells")
+                            .speed(0.1)
+                            .clamp_range(1.0..=64.0),
+                    );
+                    ui.add(
+                        egui::DragValue::new(&mut settings.curvature_range)
+                            .prefix("Full at: ")
+                            .suffix(" per unit")
+                            .speed(0.001)
+                            .clamp_range(0.0001..=f32::MAX),
+                    );
+                }
+            }
+            ui.separator();
+            ui.checkbox(&mut panel.tiles, "Chunk tiles");
+            ui.add_enabled_ui(selection.0.is_some(), |ui| {
+                ui.checkbox(&mut panel.selection_only, "Selection only");
+            });
+            let bounds = selection
+                .0
+                .filter(|_| panel.selection_only)
+                .or_else(|| terrain.data.bounds());
+            for format in BakeFormat::ALL {
+                if ui.button(format!("Bake ({})", format.name())).clicked() {
+                    if let Some(bounds) = bounds {
+                        let path =
+                            format!("terrain_{}.{}", panel.bake.suffix(), format.extension());
+                        bake::export_bake(
+                            terrain,
+                            panel.bake,
+                            &panel.settings,
+                            bounds,
+                            panel.tiles,
+                            format,
+                            &path,
+                        )
+                        .unwrap();
+                    }
+                }
+            }
+        });
+    panel.open = open;
+}
+
+fn map_window(
+    ctx: &egui::Context,
+    terrain: &mut Terrain,
+    panel: &mut MapPanel,
+    selection: &Selection,
+) {
+    let mut open = panel.open;
+    egui::Window::new("Map export")
+        .open(&mut open)
+        .show(ctx, |ui| {
+            ui.add_enabled_ui(selection.0.is_some(), |ui| {
+                ui.checkbox(&mut panel.selection_only, "Selection only");
+            });
+            let bounds = selection.0.filter(|_| panel.selection_only);
+            ui.separator();
+            let contours = &mut panel.contours;
+            ui.add(
+                egui::DragValue::new(&mut contours.interval)
+                    .prefix("Contour interval: ")
+                    .speed(0.1)
+                    .clamp_range(0.01..=f32::MAX),
+            );
+            ui.add(
+                egui::DragValue::new(&mut contours.index_every)
+                    .prefix("Index line every: ")
+                    .clamp_range(0..=100),
+            );
+            ui.add(
+                egui::DragValue::new(&mut contours.tolerance)
+                    .prefix("Simplify to: ")
+                    .speed(0.01)
+                    .clamp_range(0.0..=f32::MAX),
+            );
+            if ui.button("Export contours (SVG, GeoJSON)").clicked() {
+                let features = contours::features(&contours::contours(terrain, contours, bounds));
+                vector::write_svg(&features, contours::svg_style, "contours.svg").unwrap();
+                vector::write_geojson(&features, "contours.geojson").unwrap();
+            }
+            ui.separator();
+            let style = &mut panel.style;
+            ui.add(
+                egui::DragValue::new(&mut style.azimuth)
+                    .prefix("Sun azimuth: ")
+                    .suffix("°")
+                    .clamp_range(0.0..=360.0),
+            );
+            ui.add(
+                egui::DragValue::new(&mut style.altitude)
+                    .prefix("Sun altitude: ")
+                    .suffix("°")
+                    .clamp_range(0.0..=90.0),
+            );
+            ui.checkbox(&mut style.multidirectional, "Multidirectional");
+            ui.add(
+                egui::DragValue::new(&mut style.exaggeration)
+                    .prefix("Exaggeration: ")
+                    .speed(0.1)
+                    .clamp_range(0.0..=100.0),
+            );
+            ui.add(egui::Slider::new(&mut style.shading, 0.0..=1.0).text("Shading"));
+            ui.checkbox(&mut style.hypsometric, "Colour by height");
+            ui.checkbox(&mut style.sea_tint, "Tint the sea");
+            ui.checkbox(&mut panel.shaded_contours, "Contours");
+            ui.add(
+                egui::DragValue::new(&mut style.scale)
+                    .prefix("Pixels per cell: ")
+                    .clamp_range(1..=16),
+            );
+            if ui.button("Export shaded map (PNG)").clicked() {
+                let style = MapStyle {
+                    contours: Some(panel.contours).filter(|_| panel.shaded_contours),
+                    ..panel.style
+                };
+                if let Some(img) = map::render(terrain, &style, bounds) {
+                    img.save("map.png").unwrap();
+                }
+            }
+            ui.separator();
+            let atlas = &mut panel.atlas;
+            ui.add(
+                egui::DragValue::new(&mut terrain.worldscale)
+                    .prefix("World scale: ")
+                    .suffix(" units per km")
+                    .clamp_range(0.001..=f32::MAX),
+            );
+            ui.add(
+                egui::DragValue::new(&mut atlas.glyph_spacing)
+                    .prefix("Glyphs every: ")
+                    .suffix(" cells")
+                    .clamp_range(2..=256),
+            );
+            ui.add(
+                egui::Slider::new(&mut atlas.mountain_height, 0.0..=1.0)
+                    .text("Mountains from height"),
+            );
+            ui.add(egui::Slider::new(&mut atlas.hill_height, 0.0..=1.0).text("Hills from height"));
+            ui.add(
+                egui::DragValue::new(&mut atlas.min_slope)
+                    .prefix("Glyphs on slopes from: ")
+                    .suffix("°")
+                    .clamp_range(0.0..=90.0),
+            );
+            ui.add(
+                egui::DragValue::new(&mut atlas.river_threshold)
+                    .prefix("Rivers from: ")
+                    .suffix(" cells")
+                    .clamp_range(2.0..=f32::MAX),
+            );
+            if ui.button("Export atlas map (SVG)").clicked() {
+                atlas::write_atlas(terrain, atlas, bounds, "atlas.svg").unwrap();
+            }
+            ui.separator();
+            let settings = &mut panel.grid;
+            egui::ComboBox::from_label("Grid")
+                .selected_text(settings.shape.name())
+                .show_ui(ui, |ui| {
+                    for shape in GridShape::ALL {
+                        ui.selectable_value(&mut settings.shape, shape, shape.name());
+                    }
+                });
+            ui.add(
+                egui::DragValue::new(&mut settings.size)
+                    .prefix("Tile size: ")
+                    .suffix(" units")
+                    .speed(0.1)
+                    .clamp_range(0.01..=f32::MAX),
+            );
+            ui.add(egui::Slider::new(&mut settings.water, 0.0..=1.0).text("Water from share"));
+            ui.add(
+                egui::Slider::new(&mut settings.mountain_height, 0.0..=1.0)
+                    .text("Mountains from height"),
+            );
+            ui.add(
+                egui::Slider::new(&mut settings.hill_height, 0.0..=1.0).text("Hills from height"),
+            );
+            ui.add(
+                egui::Slider::new(&mut settings.hill_slope, 0.0..=1.0)
+                    .text("Hills from slope rank"),
+            );
+            ui.add(
+                egui::Slider::new(&mut settings.forest_slope, 0.0..=1.0)
+                    .text("Forest from slope rank"),
+            );
+            if ui.button("Export grid (CSV, JSON, SVG)").clicked() {
+                let tiles = grid::tiles(terrain, settings, bounds);
+                std::fs::write("grid.csv", grid::to_csv(&tiles)).unwrap();
+                std::fs::write("grid.json", grid::to_json(&tiles, settings)).unwrap();
+                let features = grid::features(&tiles, settings);
+                vector::write_svg(&features, grid::svg_style, "grid.svg").unwrap();
+            }
         });
+    panel.open = open;
+}
+
+fn analysis_window(
+    ctx: &egui::Context,
+    terrain: &Terrain,
+    panel: &mut AnalysisPanel,
+    layer_mask: &mut LayerMaskSetting,
+) {
+    let mut open = panel.open;
+    egui::Window::new("Analysis")
+        .open(&mut open)
+        .show(ctx, |ui| {
+            egui::ComboBox::from_label("Layer")
+                .selected_text(panel.layer.name())
+                .show_ui(ui, |ui| {
+                    for layer in Layer::ALL {
+                        ui.selectable_value(&mut panel.layer, layer, layer.name());
+                    }
+                });
+            let file_name = panel.layer.name().to_lowercase().replace(' ', "_");
+            if ui.button("Export layer (EXR)").clicked() {
+                if let Some(bounds) = terrain.data.bounds() {
+                    let layer = analysis::compute(terrain, panel.layer, bounds);
+                    analysis::save_to_exr(&layer, format!("{}.exr", file_name)).unwrap();
+                }
+            }
+            ui.separator();
+            if panel.mask.layer != panel.layer {
+                panel.mask = LayerMask::new(panel.layer);
+            }
+            let speed = match panel.layer {
+                Layer::PlanCurvature | Layer::ProfileCurvature => 0.001,
+                _ => 0.1,
+            };
+            ui.add(
+                egui::DragValue::new(&mut panel.mask.min)
+                    .prefix("From: ")
+                    .speed(speed),
+            );
+            ui.add(
+                egui::DragValue::new(&mut panel.mask.max)
+                    .prefix("To: ")
+                    .speed(speed),
+            );
+            ui.add(
+                egui::DragValue::new(&mut panel.mask.falloff)
+                    .prefix("Falloff: ")
+                    .speed(speed)
+                    .clamp_range(0.0..=f32::MAX),
+            );
+            ui.checkbox(
+                &mut panel.use_mask,
+                "Limit tools and remapping to this range",
+            );
+            if ui.button("Export mask (PNG)").clicked() {
+                if let Some(bounds) = terrain.data.bounds() {
+                    let weights = panel.mask.weights(terrain, bounds);
+                    analysis::save_weights_png(&weights, format!("{}_mask.png", file_name))
+                        .unwrap();
+                }
+            }
+            ui.separator();
+            egui::ComboBox::from_label("Flow")
+                .selected_text(panel.flow_method.name())
+                .show_ui(ui, |ui| {
+                    for method in FlowMethod::ALL {
+                        ui.selectable_value(&mut panel.flow_method, method, method.name());
+                    }
+                });
+            ui.checkbox(&mut panel.fill, "Fill depressions first");
+            ui.add(
+                egui::DragValue::new(&mut panel.fill_epsilon)
+                    .prefix("Epsilon: ")
+                    .speed(0.0001)
+                    .clamp_range(0.0..=1.0),
+            );
+            let fill_epsilon = Some(panel.fill_epsilon).filter(|_| panel.fill);
+            if ui.button("Export flow direction (EXR)").clicked() {
+                if let Some(flow) = Flow::for_terrain(terrain, panel.flow_method, fill_epsilon) {
+                    analysis::save_to_exr(&flow.direction(), "flow_direction.exr").unwrap();
+                }
+            }
+            if ui.button("Export flow accumulation (EXR)").clicked() {
+                if let Some(flow) = Flow::for_terrain(terrain, panel.flow_method, fill_epsilon) {
+                    analysis::save_to_exr(&flow.accumulation(), "flow_accumulation.exr").unwrap();
+                }
+            }
+            ui.add(
+                egui::DragValue::new(&mut panel.river_threshold)
+                    .prefix("Rivers from: ")
+                    .suffix(" cells")
+                    .clamp_range(2.0..=f32::MAX),
+            );
+            if ui.button("Export rivers (GeoJSON)").clicked() {
+                if let Some(flow) = Flow::for_terrain(terrain, panel.flow_method, fill_epsilon) {
+                    let accumulation = flow.accumulation();
+                    let features: Vec<Feature> =
+                        hydrology::rivers(terrain, &flow, &accumulation, panel.river_threshold)
+                            .into_iter()
+                            .map(|river| {
+                                Feature::new(Geometry::LineString(river.points))
+                                    .with("order", river.order as f64)
+                                    .with("accumulation", river.accumulation as f64)
+                            })
+                            .collect();
+                    vector::write_geojson(&features, "rivers.geojson").unwrap();
+                }
+            }
+            if ui.button("Export lakes (EXR, JSON)").clicked() {
+                if let Some(bounds) = terrain.data.bounds() {
+                    let lakes = Lakes::new(terrain, &terrain.data.region(bounds));
+                    analysis::save_to_exr(&lakes.depth, "lake_depth.exr").unwrap();
+                    std::fs::write("lakes.json", lakes.to_json()).unwrap();
+                }
+            }
+            ui.add(
+                egui::DragValue::new(&mut panel.basin_min_cells)
+                    .prefix("Basins from: ")
+                    .suffix(" cells")
+                    .clamp_range(1..=usize::MAX),
+            );
+            if ui.button("Export basins (EXR, GeoJSON)").clicked() {
+                if let Some(flow) = Flow::for_terrain(terrain, panel.flow_method, fill_epsilon) {
+                    let basins = Basins::new(&flow, terrain.cell_size);
+                    analysis::save_to_exr(&basins.label_image(), "basins.exr").unwrap();
+                    let features = basins.polygons(terrain.cell_size, panel.basin_min_cells);
+                    vector::write_geojson(&features, "basins.geojson").unwrap();
+                }
+            }
+        });
+    panel.open = open;
+    layer_mask.0 = Some(panel.mask).filter(|_| panel.use_mask);
+}
+
+fn river_panel(
+    ui: &mut egui::Ui,
+    terrain: &mut Terrain,
+    points: &mut RiverPoints,
+    panel: &mut RiverPanel,
+    mask: Option<&LayerMask>,
+) {
+    let settings = &mut panel.settings;
+    ui.checkbox(&mut settings.follow_flow, "Follow the water downhill");
+    for (label, range) in [
+        ("Width", &mut settings.width),
+        ("Depth", &mut settings.depth),
+    ] {
+        ui.horizontal(|ui| {
+            ui.label(label);
+            ui.add(
+                egui::DragValue::new(&mut range.0)
+                    .prefix("source: ")
+                    .speed(0.1)
+                    .clamp_range(0.0..=f32::MAX),
+            );
+            ui.add(
+                egui::DragValue::new(&mut range.1)
+                    .prefix("mouth: ")
+                    .speed(0.1)
+                    .clamp_range(0.0..=f32::MAX),
+            );
+        });
+    }
+    ui.add(
+        egui::DragValue::new(&mut settings.bank)
+            .prefix("Banks: ")
+            .speed(0.1)
+            .clamp_range(0.0..=f32::MAX),
+    );
+    if settings.follow_flow {
+        ui.label("Click the source of the river");
+    } else {
+        ui.label(format!("{} points, click to add more", points.0.len()));
+    }
+    ui.horizontal(|ui| {
+        if ui.button("Carve").clicked() {
+            let cells: Vec<_> = points
+                .0
+                .iter()
+                .map(|point| terrain.world_to_cells(*point))
+                .collect();
+            let path = if settings.follow_flow {
+                cells.last().map_or(Vec::new(), |source| {
+                    river::flow_path(terrain, *source, &mut panel.flow)
+                })
+            } else {
+                cells
+            };
+            river::carve(&path, settings, terrain, mask);
+            points.0.clear();
+        }
+        if ui.button("Clear").clicked() {
+            points.0.clear();
+        }
     });
 }
+
+fn stats_window(
+    ctx: &egui::Context,
+    terrain: &Terrain,
+    panel: &mut StatsPanel,
+    selection: &Selection,
+) {
+    use egui::plot::{Bar, BarChart, Plot};
+    if !panel.open {
+        return;
+    }
+    let mut open = panel.open;
+    egui::Window::new("Statistics")
+        .open(&mut open)
+        .show(ctx, |ui| {
+            ui.add_enabled_ui(selection.0.is_some(), |ui| {
+                ui.checkbox(&mut panel.selection_only, "Only the selection");
+            });
+            ui.add(
+                egui::DragValue::new(&mut panel.bin_width)
+                    .prefix("Bin width: ")
+                    .speed(0.1)
+                    .clamp_range(0.01..=f32::MAX),
+            );
+            let selection = selection.0.filter(|_| panel.selection_only);
+            let stats = panel.cache.stats(terrain, panel.bin_width, selection);
+            egui::Grid::new("stats_grid").show(ui, |ui| {
+                let mut row = |name: &str, value: String| {
+                    ui.label(name);
+                    ui.label(value);
+                    ui.end_row();
+                };
+                row("Cells", stats.cells.to_string());
+                if stats.cells > 0 {
+                    row("Min", format!("{:.2}", stats.min));
+                    row("Max", format!("{:.2}", stats.max));
+                }
+                row("Mean", format!("{:.2}", stats.mean()));
+                row("Std dev", format!("{:.2}", stats.std_dev()));
+                row("Area", format!("{:.0}", stats.area()));
+                row(
+                    "Land area",
+                    format!(
+                        "{:.0} ({:.1}%)",
+                        stats.land_area(),
+                        100.0 * stats.land_area() / stats.area().max(f64::MIN_POSITIVE)
+                    ),
+                );
+                row("Volume", format!("{:.0}", stats.volume));
+                row("Land volume", format!("{:.0}", stats.land_volume));
+            });
+            ui.label("Height");
+            let bars = stats
+                .height_bins()
+                .iter()
+                .map(|&(height, count)| {
+                    Bar::new((height + stats.bin_width / 2.0) as f64, count as f64)
+                        .width(stats.bin_width as f64)
+                })
+                .collect();
+            Plot::new("height_histogram")
+                .height(120.0)
+                .allow_drag(false)
+                .allow_zoom(false)
+                .show(ui, |plot_ui| plot_ui.bar_chart(BarChart::new(bars)));
+            ui.label("Slope (degrees)");
+            let bars = stats
+                .slopes
+                .iter()
+                .enumerate()
+                .map(|(degrees, &count)| Bar::new(degrees as f64, count as f64).width(1.0))
+                .collect();
+            Plot::new("slope_histogram")
+                .height(120.0)
+                .allow_drag(false)
+                .allow_zoom(false)
+                .show(ui, |plot_ui| plot_ui.bar_chart(BarChart::new(bars)));
+        });
+    panel.open = open;
+}
+
+fn remap_window(
+    ctx: &egui::Context,
+    terrain: &mut Terrain,
+    settings: &mut RemapSettings,
+    selection: &Selection,
+    layer_mask: &LayerMaskSetting,
+) {
+    let mut open = settings.open;
+    let mut apply = false;
+    egui::Window::new("Remap heights")
+        .open(&mut open)
+        .show(ctx, |ui| {
+            let height = terrain.height;
+            let kinds = [
+                Remap::Normalise {
+                    min: 0.0,
+                    max: height,
+                },
+                Remap::Clamp {
+                    min: 0.0,
+                    max: height,
+                },
+                Remap::Gamma(1.0),
+                Remap::Curve(Curve::default()),
+                Remap::Terrace {
+                    step: height / 8.0,
+                    smoothing: 0.2,
+                },
+            ];
+            egui::ComboBox::from_label("Operation")
+                .selected_text(settings.remap.name())
+                .show_ui(ui, |ui| {
+                    for kind in kinds {
+                        if ui
+                            .selectable_label(settings.remap.name() == kind.name(), kind.name())
+                            .clicked()
+                            && settings.remap.name() != kind.name()
+                        {
+                            settings.remap = kind;
+                        }
+                    }
+                });
+            match &mut settings.remap {
+                Remap::Normalise { min, max } | Remap::Clamp { min, max } => {
+                    ui.add(egui::DragValue::new(min).prefix("Min: "));
+                    ui.add(egui::DragValue::new(max).prefix("Max: "));
+                }
+                Remap::Gamma(gamma) => {
+                    ui.add(egui::Slider::new(gamma, 0.1..=5.0).text("Gamma"));
+                }
+                Remap::Curve(curve) => curve_editor(ui, curve),
+                Remap::Terrace { step, smoothing } => {
+                    ui.add(
+                        egui::DragValue::new(step)
+                            .prefix("Step: ")
+                            .speed(0.1)
+                            .clamp_range(0.01..=f32::MAX),
+                    );
+                    ui.add(egui::Slider::new(smoothing, 0.0..=1.0).text("Smoothing"));
+                }
+            }
+            ui.separator();
+            ui.add_enabled_ui(selection.0.is_some(), |ui| {
+                ui.checkbox(&mut settings.masked, "Only the selection");
+                ui.add(
+                    egui::DragValue::new(&mut settings.feather)
+                        .prefix("Feather: ")
+                        .suffix(" cells")
+                        .clamp_range(0..=256),
+                );
+            });
+            ui.add_enabled_ui(layer_mask.0.is_some(), |ui| {
+                ui.checkbox(&mut settings.layer_masked, "Only where the layer mask is");
+            });
+            ui.checkbox(&mut settings.preview, "Preview");
+            if settings.original.is_some() {
+                ui.label("Other edits wait until the preview is applied or closed.");
+            }
+            apply = ui.button("Apply").clicked();
+        });
+    settings.open = open;
+
+    let mask_bounds = selection
+        .0
+        .filter(|_| settings.masked)
+        .map(|bounds| (bounds, settings.feather));
+    let layer_mask = layer_mask.0.filter(|_| settings.layer_masked);
+    let mask = |terrain: &Terrain,
+                mask_bounds: Option<(Bounds, i32)>,
+                layer_mask: Option<LayerMask>| {
+        let mut mask = mask_bounds.map(|(bounds, feather)| remap::rectangle_mask(bounds, feather));
+        if let Some(layer_mask) = layer_mask {
+            let bounds = match (&mask, terrain.data.bounds()) {
+                (Some(mask), _) => mask.bounds,
+                (None, Some(bounds)) => bounds,
+                (None, None) => return mask,
+            };
+            let mut weights = layer_mask.weights(terrain, bounds);
+            if let Some(mask) = &mask {
+                for (weight, other) in weights.data.iter_mut().zip(&mask.data) {
+                    *weight *= other;
+                }
+            }
+            mask = Some(weights);
+        }
+        mask
+    };
+    if apply {
+        if settings.original.take().is_none() {
+            let mask = mask(terrain, mask_bounds, layer_mask);
+            terrain.data.remap(&settings.remap, mask.as_ref());
+        }
+        // Otherwise the preview already shows the result.
+        settings.previewed = None;
+        settings.open = false;
+    } else if settings.open && settings.preview {
+        if settings.original.is_none() {
+            settings.original = Some(terrain.data.clone());
+        }
+        let current = (settings.remap.clone(), mask_bounds, layer_mask);
+        if settings.previewed.as_ref() != Some(&current) {
+            // Weights of the layer come from the terrain as it was before the preview.
+            let original = Terrain {
+                data: settings.original.clone().unwrap(),
+                ..*terrain
+            };
+            let mask = mask(&original, mask_bounds, layer_mask);
+            let mut data = original.data;
+            data.remap(&current.0, mask.as_ref());
+            terrain.data.replace(data);
+            settings.previewed = Some(current);
+        }
+    } else if let Some(original) = settings.original.take() {
+        // Closed or no longer previewing without applying.
+        terrain.data.replace(original);
+        settings.previewed = None;
+    }
+}
+
+// Plot of the curve with its control points, and a row of values for each point.
+fn curve_editor(ui: &mut egui::Ui, curve: &mut Curve) {
+    use egui::plot::{Line, Plot, Points, Value, Values};
+    let spline = curve.spline();
+    let line = Line::new(Values::from_values_iter((0..=100).map(|i| {
+        let x = i as f32 / 100.0;
+        Value::new(x, spline.get(x))
+    })));
+    let points = Points::new(Values::from_values_iter(
+        curve.points.iter().map(|&(x, y)| Value::new(x, y)),
+    ))
+    .radius(4.0);
+    Plot::new("remap_curve")
+        .height(160.0)
+        .data_aspect(1.0)
+        .allow_drag(false)
+        .allow_zoom(false)
+        .include_x(0.0)
+        .include_x(1.0)
+        .include_y(0.0)
+        .include_y(1.0)
+        .show(ui, |plot_ui| {
+            plot_ui.line(line);
+            plot_ui.points(points);
+        });
+    let mut removed = None;
+    for (i, (x, y)) in curve.points.iter_mut().enumerate() {
+        ui.horizontal(|ui| {
+            ui.add(
+                egui::DragValue::new(x)
+                    .prefix("In: ")
+                    .speed(0.01)
+                    .clamp_range(0.0..=1.0),
+            );
+            ui.add(
+                egui::DragValue::new(y)
+                    .prefix("Out: ")
+                    .speed(0.01)
+                    .clamp_range(0.0..=1.0),
+            );
+            if ui.button("Remove").clicked() {
+                removed = Some(i);
+            }
+        });
+    }
+    if let Some(i) = removed {
+        curve.points.remove(i);
+    }
+    if ui.button("Add point").clicked() {
+        curve.points.push((0.5, spline.get(0.5)));
+    }
+}
@@ -0,0 +1,290 @@
+// Right-triangulated irregular network (RTIN) over a square grid of 2^n + 1 heights, after
+// Mapbox's Martini. Every triangle is a right triangle that splits in two at the midpoint of its
+// hypotenuse, and the error at that midpoint covers the triangle and all of its children, so
+// cutting off the split as soon as the error is small enough gives a crack-free mesh within that
+// error.
+pub struct Rtin {
+    size: usize,
+    // Largest error of everything below the triangles split at each grid point.
+    errors: Vec<f32>,
+    // Corners of the rectangle meshes are cut to, inclusive.
+    rectangle: [(usize, usize); 2],
+}
+
+impl Rtin {
+    // heights is size * size values, row by row, and size has to be 2^n + 1.
+    pub fn new(heights: &[f32], size: usize) -> Self {
+        Self::build(heights, size, None)
+    }
+
+    // Like new, but meshes always keep every height along the border, so neighbouring grids meet
+    // vertex for vertex whatever their error.
+    pub fn with_border(heights: &[f32], size: usize) -> Self {
+        Self::build(heights, size, Some([(0, 0), (size - 1, size - 1)]))
+    }
+
+    // Like with_border, for meshes of just the rectangle between two grid points: no triangle
+    // crosses its border, every height along it is kept, and meshes leave out everything outside.
+    pub fn with_rectangle(heights: &[f32], size: usize, rectangle: [(usize, usize); 2]) -> Self {
+        Self::build(heights, size, Some(rectangle))
+    }
+
+    fn build(heights: &[f32], size: usize, kept: Option<[(usize, usize); 2]>) -> Self {
+        let tile_size = size - 1;
+        assert!(
+            tile_size.is_power_of_two() && heights.len() == size * size,
+            "RTIN grids have to be 2^n + 1 heights wide"
+        );
+
+        // Hypotenuse end points of every triangle in the hierarchy, parents before children.
+        let triangles = tile_size * tile_size * 2 - 2;
+        let mut coords = Vec::with_capacity(triangles);
+        for i in 0..triangles {
+            let mut id = i + 2;
+            let (mut ax, mut ay, mut bx, mut by, mut cx, mut cy) = (0, 0, 0, 0, 0, 0);
+            if id & 1 == 1 {
+                bx = tile_size;
+                by = tile_size;
+                cx = tile_size;
+            } else {
+                ax = tile_size;
+                ay = tile_size;
+                cy = tile_size;
+            }
+            id >>= 1;
+            while id > 1 {
+                let (mx, my) = ((ax + bx) / 2, (ay + by) / 2);
+                if id & 1 == 1 {
+                    bx = ax;
+                    by = ay;
+                    ax = cx;
+                    ay = cy;
+                } else {
+                    ax = bx;
+                    ay = by;
+                    bx = cx;
+                    by = cy;
+                }
+                cx = mx;
+                cy = my;
+                id >>= 1;
+            }
+            coords.push([ax, ay, bx, by]);
+        }
+
+        // Children come after their parents, so going backwards every child is done before its parent.
+        let parents = triangles - tile_size * tile_size;
+        let mut errors = vec![0.0f32; size * size];
+        for (i, &[ax, ay, bx, by]) in coords.iter().enumerate().rev() {
+            let (mx, my) = ((ax + bx) / 2, (ay + by) / 2);
+            let (cx, cy) = (mx + my - ay, my + ax - mx);
+            let middle = my * size + mx;
+            let mut error =
+                errors[middle].max(surface_error(heights, size, [(ax, ay), (bx, by), (cx, cy)]));
+            // An infinite error forces the split, and is passed on to every ancestor.
+            if let Some([min, max]) = kept {
+                let on_border = ((mx == min.0 || mx == max.0) && (min.1..=max.1).contains(&my))
+                    || ((my == min.1 || my == max.1) && (min.0..=max.0).contains(&mx));
+                // Strictly across one of the border's lines, where it runs past the triangle.
+                let (low_x, high_x) = (ax.min(bx).min(cx), ax.max(bx).max(cx));
+                let (low_y, high_y) = (ay.min(by).min(cy), ay.max(by).max(cy));
+                let across = |low: usize, high: usize, line: usize| low < line && line < high;
+                let crosses = (high_y > min.1 && low_y < max.1)
+                    && (across(low_x, high_x, min.0) || across(low_x, high_x, max.0))
+                    || (high_x > min.0 && low_x < max.0)
+                        && (across(low_y, high_y, min.1) || across(low_y, high_y, max.1));
+                if on_border || crosses {
+                    error = f32::INFINITY;
+                }
+            }
+            if i < parents {
+                let left = ((ay + cy) / 2) * size + (ax + cx) / 2;
+                let right = ((by + cy) / 2) * size + (bx + cx) / 2;
+                error = error.max(errors[left]).max(errors[right]);
+            }
+            errors[middle] = error;
+        }
+
+        Self {
+            size,
+            errors,
+            rectangle: kept.unwrap_or([(0, 0), (tile_size, tile_size)]),
+        }
+    }
+
+    // Grid coordinates of the vertices and the triangles between them, for the coarsest mesh that
+    // stays within max_error of every height. Triangles have the same winding as the regular grid
+    // in generate_mesh, where x is the first coordinate and z the second.
+    pub fn mesh(&self, max_error: f32) -> (Vec<(usize, usize)>, Vec<u32>) {
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        let mut vertex_of = vec![u32::MAX; self.size * self.size];
+        let max = self.size - 1;
+        let mut stack = vec![[0, 0, max, max, max, 0], [max, max, 0, 0, 0, max]];
+        while let Some([ax, ay, bx, by, cx, cy]) = stack.pop() {
+            let (mx, my) = ((ax + bx) / 2, (ay + by) / 2);
+            if ax.abs_diff(cx) + ay.abs_diff(cy) > 1 && self.errors[my * self.size + mx] > max_error
+            {
+                stack.push([cx, cy, ax, ay, mx, my]);
+                stack.push([bx, by, cx, cy, mx, my]);
+                continue;
+            }
+            // Triangles never cross the rectangle, so their centre says which side they're on.
+            let [min, max] = self.rectangle;
+            let (x3, y3) = (ax + bx + cx, ay + by + cy);
+            if x3 < 3 * min.0 || x3 > 3 * max.0 || y3 < 3 * min.1 || y3 > 3 * max.1 {
+                continue;
+            }
+            let mut corners = [(ax, ay), (bx, by), (cx, cy)];
+            let cross = (corners[1].0 as i64 - ax as i64) * (corners[2].1 as i64 - ay as i64)
+                - (corners[1].1 as i64 - ay as i64) * (corners[2].0 as i64 - ax as i64);
+            if cross > 0 {
+                corners.swap(1, 2);
+            }
+            for (x, y) in corners {
+                let vertex = &mut vertex_of[y * self.size + x];
+                if *vertex == u32::MAX {
+                    *vertex = vertices.len() as u32;
+                    vertices.push((x, y));
+                }
+                indices.push(*vertex);
+            }
+        }
+        (vertices, indices)
+    }
+}
+
+// Largest difference between the flat triangle and the heights at the grid points it covers.
+// Checking the midpoint of the hypotenuse alone would let the errors of split children add up.
+fn surface_error(heights: &[f32], size: usize, corners: [(usize, usize); 3]) -> f32 {
+    let [a, b, c] = corners.map(|(x, y)| (x as i64, y as i64, heights[y * size + x]));
+    let edge = |p: (i64, i64, f32), q: (i64, i64, f32), x: i64, y: i64| {
+        (q.0 - p.0) * (y - p.1) - (q.1 - p.1) * (x - p.0)
+    };
+    let area = edge(a, b, c.0, c.1) as f32;
+    let mut error: f32 = 0.0;
+    for y in a.1.min(b.1).min(c.1)..=a.1.max(b.1).max(c.1) {
+        for x in a.0.min(b.0).min(c.0)..=a.0.max(b.0).max(c.0) {
+            let (wa, wb, wc) = (edge(b, c, x, y), edge(c, a, x, y), edge(a, b, x, y));
+            // Inside or on an edge, whichever way the corners wind.
+            if (wa >= 0 && wb >= 0 && wc >= 0) || (wa <= 0 && wb <= 0 && wc <= 0) {
+                let surface = (wa as f32 * a.2 + wb as f32 * b.2 + wc as f32 * c.2) / area;
+                error = error.max((surface - heights[y as usize * size + x as usize]).abs());
+            }
+        }
+    }
+    error
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: usize = 33;
+
+    fn bumpy() -> Vec<f32> {
+        (0..SIZE * SIZE)
+            .map(|i| {
+                let (x, y) = ((i % SIZE) as f32, (i / SIZE) as f32);
+                (x * 0.3).sin() * 4.0 + (y * 0.2).cos() * 3.0 + (x * y * 0.05).sin()
+            })
+            .collect()
+    }
+
+    // Largest difference between the mesh and the heights at any grid point. Every grid point
+    // lies inside or on the edge of some triangle.
+    fn largest_error(heights: &[f32], vertices: &[(usize, usize)], indices: &[u32]) -> f32 {
+        let mut largest: f32 = 0.0;
+        let mut covered = vec![false; SIZE * SIZE];
+        for triangle in indices.chunks(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| {
+                let (x, y) = vertices[triangle[i] as usize];
+                (x as f32, y as f32, heights[y * SIZE + x])
+            });
+            let area = (b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0);
+            let (min_x, max_x) = (a.0.min(b.0).min(c.0), a.0.max(b.0).max(c.0));
+            let (min_y, max_y) = (a.1.min(b.1).min(c.1), a.1.max(b.1).max(c.1));
+            for y in min_y as usize..=max_y as usize {
+                for x in min_x as usize..=max_x as usize {
+                    let (px, py) = (x as f32, y as f32);
+                    let wa = ((b.0 - px) * (c.1 - py) - (b.1 - py) * (c.0 - px)) / area;
+                    let wb = ((c.0 - px) * (a.1 - py) - (c.1 - py) * (a.0 - px)) / area;
+                    let wc = 1.0 - wa - wb;
+                    if wa < -1e-6 || wb < -1e-6 || wc < -1e-6 {
+                        continue;
+                    }
+                    let height = wa * a.2 + wb * b.2 + wc * c.2;
+                    largest = largest.max((height - heights[y * SIZE + x]).abs());
+                    covered[y * SIZE + x] = true;
+                }
+            }
+        }
+        assert!(covered.iter().all(|covered| *covered));
+        largest
+    }
+
+    #[test]
+    fn flat_grid_is_two_triangles() {
+        let (vertices, indices) = Rtin::new(&vec![1.5; SIZE * SIZE], SIZE).mesh(0.0);
+        assert_eq!(vertices.len(), 4);
+        assert_eq!(indices.len(), 6);
+    }
+
+    #[test]
+    fn mesh_stays_within_max_error() {
+        let heights = bumpy();
+        let rtin = Rtin::new(&heights, SIZE);
+        let mut previous = usize::MAX;
+        for max_error in [0.0, 0.1, 0.5, 2.0] {
+            let (vertices, indices) = rtin.mesh(max_error);
+            assert!(largest_error(&heights, &vertices, &indices) <= max_error + 1e-4);
+            assert!(indices.len() <= previous);
+            previous = indices.len();
+        }
+        // No error allowed keeps every height.
+        assert_eq!(rtin.mesh(0.0).0.len(), SIZE * SIZE);
+    }
+
+    #[test]
+    fn border_is_kept() {
+        let heights = bumpy();
+        let rtin = Rtin::with_border(&heights, SIZE);
+        let (vertices, indices) = rtin.mesh(2.0);
+        let last = SIZE - 1;
+        let border = vertices
+            .iter()
+            .filter(|(x, y)| *x == 0 || *y == 0 || *x == last || *y == last)
+            .count();
+        assert_eq!(border, 4 * last);
+        assert!(largest_error(&heights, &vertices, &indices) <= 2.0 + 1e-4);
+        // The inside is still simplified.
+        assert!(vertices.len() < SIZE * SIZE);
+    }
+
+    #[test]
+    fn rectangle_is_cut_out() {
+        let heights = bumpy();
+        let [min, max] = [(5, 3), (20, 29)];
+        let (vertices, indices) = Rtin::with_rectangle(&heights, SIZE, [min, max]).mesh(1.0);
+        assert!(vertices
+            .iter()
+            .all(|(x, y)| (min.0..=max.0).contains(x) && (min.1..=max.1).contains(y)));
+        let border = vertices
+            .iter()
+            .filter(|(x, y)| *x == min.0 || *y == min.1 || *x == max.0 || *y == max.1)
+            .count();
+        assert_eq!(border, 2 * (max.0 - min.0 + max.1 - min.1));
+        // Nothing overlaps and nothing is missing, so the triangles add up to the rectangle.
+        let area: i64 = indices
+            .chunks(3)
+            .map(|triangle| {
+                let [a, b, c] = [0, 1, 2].map(|i| {
+                    let (x, y) = vertices[triangle[i] as usize];
+                    (x as i64, y as i64)
+                });
+                ((b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0)).abs()
+            })
+            .sum();
+        assert_eq!(area, 2 * ((max.0 - min.0) * (max.1 - min.1)) as i64);
+    }
+}
@@ -0,0 +1,365 @@
+use crate::petra::mesh::terrain_normal;
+use crate::petra::sampler::{EdgePolicy, Interpolation, Sampler};
+use crate::petra::terrain::{Bounds, Terrain, TerrainData};
+use rayon::prelude::*;
+use std::collections::{BTreeMap, HashMap};
+
+// Summary of the heights in a terrain. Every field adds up over cells, so stats of separate chunks
+// merge into the stats of all of them, and only chunks that changed have to be counted again.
+#[derive(Debug, Clone)]
+pub struct Stats {
+    pub cells: u64,
+    pub min: f32,
+    pub max: f32,
+    sum: f64,
+    sum_squares: f64,
+    // Cells per height bin, keyed by floor(height / bin_width).
+    pub histogram: BTreeMap<i64, u64>,
+    pub bin_width: f32,
+    // Cells per whole degree of slope, 0 to 90.
+    pub slopes: [u64; 91],
+    pub land_cells: u64,
+    // Area of a single cell, in square world units.
+    pub cell_area: f64,
+    // Height times area of every cell, measured from height 0, so valleys below 0 count against it.
+    pub volume: f64,
+    // Volume above sea level, of the land only.
+    pub land_volume: f64,
+}
+
+// Everything about the terrain the stats depend on, besides the heights.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StatsSettings {
+    pub bin_width: f32,
+    pub sea_level: f32,
+    pub cell_size: f32,
+    pub policy: EdgePolicy,
+    pub interpolation: Interpolation,
+    // Bounds of every chunk, which Clamp and Wrap read across.
+    pub world: Option<Bounds>,
+}
+
+impl StatsSettings {
+    pub fn new(terrain: &Terrain, bin_width: f32) -> Self {
+        Self {
+            bin_width,
+            sea_level: terrain.sea_level,
+            cell_size: terrain.cell_size,
+            policy: terrain.edge_policy,
+            interpolation: terrain.interpolation,
+            world: terrain.data.bounds(),
+        }
+    }
+}
+
+impl Stats {
+    pub fn new(settings: &StatsSettings) -> Self {
+        Self {
+            cells: 0,
+            min: f32::INFINITY,
+            max: f32::NEG_INFINITY,
+            sum: 0.0,
+            sum_squares: 0.0,
+            histogram: BTreeMap::new(),
+            bin_width: settings.bin_width,
+            slopes: [0; 91],
+            land_cells: 0,
+            cell_area: (settings.cell_size * settings.cell_size) as f64,
+            volume: 0.0,
+            land_volume: 0.0,
+        }
+    }
+
+    fn add(&mut self, height: f32, slope: f32, sea_level: f32) {
+        self.cells += 1;
+        self.min = self.min.min(height);
+        self.max = self.max.max(height);
+        self.sum += height as f64;
+        self.sum_squares += height as f64 * height as f64;
+        *self
+            .histogram
+            .entry((height / self.bin_width).floor() as i64)
+            .or_insert(0) += 1;
+        self.slopes[(slope.round() as usize).min(90)] += 1;
+        self.volume += height as f64 * self.cell_area;
+        if height > sea_level {
+            self.land_cells += 1;
+            self.land_volume += (height - sea_level) as f64 * self.cell_area;
+        }
+    }
+
+    pub fn merge(&mut self, other: &Stats) {
+        self.cells += other.cells;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.sum += other.sum;
+        self.sum_squares += other.sum_squares;
+        for (bin, count) in &other.histogram {
+            *self.histogram.entry(*bin).or_insert(0) += count;
+        }
+        for (slope, count) in self.slopes.iter_mut().zip(other.slopes) {
+            *slope += count;
+        }
+        self.land_cells += other.land_cells;
+        self.volume += other.volume;
+        self.land_volume += other.land_volume;
+    }
+
+    pub fn mean(&self) -> f32 {
+        if self.cells == 0 {
+            return 0.0;
+        }
+        (self.sum / self.cells as f64) as f32
+    }
+
+    pub fn std_dev(&self) -> f32 {
+        if self.cells == 0 {
+            return 0.0;
+        }
+        let mean = self.sum / self.cells as f64;
+        (self.sum_squares / self.cells as f64 - mean * mean)
+            .max(0.0)
+            .sqrt() as f32
+    }
+
+    pub fn area(&self) -> f64 {
+        self.cells as f64 * self.cell_area
+    }
+
+    pub fn land_area(&self) -> f64 {
+        self.land_cells as f64 * self.cell_area
+    }
+
+    // Lower edge of each height bin and the cells in it, with empty bins in between filled in.
+    pub fn height_bins(&self) -> Vec<(f32, u64)> {
+        let (first, last) = match (self.histogram.keys().next(), self.histogram.keys().last()) {
+            (Some(first), Some(last)) => (*first, *last),
+            _ => return Vec::new(),
+        };
+        (first..=last)
+            .map(|bin| {
+                (
+                    bin as f32 * self.bin_width,
+                    *self.histogram.get(&bin).unwrap_or(&0),
+                )
+            })
+            .collect()
+    }
+
+    pub fn to_json(&self) -> String {
+        let join = |values: Vec<String>| values.join(",");
+        let height_bins = join(
+            self.height_bins()
+                .iter()
+                .map(|(height, count)| format!("[{},{}]", height, count))
+                .collect(),
+        );
+        let slopes = join(self.slopes.iter().map(|count| count.to_string()).collect());
+        format!(
+            concat!(
+                "{{\"cells\":{},\"min\":{},\"max\":{},\"mean\":{},\"std_dev\":{},",
+                "\"area\":{},\"land_area\":{},\"volume\":{},\"land_volume\":{},",
+                "\"bin_width\":{},\"height_histogram\":[{}],\"slope_histogram\":[{}]}}"
+            ),
+            self.cells,
+            json_number(self.min),
+            json_number(self.max),
+            self.mean(),
+            self.std_dev(),
+            self.area(),
+            self.land_area(),
+            self.volume,
+            self.land_volume,
+            self.bin_width,
+            height_bins,
+            slopes,
+        )
+    }
+}
+
+// JSON has no infinities, which is what min and max are without any cells.
+fn json_number(value: f32) -> String {
+    if value.is_finite() {
+        value.to_string()
+    } else {
+        "null".to_string()
+    }
+}
+
+// Stats of the cells of one chunk that fall inside the bounds. Slopes come from the same normals
+// as the mesh.
+fn chunk_stats(
+    data: &TerrainData,
+    coords: (i32, i32),
+    bounds: Bounds,
+    settings: &StatsSettings,
+) -> Stats {
+    let mut stats = Stats::new(settings);
+    let cells = match Bounds::of_chunk(coords, data.chunk_size).intersection(&bounds) {
+        Some(cells) => cells,
+        None => return stats,
+    };
+    let sampler = Sampler::with_bounds(
+        data,
+        settings.policy,
+        settings.interpolation,
+        settings.world,
+    );
+    for y in cells.min.1..cells.max.1 {
+        for x in cells.min.0..cells.max.0 {
+            let height = sampler.get_or((x, y), 0.0);
+            let normal = terrain_normal(&sampler, (x, y), settings.cell_size);
+            let slope = normal.z.clamp(-1.0, 1.0).acos().to_degrees();
+            stats.add(height, slope, settings.sea_level);
+        }
+    }
+    stats
+}
+
+enum Counted {
+    // Part of a chunk, at the edge of the selection.
+    Part(Stats),
+    // A whole chunk, and the key to cache it under.
+    Chunk(Key, Stats),
+    Cached,
+}
+
+// Revisions of a chunk and its eight neighbours, since normals read into the neighbours. At the
+// edge of the world, the neighbours are the chunks the edge policy reads instead.
+type Key = [Option<u64>; 9];
+
+// Stats of every chunk that was counted, kept until the chunk or one of its neighbours changes.
+#[derive(Default)]
+pub struct StatsCache {
+    settings: Option<StatsSettings>,
+    chunks: HashMap<(i32, i32), (Key, Stats)>,
+}
+
+impl StatsCache {
+    // Stats of the whole terrain, or of the cells inside the selection. Cells of missing chunks
+    // aren't counted.
+    pub fn stats(&mut self, terrain: &Terrain, bin_width: f32, selection: Option<Bounds>) -> Stats {
+        let settings = StatsSettings::new(terrain, bin_width);
+        if self.settings != Some(settings) {
+            self.chunks.clear();
+            self.settings = Some(settings);
+        }
+        let data = &terrain.data;
+        self.chunks
+            .retain(|coords, _| data.chunks.contains_key(coords));
+        let chunk_size = data.chunk_size;
+        let key = |coords: (i32, i32)| -> Key {
+            let chunk = Bounds::of_chunk(coords, chunk_size);
+            let mut key = [None; 9];
+            for (i, revision) in key.iter_mut().enumerate() {
+                // A cell just across that side or corner of the chunk.
+                let across = |offset: i32, min: i32, max: i32| match offset {
+                    -1 => min - 1,
+                    0 => min,
+                    _ => max,
+                };
+                let cell = (
+                    across(i as i32 % 3 - 1, chunk.min.0, chunk.max.0),
+                    across(i as i32 / 3 - 1, chunk.min.1, chunk.max.1),
+                );
+                *revision = settings
+                    .world
+                    .and_then(|world| settings.policy.resolve(world, cell))
+                    .and_then(|(x, y)| {
+                        let size = chunk_size as i32;
+                        data.chunks.get(&(x.div_euclid(size), y.div_euclid(size)))
+                    })
+                    .map(|chunk| chunk.revision);
+            }
+            key
+        };
+        let cache = &self.chunks;
+        let counted: Vec<((i32, i32), Counted)> = data
+            .chunks
+            .par_iter()
+            .filter_map(|(&coords, _)| {
+                let chunk_bounds = Bounds::of_chunk(coords, chunk_size);
+                let bounds = match selection {
+                    Some(selection) => selection.intersection(&chunk_bounds)?,
+                    None => chunk_bounds,
+                };
+                if bounds != chunk_bounds {
+                    // Part of a chunk is cheap enough to count every time.
+                    let stats = chunk_stats(data, coords, bounds, &settings);
+                    return Some((coords, Counted::Part(stats)));
+                }
+                let key = key(coords);
+                match cache.get(&coords) {
+                    Some((cached, _)) if *cached == key => Some((coords, Counted::Cached)),
+                    _ => Some((
+                        coords,
+                        Counted::Chunk(key, chunk_stats(data, coords, bounds, &settings)),
+                    )),
+                }
+            })
+            .collect();
+
+        let mut total = Stats::new(&settings);
+        for (coords, counted) in counted {
+            match counted {
+                Counted::Part(stats) => total.merge(&stats),
+                Counted::Chunk(key, stats) => {
+                    total.merge(&stats);
+                    self.chunks.insert(coords, (key, stats));
+                }
+                Counted::Cached => total.merge(&self.chunks[&coords].1),
+            }
+        }
+        total
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::petra::generate;
+
+    #[test]
+    fn cache_recounts_only_what_changed() {
+        let mut terrain = Terrain::default();
+        terrain.data = terrain.data.rechunk(16);
+        generate::fbm(&mut terrain, (0, 0), (3, 3), 0);
+        let mut cache = StatsCache::default();
+        cache.stats(&terrain, 1.0, None);
+        let (near, far) = (cache.chunks[&(1, 1)].0, cache.chunks[&(3, 3)].0);
+
+        terrain.data.add_to_cell((5, 5), 10.0);
+        let cached = cache.stats(&terrain, 1.0, None);
+        let fresh = StatsCache::default().stats(&terrain, 1.0, None);
+        assert_eq!(format!("{:?}", cached), format!("{:?}", fresh));
+        // Only the edited chunk and its neighbours get new keys.
+        assert_eq!(cache.chunks[&(3, 3)].0, far);
+        assert_ne!(cache.chunks[&(1, 1)].0, near);
+    }
+
+    #[test]
+    fn cache_follows_wrapped_edges() {
+        let mut terrain = Terrain {
+            edge_policy: EdgePolicy::Wrap,
+            ..Default::default()
+        };
+        terrain.data = terrain.data.rechunk(16);
+        generate::fbm(&mut terrain, (0, 0), (3, 3), 0);
+        let mut cache = StatsCache::default();
+        cache.stats(&terrain, 1.0, None);
+        let opposite = cache.chunks[&(3, 1)].0;
+
+        // Normals along the east edge read the west edge.
+        terrain.data.add_to_cell((0, 20), 10.0);
+        let cached = cache.stats(&terrain, 1.0, None);
+        let fresh = StatsCache::default().stats(&terrain, 1.0, None);
+        assert_eq!(format!("{:?}", cached), format!("{:?}", fresh));
+        assert_ne!(cache.chunks[&(3, 1)].0, opposite);
+
+        // A new chunk moves the edges the others wrap across.
+        terrain.data.add_to_cell((70, 20), 10.0);
+        let cached = cache.stats(&terrain, 1.0, None);
+        let fresh = StatsCache::default().stats(&terrain, 1.0, None);
+        assert_eq!(format!("{:?}", cached), format!("{:?}", fresh));
+    }
+}
@@ -1,28 +1,50 @@
-use bevy::math::{vec2, Vec2};
+use super::cursor::Region;
+use super::sampler::{EdgePolicy, Interpolation};
+use bevy::math::Vec2;
 use image::{ImageFormat::OpenExr, ImageResult, Rgba, Rgba32FImage};
 use std::collections::HashMap;
 use std::f32;
 use std::ops::Index;
 use std::ops::IndexMut;
-const CHUNK_SIZE: usize = 64;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+pub const DEFAULT_CHUNK_SIZE: usize = 64;
+// Chunk sizes have to be powers of two, so that LODs and RTIN simplification fit into a chunk.
+pub const CHUNK_SIZES: [usize; 5] = [16, 32, 64, 128, 256];
+
+// Every new or changed chunk gets a revision no chunk has had before, so caches can tell whether
+// they have seen a chunk as it is now, even after it was replaced or rechunked.
+static REVISIONS: AtomicU64 = AtomicU64::new(0);
 
 // This is mostly meant as a thin layer on top of TerrainData. Most relevant methods will go under TerrainData.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct TerrainDataChunk {
-    pub data: [f32; (CHUNK_SIZE * CHUNK_SIZE) as usize],
+    pub data: Vec<f32>,
     pub coords: (i32, i32),
-    pub modified: bool,
+    pub modified: bool, // Changed since it was last meshed.
+    pub revision: u64,
 }
 
 impl TerrainDataChunk {
-    pub const size: usize = CHUNK_SIZE;
-    pub fn new(coords: (i32, i32)) -> Self {
+    pub fn new(coords: (i32, i32), size: usize) -> Self {
         Self {
-            data: [0.0; Self::size * Self::size],
+            data: vec![0.0; size * size],
             coords,
             modified: false,
+            revision: REVISIONS.fetch_add(1, Ordering::Relaxed),
         }
     }
+
+    // Marks the chunk as changed, for meshing and for anything keyed by its revision.
+    pub fn touch(&mut self) {
+        self.modified = true;
+        self.revision = REVISIONS.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Cells along each side.
+    pub fn size(&self) -> usize {
+        (self.data.len() as f64).sqrt() as usize
+    }
     pub fn get_safe(&self, x: usize, y: usize, x_offset: i32, y_offset: i32) -> Option<f32> {
         let new_x = if x_offset.is_positive() {
             x.checked_add(x_offset as usize)?
@@ -36,30 +58,85 @@ impl TerrainDataChunk {
             y.checked_sub(y_offset.abs() as usize)?
         };
 
-        if new_x >= Self::size || new_y >= Self::size {
+        let size = self.size();
+        if new_x >= size || new_y >= size {
             return None;
         }
 
-        Some(self.data[new_y * TerrainDataChunk::size + new_x])
+        Some(self.data[new_y * size + new_x])
     }
 }
+#[derive(Clone)]
 pub struct TerrainData {
     pub chunks: HashMap<(i32, i32), TerrainDataChunk>,
+    pub chunk_size: usize,
 }
 
-fn lerp(s: f32, e: f32, i: f32) -> f32 {
-    s + (e - s) * i
+// Rectangle of cells. min is inclusive, max is exclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bounds {
+    pub min: (i32, i32),
+    pub max: (i32, i32),
+}
+
+impl Bounds {
+    pub fn contains(&self, cell: (i32, i32)) -> bool {
+        cell.0 >= self.min.0 && cell.0 < self.max.0 && cell.1 >= self.min.1 && cell.1 < self.max.1
+    }
+
+    pub fn width(&self) -> i32 {
+        self.max.0 - self.min.0
+    }
+
+    pub fn height(&self) -> i32 {
+        self.max.1 - self.min.1
+    }
+
+    pub fn intersection(&self, other: &Bounds) -> Option<Bounds> {
+        let bounds = Bounds {
+            min: (self.min.0.max(other.min.0), self.min.1.max(other.min.1)),
+            max: (self.max.0.min(other.max.0), self.max.1.min(other.max.1)),
+        };
+        if bounds.width() > 0 && bounds.height() > 0 {
+            Some(bounds)
+        } else {
+            None
+        }
+    }
+
+    // Cells of the chunk at the given chunk coordinates.
+    pub fn of_chunk(chunk_coordinates: (i32, i32), chunk_size: usize) -> Bounds {
+        let size = chunk_size as i32;
+        Bounds {
+            min: (chunk_coordinates.0 * size, chunk_coordinates.1 * size),
+            max: (
+                (chunk_coordinates.0 + 1) * size,
+                (chunk_coordinates.1 + 1) * size,
+            ),
+        }
+    }
+
+    // Coordinates of every chunk that overlaps these bounds.
+    pub fn chunk_coordinates(&self, chunk_size: usize) -> impl Iterator<Item = (i32, i32)> {
+        let size = chunk_size as i32;
+        let min = (self.min.0.div_euclid(size), self.min.1.div_euclid(size));
+        let max = (
+            (self.max.0 - 1).div_euclid(size),
+            (self.max.1 - 1).div_euclid(size),
+        );
+        (min.1..=max.1).flat_map(move |y| (min.0..=max.0).map(move |x| (x, y)))
+    }
 }
 
 impl Index<(i32, i32)> for TerrainData {
     type Output = f32;
 
     fn index(&self, coordinates: (i32, i32)) -> &Self::Output {
-        let chunk_coordinates = Self::get_terrain_chunk_coordinates(coordinates);
-        let relative_x = coordinates.0.rem_euclid(TerrainDataChunk::size as i32);
-        let relative_y = coordinates.1.rem_euclid(TerrainDataChunk::size as i32);
+        let chunk_coordinates = self.get_terrain_chunk_coordinates(coordinates);
+        let relative_x = coordinates.0.rem_euclid(self.chunk_size as i32);
+        let relative_y = coordinates.1.rem_euclid(self.chunk_size as i32);
         if let Some(chunk) = self.chunks.get(&chunk_coordinates) {
-            &chunk.data[(relative_y as usize) * TerrainDataChunk::size + (relative_x as usize)]
+            &chunk.data[(relative_y as usize) * self.chunk_size + (relative_x as usize)]
         } else {
             &0.0
         }
@@ -68,139 +145,241 @@ impl Index<(i32, i32)> for TerrainData {
 
 impl IndexMut<(i32, i32)> for TerrainData {
     fn index_mut(&mut self, coordinates: (i32, i32)) -> &mut f32 {
-        let chunk_coordinates = Self::get_terrain_chunk_coordinates(coordinates);
-        let relative_x = coordinates.0.rem_euclid(TerrainDataChunk::size as i32);
-        let relative_y = coordinates.1.rem_euclid(TerrainDataChunk::size as i32);
+        let chunk_coordinates = self.get_terrain_chunk_coordinates(coordinates);
+        let relative_x = coordinates.0.rem_euclid(self.chunk_size as i32);
+        let relative_y = coordinates.1.rem_euclid(self.chunk_size as i32);
         if !self.chunks.contains_key(&chunk_coordinates) {
-            self.chunks
-                .insert(chunk_coordinates, TerrainDataChunk::new(chunk_coordinates));
+            self.chunks.insert(
+                chunk_coordinates,
+                TerrainDataChunk::new(chunk_coordinates, self.chunk_size),
+            );
         }
-        &mut self.chunks.get_mut(&chunk_coordinates).unwrap().data
-            [(relative_y as usize) * TerrainDataChunk::size + (relative_x as usize)]
+        let chunk_size = self.chunk_size;
+        let chunk = self.chunks.get_mut(&chunk_coordinates).unwrap();
+        chunk.touch();
+        &mut chunk.data[(relative_y as usize) * chunk_size + (relative_x as usize)]
     }
 }
 
 impl TerrainData {
-    pub fn new(data: HashMap<(i32, i32), TerrainDataChunk>) -> Self {
-        TerrainData { chunks: data }
+    pub fn new(data: HashMap<(i32, i32), TerrainDataChunk>, chunk_size: usize) -> Self {
+        TerrainData {
+            chunks: data,
+            chunk_size,
+        }
     }
 
-    pub fn zeros() -> Self {
+    pub fn zeros(chunk_size: usize) -> Self {
         TerrainData {
             chunks: HashMap::new(),
+            chunk_size,
         }
     }
 
-    pub fn get_terrain_chunk_coordinates(coordinates: (i32, i32)) -> (i32, i32) {
+    pub fn get_terrain_chunk_coordinates(&self, coordinates: (i32, i32)) -> (i32, i32) {
         (
-            (coordinates.0 as i32).div_euclid(TerrainDataChunk::size as i32),
-            (coordinates.1 as i32).div_euclid(TerrainDataChunk::size as i32),
+            (coordinates.0 as i32).div_euclid(self.chunk_size as i32),
+            (coordinates.1 as i32).div_euclid(self.chunk_size as i32),
         )
     }
 
-    pub fn sample(&self, pos: Vec2) -> Option<f32> {
-        // P1   P2
-        //  (x,y)
-        // P3   P4
-        // Bilinear interpolation, with linear interpolation/nearest neighbor for edge values.
-        let x = pos.x;
-        let y = pos.y;
-        let x_adjusted = x.rem_euclid(1.0);
-        let y_adjusted = y.rem_euclid(1.0);
-
-        let p1 = self[(x.floor() as i32, y.floor() as i32)];
-        let p2 = self[(x.ceil() as i32, y.floor() as i32)];
-        let p3 = self[(x.floor() as i32, y.ceil() as i32)];
-        let p4 = self[(x.ceil() as i32, y.ceil() as i32)];
-        let top_x_interp = lerp(p1, p2, x_adjusted);
-        let bottom_x_interp = lerp(p3, p4, x_adjusted);
-        Some(lerp(top_x_interp, bottom_x_interp, y_adjusted))
-    }
-
-    fn get_subpixel_weights(&self, xy: Vec2) -> (f32, f32, f32, f32) {
-        let x_adjusted = xy.x.rem_euclid(1.0);
-        let y_adjusted = xy.y.rem_euclid(1.0);
-        let p1_weight = lerp(lerp(1.0, 0.0, x_adjusted), 0.0, y_adjusted);
-        let p2_weight = lerp(lerp(0.0, 1.0, x_adjusted), 0.0, y_adjusted);
-        let p3_weight = lerp(0.0, lerp(1.0, 0.0, x_adjusted), y_adjusted);
-        let p4_weight = lerp(0.0, lerp(0.0, 1.0, x_adjusted), y_adjusted);
-        (p1_weight, p2_weight, p3_weight, p4_weight)
-    }
-
-    pub fn modify(&mut self, xy: Vec2, change: f32) {
-        //println!("Bleh. ${:?}, ${:?}", xy, change);
-        let (p1, p2, p3, p4) = &self.get_subpixel_weights(xy);
-        let (x, y) = (xy.x, xy.y);
-        if let Some(i) = self.chunks.get_mut(&Self::get_terrain_chunk_coordinates((
-            xy.x as i32,
-            xy.y as i32,
-        ))) {
-            i.modified = true;
-        }
-        //println!("I am going to add {} * {} to {} = {}", p1, change, self[(x.floor() as i32, y.floor() as i32)], p1 * change);
-        self[(x.floor() as i32, y.floor() as i32)] += p1 * change;
-        self[(x.ceil() as i32, y.floor() as i32)] += p2 * change;
-        self[(x.floor() as i32, y.ceil() as i32)] += p3 * change;
-        self[(x.ceil() as i32, y.ceil() as i32)] += p4 * change;
-    }
-    //Takes point and value map, returns downhill vector
-    pub fn get_slope_vector(&self, pos: Vec2) -> Option<Vec2> {
-        // To get the angle, we "pull" the point towards each side based on the values of each side subpixel.
-        let left_value: f32 = self.sample(pos + vec2(-1., 0.0))?;
-        let right_value: f32 = self.sample(pos + vec2(1., 0.0))?;
-        let up_value: f32 = self.sample(pos + vec2(0.0, -1.0))?;
-        let down_value: f32 = self.sample(pos + vec2(0.0, 1.0))?;
-
-        let x_weighted: f32 = left_value - right_value;
-        let y_weighted: f32 = up_value - down_value;
-
-        return Some(vec2(x_weighted, y_weighted).normalize_or_zero());
+    // Terrain made of just the region's cells, with every chunk it covers marked as modified.
+    pub fn from_region(region: &Region, chunk_size: usize) -> TerrainData {
+        let mut data = TerrainData::zeros(chunk_size);
+        data.apply_region(region);
+        // apply_region only marks chunks it changed, and may have skipped chunks of zeros.
+        for chunk_coordinates in region.bounds.chunk_coordinates(chunk_size) {
+            data.chunks
+                .entry(chunk_coordinates)
+                .or_insert_with(|| TerrainDataChunk::new(chunk_coordinates, chunk_size))
+                .touch();
+        }
+        data
+    }
+
+    // Swaps in other cells, marking as modified only the chunks that look any different, so a
+    // preview can be swapped in and out without remeshing the whole terrain.
+    pub fn replace(&mut self, mut data: TerrainData) {
+        for (coords, chunk) in data.chunks.iter_mut() {
+            chunk.modified = match self.chunks.get(coords) {
+                Some(old) => old.modified || old.data != chunk.data,
+                None => true,
+            };
+        }
+        // Chunks that are gone get meshed flat again without being marked.
+        *self = data;
+    }
+
+    // Same cells, cut into chunks of another size. Every new chunk is marked as modified.
+    pub fn rechunk(&self, chunk_size: usize) -> TerrainData {
+        match self.bounds() {
+            Some(bounds) => TerrainData::from_region(&self.region(bounds), chunk_size),
+            None => TerrainData::zeros(chunk_size),
+        }
+    }
+
+    // Smallest and largest chunk coordinates in use, or None if no chunk has been allocated yet.
+    pub fn chunk_bounds(&self) -> Option<((i32, i32), (i32, i32))> {
+        let first = *self.chunks.keys().next()?;
+        Some(
+            self.chunks
+                .keys()
+                .fold((first, first), |(min, max), chunk_coords| {
+                    (
+                        (min.0.min(chunk_coords.0), min.1.min(chunk_coords.1)),
+                        (max.0.max(chunk_coords.0), max.1.max(chunk_coords.1)),
+                    )
+                }),
+        )
+    }
+
+    // Cells covered by the allocated chunks. This is what counts as the edge of the world.
+    pub fn bounds(&self) -> Option<Bounds> {
+        let (min, max) = self.chunk_bounds()?;
+        let size = self.chunk_size as i32;
+        Some(Bounds {
+            min: (min.0 * size, min.1 * size),
+            max: ((max.0 + 1) * size, (max.1 + 1) * size),
+        })
+    }
+
+    pub fn add_to_cell(&mut self, coordinates: (i32, i32), change: f32) {
+        let chunk_coordinates = self.get_terrain_chunk_coordinates(coordinates);
+        let chunk_size = self.chunk_size;
+        let chunk = self
+            .chunks
+            .entry(chunk_coordinates)
+            .or_insert_with(|| TerrainDataChunk::new(chunk_coordinates, chunk_size));
+        let relative_x = coordinates.0.rem_euclid(chunk_size as i32);
+        let relative_y = coordinates.1.rem_euclid(chunk_size as i32);
+        chunk.data[(relative_y as usize) * chunk_size + (relative_x as usize)] += change;
+        chunk.touch();
+    }
+
+    // Spreads the change over the cells around xy with the interpolation's weights, so a write
+    // is the mirror image of a read. The edge policy decides what happens to the parts that fall
+    // outside of the bounds.
+    pub fn modify_within(
+        &mut self,
+        xy: Vec2,
+        change: f32,
+        interpolation: Interpolation,
+        policy: EdgePolicy,
+        bounds: Option<Bounds>,
+    ) {
+        for (cell, weight) in interpolation.taps(xy) {
+            if let Some(target) = policy.write_target(bounds, cell) {
+                self.add_to_cell(target, weight * change);
+            }
+        }
+    }
+
+    // Copies a rectangle of cells out of the chunks, one chunk row at a time. Missing chunks read as 0.
+    pub fn region(&self, bounds: Bounds) -> Region {
+        let mut region = Region::new(bounds);
+        for chunk_coordinates in bounds.chunk_coordinates(self.chunk_size) {
+            if let Some(chunk) = self.chunks.get(&chunk_coordinates) {
+                let chunk_bounds = Bounds::of_chunk(chunk_coordinates, self.chunk_size);
+                let overlap = bounds.intersection(&chunk_bounds).unwrap();
+                let width = overlap.width() as usize;
+                let chunk_x = (overlap.min.0 - chunk_bounds.min.0) as usize;
+                let region_x = (overlap.min.0 - bounds.min.0) as usize;
+                for y in overlap.min.1..overlap.max.1 {
+                    let chunk_start = (y - chunk_bounds.min.1) as usize * self.chunk_size + chunk_x;
+                    region.row_mut(y)[region_x..region_x + width]
+                        .copy_from_slice(&chunk.data[chunk_start..chunk_start + width]);
+                }
+            }
+        }
+        region
+    }
+
+    // Writes a region back. Only chunks whose cells actually changed are touched and marked as
+    // modified, and missing chunks are only allocated if the region put something other than 0 in them.
+    pub fn apply_region(&mut self, region: &Region) {
+        let bounds = region.bounds;
+        let chunk_size = self.chunk_size;
+        for chunk_coordinates in bounds.chunk_coordinates(chunk_size) {
+            let chunk_bounds = Bounds::of_chunk(chunk_coordinates, chunk_size);
+            let overlap = bounds.intersection(&chunk_bounds).unwrap();
+            let width = overlap.width() as usize;
+            let chunk_x = (overlap.min.0 - chunk_bounds.min.0) as usize;
+            let region_x = (overlap.min.0 - bounds.min.0) as usize;
+            let rows = || {
+                (overlap.min.1..overlap.max.1).map(|y| {
+                    (
+                        (y - chunk_bounds.min.1) as usize * chunk_size + chunk_x,
+                        &region.row(y)[region_x..region_x + width],
+                    )
+                })
+            };
+
+            if !self.chunks.contains_key(&chunk_coordinates)
+                && rows().all(|(_, row)| row.iter().all(|value| *value == 0.0))
+            {
+                continue;
+            }
+            let chunk = self
+                .chunks
+                .entry(chunk_coordinates)
+                .or_insert_with(|| TerrainDataChunk::new(chunk_coordinates, chunk_size));
+            for (chunk_start, row) in rows() {
+                let chunk_row = &mut chunk.data[chunk_start..chunk_start + width];
+                if chunk_row != row {
+                    chunk_row.copy_from_slice(row);
+                    chunk.touch();
+                }
+            }
+        }
+    }
+
+    // Writes back a region read with region() and then changed from before to after, through the
+    // edge policy like modify_within. Under Zero this is apply_region, Clamp and None drop the
+    // cells outside of the bounds, and Wrap adds their changes to the cells on the opposite side.
+    pub fn apply_region_within(&mut self, before: &Region, after: &Region, policy: EdgePolicy) {
+        if policy == EdgePolicy::Zero {
+            return self.apply_region(after);
+        }
+        let world = match self.bounds() {
+            Some(world) => world,
+            None => return,
+        };
+        let bounds = after.bounds;
+        if let Some(inside) = bounds.intersection(&world) {
+            let mut part = Region::new(inside);
+            let start = (inside.min.0 - bounds.min.0) as usize;
+            for y in inside.min.1..inside.max.1 {
+                part.row_mut(y)
+                    .copy_from_slice(&after.row(y)[start..start + inside.width() as usize]);
+            }
+            self.apply_region(&part);
+        }
+        if policy == EdgePolicy::Wrap {
+            for y in bounds.min.1..bounds.max.1 {
+                for x in bounds.min.0..bounds.max.0 {
+                    let change = after.get((x, y)).unwrap() - before.get((x, y)).unwrap();
+                    if world.contains((x, y)) || change == 0.0 {
+                        continue;
+                    }
+                    if let Some(target) = policy.write_target(Some(world), (x, y)) {
+                        self.add_to_cell(target, change);
+                    }
+                }
+            }
+        }
     }
 
     pub fn save_to_exr(&self, path: &str) -> ImageResult<()> {
         // Get bounds of terrain data.
-        let top_left_coords = self.chunks.keys().fold(
-            self.chunks.keys().next().unwrap().clone(),
-            |a: (i32, i32), chunk_coords: &(i32, i32)| {
-                (
-                    if chunk_coords.0 < a.0 {
-                        chunk_coords.0
-                    } else {
-                        a.0
-                    },
-                    if chunk_coords.1 < a.1 {
-                        chunk_coords.1
-                    } else {
-                        a.1
-                    },
-                )
-            },
-        );
-        let bottom_right_coords = self.chunks.keys().fold(
-            self.chunks.keys().next().unwrap().clone(),
-            |a: (i32, i32), chunk_coords: &(i32, i32)| {
-                (
-                    if chunk_coords.0 > a.0 {
-                        chunk_coords.0
-                    } else {
-                        a.0
-                    },
-                    if chunk_coords.1 > a.1 {
-                        chunk_coords.1
-                    } else {
-                        a.1
-                    },
-                )
-            },
-        );
+        let (top_left_coords, bottom_right_coords) = self.chunk_bounds().unwrap();
 
         // Create the image buffer
 
         let mut img = Rgba32FImage::new(
-            ((bottom_right_coords.0 - top_left_coords.0 + 1) * CHUNK_SIZE as i32)
+            ((bottom_right_coords.0 - top_left_coords.0 + 1) * self.chunk_size as i32)
                 .try_into()
                 .unwrap(),
-            ((bottom_right_coords.1 - top_left_coords.1 + 1) * CHUNK_SIZE as i32)
+            ((bottom_right_coords.1 - top_left_coords.1 + 1) * self.chunk_size as i32)
                 .try_into()
                 .unwrap(),
         );
@@ -211,14 +390,14 @@ impl TerrainData {
                 chunk_coords.0 - top_left_coords.0,
                 chunk_coords.1 - top_left_coords.1,
             );
-            let chunk_data = e.1.data;
+            let chunk_data = &e.1.data;
 
             chunk_data.iter().enumerate().for_each(|(i, val)| {
                 img.put_pixel(
-                    (i % CHUNK_SIZE + (relative_chunk_coords.0 as usize) * CHUNK_SIZE)
+                    (i % self.chunk_size + (relative_chunk_coords.0 as usize) * self.chunk_size)
                         .try_into()
                         .unwrap(),
-                    (i / CHUNK_SIZE + (relative_chunk_coords.1 as usize) * CHUNK_SIZE)
+                    (i / self.chunk_size + (relative_chunk_coords.1 as usize) * self.chunk_size)
                         .try_into()
                         .unwrap(),
                     Rgba([*val, *val, *val, 1.0]),
@@ -228,22 +407,56 @@ impl TerrainData {
 
         img.save_with_format(path, OpenExr)
     }
+
+    // Reads a heightmap from the red channel of an image, such as one written by save_to_exr. Its
+    // top left pixel becomes cell (0, 0).
+    pub fn load_from_image<P: AsRef<Path>>(path: P, chunk_size: usize) -> ImageResult<TerrainData> {
+        let img = image::open(path)?.into_rgba32f();
+        let bounds = Bounds {
+            min: (0, 0),
+            max: (img.width() as i32, img.height() as i32),
+        };
+        let mut region = Region::new(bounds);
+        for (value, pixel) in region.data.iter_mut().zip(img.pixels()) {
+            *value = pixel.0[0];
+        }
+        Ok(TerrainData::from_region(&region, chunk_size))
+    }
 }
 
 pub struct Terrain {
     pub data: TerrainData,
-    pub worldscale: f32,
+    pub worldscale: f32, // World units per kilometre, for map scale bars.
     pub height: f32,
     pub noisescale: f32,
+    pub edge_policy: EdgePolicy,
+    pub interpolation: Interpolation,
+    pub seed: u64,
+    pub cell_size: f32,  // Distance between neighbouring cells in world units.
+    pub mesh_error: f32, // Largest height error allowed when simplifying meshes. 0 meshes every cell.
+    pub sea_level: f32,  // Height below which cells count as sea.
+}
+
+impl Terrain {
+    // World x and z to cell coordinates.
+    pub fn world_to_cells(&self, position: Vec2) -> Vec2 {
+        position / self.cell_size
+    }
 }
 
 impl Default for Terrain {
     fn default() -> Terrain {
         Terrain {
-            data: TerrainData::zeros(),
+            data: TerrainData::zeros(DEFAULT_CHUNK_SIZE),
             worldscale: 256.0,
             height: 64.0,
             noisescale: 0.01,
+            edge_policy: EdgePolicy::Zero,
+            interpolation: Interpolation::Bilinear,
+            seed: 0,
+            cell_size: 1.0,
+            mesh_error: 0.0,
+            sea_level: 0.0,
         }
     }
 }
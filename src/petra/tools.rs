@@ -1,2 +1,4 @@
 pub mod erode;
 pub mod raise;
+pub mod river;
+pub mod smooth;
@@ -1,6 +1,10 @@
-use crate::petra::terrain::Terrain;
+use crate::petra::analysis::LayerMask;
+use crate::petra::cursor::Region;
+use crate::petra::sampler::{EdgePolicy, Interpolation, Sampler};
+use crate::petra::terrain::{Bounds, Terrain};
 use bevy::math::{vec2, Vec2};
-use rand::random;
+use rayon::prelude::*;
+use std::collections::BTreeMap;
 use std::f32;
 
 const MAX_ITERATIONS: u32 = 512;
@@ -8,6 +12,39 @@ const MAX_CARRIED_SEDIMENT: f32 = 0.5;
 const EROSION_RATE: f32 = 0.1;
 const DEPOSITION_RATE: f32 = 0.05;
 const DRY_TRESHOLD: f32 = 0.0;
+// Droplets start in square tiles of cells and can run this many cells past the tile before they
+// are stopped, whatever the chunk size. A halo as wide as the tile lets three tiles apart run
+// side by side.
+const TILE_SIZE: i32 = 64;
+const HALO: i32 = TILE_SIZE;
+// How far droplets have to stay from the edge of their tile's window, so the widest kernel never
+// reads or writes outside of it.
+const WINDOW_MARGIN: i32 = 3;
+
+// Everything a droplet needs to know besides the heights themselves.
+struct Settings {
+    policy: EdgePolicy,
+    interpolation: Interpolation,
+    bounds: Option<Bounds>,
+    window: Bounds, // Droplets that leave this die, see Tile.
+}
+
+impl Settings {
+    // Height of a cell in the window. Cells that read as nothing under the edge policy are NaN in
+    // the window, and clamped cells read the edge cell as the droplets change it.
+    fn read(&self, heights: &Region, cell: (i32, i32)) -> Option<f32> {
+        let cell = match (self.policy, self.bounds) {
+            (EdgePolicy::Clamp, Some(bounds)) => self.policy.resolve(bounds, cell)?,
+            _ => cell,
+        };
+        heights.get(cell).filter(|height| !height.is_nan())
+    }
+
+    // Whether the edge policy keeps a write to this cell, like TerrainData::modify_within.
+    fn writable(&self, cell: (i32, i32)) -> bool {
+        self.policy.write_target(self.bounds, cell).is_some()
+    }
+}
 
 struct Droplet {
     xy: Vec2,
@@ -23,26 +60,44 @@ impl Droplet {
             alive: true,
         }
     }
-    fn step(&mut self, terrain: &mut Terrain) {
-        let slope_vector_option = terrain.data.get_slope_vector(self.xy);
-        if slope_vector_option.is_some() {
-            let slope_vector = slope_vector_option.unwrap();
-            let new_xy = slope_vector + self.xy;
+
+    fn modify(&self, heights: &mut Region, change: f32, settings: &Settings) {
+        for (cell, weight) in settings.interpolation.taps(self.xy) {
+            if settings.writable(cell) {
+                if let Some(height) = heights.get_mut(cell) {
+                    *height += weight * change;
+                }
+            }
+        }
+    }
+
+    fn step(&mut self, heights: &mut Region, settings: &Settings) {
+        let get = |cell| settings.read(heights, cell);
+        let interpolation = settings.interpolation;
+        // Any of these reads failing means the droplet ran off the edge of the world.
+        let next_step = interpolation.gradient(self.xy, get).and_then(|gradient| {
+            let new_xy = self.xy + (-gradient).normalize_or_zero();
             let height_difference =
-                terrain.data.sample(new_xy).unwrap() - terrain.data.sample(self.xy).unwrap();
+                interpolation.sample(new_xy, get)? - interpolation.sample(self.xy, get)?;
+            Some((new_xy, height_difference))
+        });
+        if let Some((new_xy, height_difference)) = next_step {
             if height_difference >= 0.0 {
                 // Deposit the carried sediment.
-                terrain
-                    .data
-                    .modify(self.xy, self.sediment.min(DEPOSITION_RATE));
+                self.modify(heights, self.sediment.min(DEPOSITION_RATE), settings);
                 self.sediment -= self.sediment.min(DEPOSITION_RATE);
                 if self.sediment <= DRY_TRESHOLD {
                     self.alive = false;
                 }
+            } else if !settings
+                .window
+                .contains((new_xy.x.floor() as i32, new_xy.y.floor() as i32))
+            {
+                self.alive = false;
             } else {
                 self.xy = new_xy;
                 if self.sediment < MAX_CARRIED_SEDIMENT {
-                    terrain.data.modify(self.xy, -EROSION_RATE);
+                    self.modify(heights, -EROSION_RATE, settings);
                     self.sediment += EROSION_RATE;
                 }
             }
@@ -52,19 +107,297 @@ impl Droplet {
     }
 }
 
-pub fn trigger(xy: Vec2, radius: i64, terrain: &mut Terrain) {
+// Droplets are simulated in tiles of TILE_SIZE cells. A tile works on a dense copy of its cells
+// and the HALO around them, read once through the edge policy, so droplets never go through the
+// chunk map and tiles can run on separate threads. Droplets that wander out of the halo are
+// stopped.
+struct Tile {
+    before: Region,
+    after: Region,
+}
+
+impl Tile {
+    fn new(terrain: &Terrain, tile: (i32, i32)) -> Self {
+        let bounds = Bounds {
+            min: (tile.0 * TILE_SIZE - HALO, tile.1 * TILE_SIZE - HALO),
+            max: (
+                (tile.0 + 1) * TILE_SIZE + HALO,
+                (tile.1 + 1) * TILE_SIZE + HALO,
+            ),
+        };
+        let mut before = Region::new(bounds);
+        let sampler = Sampler::for_terrain(terrain);
+        for y in bounds.min.1..bounds.max.1 {
+            for (i, height) in before.row_mut(y).iter_mut().enumerate() {
+                *height = sampler
+                    .get((bounds.min.0 + i as i32, y))
+                    .unwrap_or(f32::NAN);
+            }
+        }
+        Self {
+            after: before.clone(),
+            before,
+        }
+    }
+
+    fn window(&self) -> Bounds {
+        let bounds = self.before.bounds;
+        Bounds {
+            min: (bounds.min.0 + WINDOW_MARGIN, bounds.min.1 + WINDOW_MARGIN),
+            max: (bounds.max.0 - WINDOW_MARGIN, bounds.max.1 - WINDOW_MARGIN),
+        }
+    }
+
+    // Every cell the droplets changed, with what they changed it by.
+    fn changes(&self) -> impl Iterator<Item = ((i32, i32), f32)> + '_ {
+        let bounds = self.before.bounds;
+        let changes = self.before.data.iter().zip(&self.after.data);
+        changes.enumerate().filter_map(move |(i, (before, after))| {
+            let change = after - before;
+            if change == 0.0 || change.is_nan() {
+                return None;
+            }
+            let cell = (
+                bounds.min.0 + i as i32 % bounds.width(),
+                bounds.min.1 + i as i32 / bounds.width(),
+            );
+            Some((cell, change))
+        })
+    }
+
+    // Smallest bounds around the changed cells.
+    fn changed(&self) -> Option<Bounds> {
+        self.changes()
+            .fold(None, |bounds: Option<Bounds>, (cell, _)| {
+                let bounds = bounds.unwrap_or(Bounds {
+                    min: cell,
+                    max: (cell.0 + 1, cell.1 + 1),
+                });
+                Some(Bounds {
+                    min: (bounds.min.0.min(cell.0), bounds.min.1.min(cell.1)),
+                    max: (bounds.max.0.max(cell.0 + 1), bounds.max.1.max(cell.1 + 1)),
+                })
+            })
+    }
+
+    // Adds what the droplets changed to the terrain, through the same edge policy as their writes,
+    // and scaled by the weights where there are any. Working with differences means tiles never
+    // overwrite each other's results, even if their windows overlap on a small wrapped world.
+    fn apply(&self, terrain: &mut Terrain, settings: &Settings, weights: Option<&Region>) {
+        for (cell, change) in self.changes() {
+            let weight = weights.and_then(|weights| weights.get(cell)).unwrap_or(1.0);
+            if let Some(target) = terrain.edge_policy.write_target(settings.bounds, cell) {
+                terrain.data.add_to_cell(target, change * weight);
+            }
+        }
+    }
+}
+
+// Cheap hash of the seed and a brush offset (splitmix64), so whether a droplet spawns doesn't
+// depend on which thread runs it or in what order.
+fn random(seed: u64, x: i64, y: i64) -> f32 {
+    let mut z = seed
+        ^ (x as u64).wrapping_mul(0x9E3779B97F4A7C15)
+        ^ (y as u64).wrapping_mul(0xC2B2AE3D27D4EB4F);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^= z >> 31;
+    (z >> 40) as f32 / (1u64 << 24) as f32
+}
+
+// The result only depends on the terrain and the seed, not on the number of threads or the chunk
+// size. With a mask, each cell only keeps its weight's share of the change, weighted by the
+// terrain as it was before the pass that changed it.
+pub fn trigger(xy: Vec2, radius: i64, seed: u64, terrain: &mut Terrain, mask: Option<&LayerMask>) {
+    let mut tiles: BTreeMap<(i32, i32), Vec<Vec2>> = BTreeMap::new();
     for x in -radius..radius {
         for y in -radius..radius {
             let strength = ((radius as f32) - vec2(x as f32, y as f32).length()) / (radius as f32);
-            if random::<f32>() < strength {
-                let mut droplet = Droplet::new(xy + vec2(x as f32, y as f32), 0.0);
-                for _i in 0..MAX_ITERATIONS {
-                    droplet.step(terrain);
-                    if !droplet.alive {
-                        break;
+            if random(seed, x, y) < strength {
+                let start = xy + vec2(x as f32, y as f32);
+                tiles
+                    .entry((
+                        (start.x.floor() as i32).div_euclid(TILE_SIZE),
+                        (start.y.floor() as i32).div_euclid(TILE_SIZE),
+                    ))
+                    .or_default()
+                    .push(start);
+            }
+        }
+    }
+
+    // Tiles three apart have windows that don't overlap. Each of the nine passes runs its
+    // tiles in parallel, and later passes see what the earlier ones did.
+    for pass in 0..9 {
+        // The bounds only change when Zero lets droplets allocate new chunks, so once per pass is
+        // enough.
+        let bounds = terrain.data.bounds();
+        let snapshot: &Terrain = terrain;
+        let results: Vec<(Tile, Settings, Option<Region>)> = tiles
+            .iter()
+            .filter(|(coordinates, _)| {
+                (coordinates.0.rem_euclid(3), coordinates.1.rem_euclid(3)) == (pass % 3, pass / 3)
+            })
+            .collect::<Vec<_>>()
+            .par_iter()
+            .map(|(coordinates, starts)| {
+                let mut tile = Tile::new(snapshot, **coordinates);
+                // Inside a tile, wrapping is already taken care of by the window holding the
+                // wrapped cells.
+                let policy = match snapshot.edge_policy {
+                    EdgePolicy::Wrap => EdgePolicy::Zero,
+                    policy => policy,
+                };
+                let settings = Settings {
+                    policy,
+                    interpolation: snapshot.interpolation,
+                    bounds,
+                    window: tile.window(),
+                };
+                for start in starts.iter() {
+                    let mut droplet = Droplet::new(*start, 0.0);
+                    for _i in 0..MAX_ITERATIONS {
+                        droplet.step(&mut tile.after, &settings);
+                        if !droplet.alive {
+                            break;
+                        }
                     }
                 }
+                // Only the cells that changed need weights.
+                let weights = mask
+                    .zip(tile.changed())
+                    .map(|(mask, changed)| mask.weights(snapshot, changed));
+                (tile, settings, weights)
+            })
+            .collect();
+        // Collecting keeps the tiles in order, so they are applied the same way every time.
+        for (tile, settings, weights) in results {
+            tile.apply(terrain, &settings, weights.as_ref());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::petra::generate;
+    use crate::petra::terrain::TerrainData;
+
+    // 256 cells square, whole chunks at both sizes used below.
+    fn terrain(chunk_size: usize) -> Terrain {
+        let mut terrain = Terrain::default();
+        generate::fbm(&mut terrain, (-2, -2), (1, 1), 0);
+        terrain.data = terrain.data.rechunk(chunk_size);
+        terrain
+    }
+
+    fn erode(terrain: &mut Terrain, seed: u64) {
+        for (i, xy) in [vec2(10.5, 20.5), vec2(-70.0, 40.0), vec2(100.0, -100.0)]
+            .into_iter()
+            .enumerate()
+        {
+            trigger(xy, 25, seed + i as u64, terrain, None);
+        }
+    }
+
+    fn heights(terrain: &Terrain) -> Vec<f32> {
+        terrain
+            .data
+            .region(Bounds {
+                min: (-256, -256),
+                max: (256, 256),
+            })
+            .data
+    }
+
+    #[test]
+    fn same_seed_erodes_the_same() {
+        let mut first = terrain(64);
+        let mut second = terrain(64);
+        erode(&mut first, 7);
+        erode(&mut second, 7);
+        assert_eq!(heights(&first), heights(&second));
+        assert_ne!(heights(&first), heights(&terrain(64)));
+    }
+
+    #[test]
+    fn chunk_size_does_not_change_the_result() {
+        for policy in [
+            EdgePolicy::Zero,
+            EdgePolicy::Clamp,
+            EdgePolicy::Wrap,
+            EdgePolicy::None,
+        ] {
+            let mut small = terrain(16);
+            let mut large = terrain(64);
+            small.edge_policy = policy;
+            large.edge_policy = policy;
+            erode(&mut small, 3);
+            erode(&mut large, 3);
+            assert_eq!(heights(&small), heights(&large), "{:?}", policy);
+        }
+    }
+
+    #[test]
+    fn thread_count_does_not_change_the_result() {
+        let mut single = terrain(16);
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap()
+            .install(|| erode(&mut single, 5));
+        let mut parallel = terrain(16);
+        erode(&mut parallel, 5);
+        assert_eq!(heights(&single), heights(&parallel));
+    }
+
+    #[test]
+    fn mask_limits_the_change() {
+        use crate::petra::analysis::Layer;
+        let everywhere = LayerMask {
+            layer: Layer::Slope,
+            min: 0.0,
+            max: 90.0,
+            falloff: 0.0,
+        };
+        let nowhere = LayerMask {
+            min: 91.0,
+            max: 92.0,
+            ..everywhere
+        };
+        let (mut unmasked, mut all, mut none) = (terrain(64), terrain(64), terrain(64));
+        trigger(vec2(10.5, 20.5), 25, 3, &mut unmasked, None);
+        trigger(vec2(10.5, 20.5), 25, 3, &mut all, Some(&everywhere));
+        trigger(vec2(10.5, 20.5), 25, 3, &mut none, Some(&nowhere));
+        assert_eq!(heights(&all), heights(&unmasked));
+        assert_eq!(heights(&none), heights(&terrain(64)));
+    }
+
+    // Droplets used to stop about a chunk from where they started, so small chunks cut trails.
+    #[test]
+    fn droplets_run_past_small_chunks() {
+        let bounds = Bounds {
+            min: (-256, -16),
+            max: (256, 16),
+        };
+        let mut slope = Region::new(bounds);
+        for y in bounds.min.1..bounds.max.1 {
+            for (i, height) in slope.row_mut(y).iter_mut().enumerate() {
+                // Downhill to the east, then flat, where droplets drop their sediment.
+                *height = -0.5 * (bounds.min.0 + i as i32).min(96) as f32;
             }
         }
+        let mut terrain = Terrain {
+            data: TerrainData::from_region(&slope, 16),
+            ..Default::default()
+        };
+        trigger(vec2(0.5, 0.5), 4, 1, &mut terrain, None);
+        let after = terrain.data.region(bounds);
+        let farthest = (0..slope.data.len())
+            .filter(|i| slope.data[*i] != after.data[*i])
+            .map(|i| bounds.min.0 + i as i32 % bounds.width())
+            .max()
+            .unwrap();
+        assert!(farthest > HALO, "trails stop {} cells east", farthest);
     }
 }
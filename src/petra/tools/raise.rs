@@ -1,15 +1,73 @@
-use crate::petra::terrain;
+use crate::petra::analysis::{self, LayerMask};
+use crate::petra::terrain::{self, Bounds};
 use bevy::math::{vec2, Vec2};
 
-pub fn trigger(xy: Vec2, radius: i64, terrain: &mut terrain::Terrain) {
+// With a mask, each cell only keeps its weight's share of the change, weighted by the terrain as
+// it was before the dab. Changes past the edge of the world follow the edge policy.
+pub fn trigger(xy: Vec2, radius: i64, terrain: &mut terrain::Terrain, mask: Option<&LayerMask>) {
+    // Work on a copy of the brush's footprint, with room for the widest kernel on every side.
+    let margin = radius as i32 + 2;
+    let center = (xy.x.floor() as i32, xy.y.floor() as i32);
+    let mut region = terrain.data.region(Bounds {
+        min: (center.0 - margin, center.1 - margin),
+        max: (center.0 + margin + 1, center.1 + margin + 1),
+    });
+    let before = region.clone();
     for x in -radius..radius {
         for y in -radius..radius {
             let strength = ((radius as f32) - vec2(x as f32, y as f32).length()) / (radius as f32);
             if strength > 0.0 {
-                terrain
-                    .data
-                    .modify(xy + vec2(x as f32, y as f32), strength * 1.0);
+                region.modify(
+                    xy + vec2(x as f32, y as f32),
+                    strength * 1.0,
+                    terrain.interpolation,
+                );
             }
         }
     }
+    if let Some(mask) = mask {
+        let weights = mask.weights(terrain, region.bounds);
+        analysis::blend(&before, &mut region, &weights);
+    }
+    terrain
+        .data
+        .apply_region_within(&before, &region, terrain.edge_policy);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::petra::sampler::EdgePolicy;
+    use crate::petra::terrain::Terrain;
+
+    // One chunk of flat ground, raised right at its east edge.
+    fn raised(policy: EdgePolicy) -> Terrain {
+        let mut terrain = Terrain {
+            edge_policy: policy,
+            ..Default::default()
+        };
+        terrain.data = terrain.data.rechunk(16);
+        terrain.data.add_to_cell((0, 0), 1.0);
+        trigger(vec2(15.5, 8.5), 4, &mut terrain, None);
+        terrain
+    }
+
+    #[test]
+    fn brushes_follow_the_edge_policy() {
+        let zero = raised(EdgePolicy::Zero);
+        assert!(zero.data.chunks.contains_key(&(1, 0)));
+        assert!(zero.data[(17, 8)] > 0.0);
+
+        for policy in [EdgePolicy::Clamp, EdgePolicy::None] {
+            let terrain = raised(policy);
+            assert_eq!(terrain.data.chunks.len(), 1);
+            assert_eq!(terrain.data[(14, 8)], zero.data[(14, 8)]);
+            assert_eq!(terrain.data[(1, 8)], 0.0);
+        }
+
+        let wrapped = raised(EdgePolicy::Wrap);
+        assert_eq!(wrapped.data.chunks.len(), 1);
+        assert_eq!(wrapped.data[(14, 8)], zero.data[(14, 8)]);
+        assert_eq!(wrapped.data[(1, 8)], zero.data[(17, 8)]);
+    }
 }
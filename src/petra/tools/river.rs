@@ -0,0 +1,273 @@
+use crate::petra::analysis::{self, LayerMask};
+use crate::petra::hydrology::{Flow, FlowMethod, FILL_EPSILON};
+use crate::petra::sampler::Sampler;
+use crate::petra::terrain::{Bounds, Terrain};
+use bevy::math::{vec2, Vec2};
+use rayon::prelude::*;
+
+// Cells between the control points picked from a flow path.
+const FLOW_POINT_SPACING: usize = 4;
+
+// Shape of the channel, in world units. Width and depth change evenly from the first point to
+// the last, and the banks blend the channel into the terrain around it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RiverSettings {
+    pub width: (f32, f32),
+    pub depth: (f32, f32),
+    pub bank: f32,
+    // Clicks pick the source and the river follows the water downhill, instead of clicks being
+    // the control points.
+    pub follow_flow: bool,
+}
+
+impl Default for RiverSettings {
+    fn default() -> Self {
+        Self {
+            width: (4.0, 12.0),
+            depth: (1.0, 3.0),
+            bank: 8.0,
+            follow_flow: false,
+        }
+    }
+}
+
+// Catmull-Rom spline through the points, about two samples per cell.
+fn spline(points: &[Vec2]) -> Vec<Vec2> {
+    let mut samples = vec![points[0]];
+    for i in 0..points.len() - 1 {
+        let p0 = points[i.saturating_sub(1)];
+        let (p1, p2) = (points[i], points[i + 1]);
+        let p3 = points[(i + 2).min(points.len() - 1)];
+        let steps = (p1.distance(p2) * 2.0).ceil().max(1.0) as usize;
+        for step in 1..=steps {
+            let t = step as f32 / steps as f32;
+            let (t2, t3) = (t * t, t * t * t);
+            samples.push(
+                0.5 * (2.0 * p1
+                    + (p2 - p0) * t
+                    + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t2
+                    + (3.0 * p1 - p0 - 3.0 * p2 + p3) * t3),
+            );
+        }
+    }
+    samples
+}
+
+// Revisions of every chunk and the sea level, which the filled heights depend on.
+type Key = (Vec<((i32, i32), u64)>, u32);
+
+// D8 flow over the filled terrain, kept until the terrain or the sea level changes.
+#[derive(Default)]
+pub struct FlowCache {
+    key: Option<Key>,
+    flow: Option<Flow>,
+}
+
+impl FlowCache {
+    pub fn flow(&mut self, terrain: &Terrain) -> Option<&Flow> {
+        let mut revisions: Vec<_> = terrain
+            .data
+            .chunks
+            .iter()
+            .map(|(coords, chunk)| (*coords, chunk.revision))
+            .collect();
+        revisions.sort_unstable();
+        let key = (revisions, terrain.sea_level.to_bits());
+        if self.key.as_ref() != Some(&key) {
+            self.flow = Flow::for_terrain(terrain, FlowMethod::D8, Some(FILL_EPSILON));
+            self.key = Some(key);
+        }
+        self.flow.as_ref()
+    }
+}
+
+// Control points along the way water runs from a cell over the filled terrain, down to the edge of
+// the world or the sea.
+pub fn flow_path(terrain: &Terrain, from: Vec2, cache: &mut FlowCache) -> Vec<Vec2> {
+    let flow = match cache.flow(terrain) {
+        Some(flow) => flow,
+        None => return Vec::new(),
+    };
+    let path = flow.path((from.x.round() as i32, from.y.round() as i32));
+    let end = path.len();
+    let mut points: Vec<Vec2> = path
+        .iter()
+        .step_by(FLOW_POINT_SPACING)
+        .map(|(x, y)| vec2(*x as f32, *y as f32))
+        .collect();
+    if end > 0 && (end - 1) % FLOW_POINT_SPACING != 0 {
+        let (x, y) = path[end - 1];
+        points.push(vec2(x as f32, y as f32));
+    }
+    points
+}
+
+// Carves a channel along a path of control points in cells, from the source to the mouth. The bed
+// never rises downstream, so it cuts through any hills in the way, and the terrain is only ever
+// lowered. With a mask, each cell only keeps its weight's share of the cut.
+pub fn carve(
+    points: &[Vec2],
+    settings: &RiverSettings,
+    terrain: &mut Terrain,
+    mask: Option<&LayerMask>,
+) {
+    if points.len() < 2 {
+        return;
+    }
+    let samples = spline(points);
+    let cell_size = terrain.cell_size;
+    let mut lengths = vec![0.0f32];
+    for pair in samples.windows(2) {
+        lengths.push(lengths.last().unwrap() + pair[0].distance(pair[1]) * cell_size);
+    }
+    let total = lengths.last().unwrap().max(f32::EPSILON);
+    let along = |length: f32, (start, end): (f32, f32)| start + (end - start) * length / total;
+
+    // Where the edge policy has nothing to read, the ground is taken to go on at the last height
+    // there was, or the first for samples before any.
+    let sampler = Sampler::for_terrain(terrain);
+    let mut ground = match samples.iter().find_map(|sample| sampler.sample(*sample)) {
+        Some(ground) => ground,
+        None => return,
+    };
+    let mut beds = Vec::with_capacity(samples.len());
+    let mut lowest = f32::INFINITY;
+    for (sample, length) in samples.iter().zip(&lengths) {
+        ground = sampler.sample(*sample).unwrap_or(ground);
+        lowest = lowest.min(ground - along(*length, settings.depth));
+        beds.push(lowest);
+    }
+
+    let reach = ((settings.width.0.max(settings.width.1) / 2.0 + settings.bank) / cell_size).ceil()
+        as i32
+        + 1;
+    let (min, max) = samples.iter().fold(
+        (Vec2::splat(f32::INFINITY), Vec2::splat(f32::NEG_INFINITY)),
+        |(min, max), sample| (min.min(*sample), max.max(*sample)),
+    );
+    let mut region = terrain.data.region(Bounds {
+        min: (min.x.floor() as i32 - reach, min.y.floor() as i32 - reach),
+        max: (
+            max.x.ceil() as i32 + reach + 1,
+            max.y.ceil() as i32 + reach + 1,
+        ),
+    });
+    let bounds = region.bounds;
+    let before = region.clone();
+
+    // Closest point on the path to every cell, with the bed and the length along the path there.
+    // Each segment only looks at the cells within reach of it.
+    let mut closest = vec![(f32::INFINITY, 0.0, 0.0); region.data.len()];
+    for (j, pair) in samples.windows(2).enumerate() {
+        let segment = pair[1] - pair[0];
+        let (low, high) = (pair[0].min(pair[1]), pair[0].max(pair[1]));
+        let min_x = (low.x.floor() as i32 - reach).max(bounds.min.0);
+        let max_x = (high.x.ceil() as i32 + reach).min(bounds.max.0 - 1);
+        for y in (low.y.floor() as i32 - reach).max(bounds.min.1)
+            ..=(high.y.ceil() as i32 + reach).min(bounds.max.1 - 1)
+        {
+            let row = ((y - bounds.min.1) * bounds.width()) as usize;
+            for x in min_x..=max_x {
+                let cell = vec2(x as f32, y as f32);
+                let t = ((cell - pair[0]).dot(segment) / segment.length_squared().max(1e-12))
+                    .clamp(0.0, 1.0);
+                let distance = cell.distance(pair[0] + segment * t);
+                let nearest = &mut closest[row + (x - bounds.min.0) as usize];
+                if distance < nearest.0 {
+                    *nearest = (
+                        distance,
+                        beds[j] + (beds[j + 1] - beds[j]) * t,
+                        lengths[j] + (lengths[j + 1] - lengths[j]) * t,
+                    );
+                }
+            }
+        }
+    }
+    region
+        .data
+        .par_iter_mut()
+        .zip(&closest)
+        .for_each(|(value, &(distance, bed, length))| {
+            let distance = distance * cell_size;
+            let half_width = along(length, settings.width) / 2.0;
+            let depth = along(length, settings.depth);
+            // Rounded bed inside the channel, rising to the top of the banks at its edges.
+            let carved = if distance <= half_width {
+                bed + depth * (distance / half_width.max(f32::EPSILON)).powi(2)
+            } else if distance < half_width + settings.bank {
+                let t = (distance - half_width) / settings.bank;
+                let t = t * t * (3.0 - 2.0 * t);
+                bed + depth + (*value - bed - depth) * t
+            } else {
+                return;
+            };
+            *value = value.min(carved);
+        });
+    if let Some(mask) = mask {
+        let weights = mask.weights(terrain, bounds);
+        analysis::blend(&before, &mut region, &weights);
+    }
+    terrain
+        .data
+        .apply_region_within(&before, &region, terrain.edge_policy);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::petra::sampler::EdgePolicy;
+
+    // Flat ground at 5 with a round hill on the way from x = 5 to x = 45.
+    fn hill(policy: EdgePolicy) -> Terrain {
+        let mut terrain = Terrain {
+            edge_policy: policy,
+            ..Default::default()
+        };
+        for y in 0..32 {
+            for x in 0..64 {
+                let distance = vec2(x as f32 - 25.0, y as f32 - 16.0).length();
+                terrain.data[(x, y)] = 5.0 + (6.0 - distance * 0.5).max(0.0);
+            }
+        }
+        terrain
+    }
+
+    fn heights(terrain: &Terrain) -> Vec<f32> {
+        terrain
+            .data
+            .region(Bounds {
+                min: (0, 0),
+                max: (64, 32),
+            })
+            .data
+    }
+
+    #[test]
+    fn bed_never_rises_and_ground_only_drops() {
+        let mut terrain = hill(EdgePolicy::Zero);
+        let before = heights(&terrain);
+        let points = [vec2(5.0, 16.0), vec2(25.0, 16.0), vec2(45.0, 16.0)];
+        carve(&points, &RiverSettings::default(), &mut terrain, None);
+        let after = heights(&terrain);
+        assert!(after
+            .iter()
+            .zip(&before)
+            .all(|(after, before)| after <= before));
+        for x in 5..45 {
+            assert!(terrain.data[(x + 1, 16)] <= terrain.data[(x, 16)] + 1e-4);
+        }
+        // The channel cuts through the hill.
+        assert!(terrain.data[(25, 16)] < 5.0);
+    }
+
+    #[test]
+    fn channel_starting_off_the_world_has_no_gaps() {
+        let mut terrain = hill(EdgePolicy::None);
+        let points = [vec2(-10.0, 16.0), vec2(30.0, 16.0)];
+        carve(&points, &RiverSettings::default(), &mut terrain, None);
+        for x in 0..30 {
+            let height = terrain.data[(x, 16)];
+            assert!(height.is_finite() && height < 5.0, "gap at {}", x);
+        }
+    }
+}
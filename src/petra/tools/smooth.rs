@@ -0,0 +1,54 @@
+use crate::petra::analysis::{self, LayerMask};
+use crate::petra::terrain::{Bounds, Terrain};
+use bevy::math::{vec2, Vec2};
+use rayon::prelude::*;
+
+const SMOOTHING_RATE: f32 = 0.5;
+
+// Pulls every cell under the brush towards the average of its 3x3 neighbourhood. All cells read
+// from the same copy of the terrain, so the rows can be smoothed in parallel. With a mask, each
+// cell only keeps its weight's share of the change. Changes past the edge of the world follow the
+// edge policy.
+pub fn trigger(xy: Vec2, radius: i64, terrain: &mut Terrain, mask: Option<&LayerMask>) {
+    let radius = radius as i32;
+    let center = (xy.x.floor() as i32, xy.y.floor() as i32);
+    let source = terrain.data.region(Bounds {
+        min: (center.0 - radius - 1, center.1 - radius - 1),
+        max: (center.0 + radius + 2, center.1 + radius + 2),
+    });
+    let mut target = source.clone();
+    let bounds = source.bounds;
+
+    target
+        .data
+        .par_chunks_mut(bounds.width() as usize)
+        .enumerate()
+        .for_each(|(row, values)| {
+            let y = bounds.min.1 + row as i32;
+            if y == bounds.min.1 || y == bounds.max.1 - 1 {
+                return;
+            }
+            for x in bounds.min.0 + 1..bounds.max.0 - 1 {
+                let offset = vec2(x as f32, y as f32) - xy;
+                let strength = (radius as f32 - offset.length()) / radius as f32;
+                if strength <= 0.0 {
+                    continue;
+                }
+                let sum: f32 = (y - 1..=y + 1)
+                    .flat_map(|neighbour_y| {
+                        (x - 1..=x + 1).map(move |neighbour_x| (neighbour_x, neighbour_y))
+                    })
+                    .map(|cell| source.get(cell).unwrap())
+                    .sum();
+                let value = &mut values[(x - bounds.min.0) as usize];
+                *value += (sum / 9.0 - *value) * strength * SMOOTHING_RATE;
+            }
+        });
+
+    if let Some(mask) = mask {
+        analysis::blend(&source, &mut target, &mask.weights(terrain, bounds));
+    }
+    terrain
+        .data
+        .apply_region_within(&source, &target, terrain.edge_policy);
+}
@@ -0,0 +1,245 @@
+use crate::petra::cursor::Region;
+use crate::petra::sampler::{EdgePolicy, Interpolation, Sampler};
+use crate::petra::terrain::{Bounds, TerrainData};
+use bevy::math::vec2;
+use rayon::prelude::*;
+
+// Terrain-wide operations. Each one builds a new TerrainData with the same chunk size, in which
+// every chunk covering the result is marked as modified. Cells outside of the old bounds read as 0.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Axis {
+    X,
+    Y,
+}
+
+impl TerrainData {
+    // Scales the terrain around the world origin, so a factor of 2 doubles the resolution. Cell
+    // centres are lined up, and the edge policy decides what the kernel reads past the edges.
+    pub fn resample(
+        &self,
+        factor: f32,
+        interpolation: Interpolation,
+        policy: EdgePolicy,
+    ) -> TerrainData {
+        let bounds = match self.bounds() {
+            Some(bounds) => bounds,
+            None => return TerrainData::zeros(self.chunk_size),
+        };
+        let min = (
+            (bounds.min.0 as f32 * factor).floor() as i32,
+            (bounds.min.1 as f32 * factor).floor() as i32,
+        );
+        let scaled = Bounds {
+            min,
+            max: (
+                ((bounds.max.0 as f32 * factor).ceil() as i32).max(min.0 + 1),
+                ((bounds.max.1 as f32 * factor).ceil() as i32).max(min.1 + 1),
+            ),
+        };
+        let mut region = Region::new(scaled);
+        region
+            .data
+            .par_chunks_mut(scaled.width() as usize)
+            .enumerate()
+            .for_each(|(row, values)| {
+                let sampler = Sampler::with_bounds(self, policy, interpolation, Some(bounds));
+                let y = scaled.min.1 + row as i32;
+                for (i, value) in values.iter_mut().enumerate() {
+                    let x = scaled.min.0 + i as i32;
+                    let source = vec2(
+                        (x as f32 + 0.5) / factor - 0.5,
+                        (y as f32 + 0.5) / factor - 0.5,
+                    );
+                    // Under EdgePolicy::None the kernel can't reach past the edge, so use the nearest cell.
+                    *value = sampler.sample(source).unwrap_or_else(|| {
+                        sampler.get_or((source.x.round() as i32, source.y.round() as i32), 0.0)
+                    });
+                }
+            });
+        TerrainData::from_region(&region, self.chunk_size)
+    }
+
+    // Moves every cell by the offset.
+    pub fn translate(&self, offset: (i32, i32)) -> TerrainData {
+        let mut region = match self.bounds() {
+            Some(bounds) => self.region(bounds),
+            None => return TerrainData::zeros(self.chunk_size),
+        };
+        region.bounds = Bounds {
+            min: (
+                region.bounds.min.0 + offset.0,
+                region.bounds.min.1 + offset.1,
+            ),
+            max: (
+                region.bounds.max.0 + offset.0,
+                region.bounds.max.1 + offset.1,
+            ),
+        };
+        TerrainData::from_region(&region, self.chunk_size)
+    }
+
+    // Turns the terrain by quarter turns, each one taking the +x direction to +y. The turned
+    // terrain keeps the same smallest corner, so it stays roughly where it was.
+    pub fn rotate(&self, quarter_turns: i32) -> TerrainData {
+        let source = match self.bounds() {
+            Some(bounds) => self.region(bounds),
+            None => return TerrainData::zeros(self.chunk_size),
+        };
+        let (min, width, height) = (
+            source.bounds.min,
+            source.bounds.width(),
+            source.bounds.height(),
+        );
+        let turns = quarter_turns.rem_euclid(4);
+        let (new_width, new_height) = if turns % 2 == 0 {
+            (width, height)
+        } else {
+            (height, width)
+        };
+        let mut target = Region::new(Bounds {
+            min,
+            max: (min.0 + new_width, min.1 + new_height),
+        });
+        for y in 0..new_height {
+            for x in 0..new_width {
+                // Where the cell came from, relative to the corner.
+                let (source_x, source_y) = match turns {
+                    0 => (x, y),
+                    1 => (y, new_width - 1 - x),
+                    2 => (width - 1 - x, height - 1 - y),
+                    _ => (new_height - 1 - y, x),
+                };
+                *target.get_mut((min.0 + x, min.1 + y)).unwrap() =
+                    source.get((min.0 + source_x, min.1 + source_y)).unwrap();
+            }
+        }
+        TerrainData::from_region(&target, self.chunk_size)
+    }
+
+    // Mirrors the terrain along the axis, in place.
+    pub fn flip(&self, axis: Axis) -> TerrainData {
+        let mut region = match self.bounds() {
+            Some(bounds) => self.region(bounds),
+            None => return TerrainData::zeros(self.chunk_size),
+        };
+        let width = region.bounds.width() as usize;
+        match axis {
+            Axis::X => region.data.chunks_mut(width).for_each(|row| row.reverse()),
+            Axis::Y => {
+                let rows: Vec<Vec<f32>> = region.data.chunks(width).rev().map(Vec::from).collect();
+                region.data = rows.concat();
+            }
+        }
+        TerrainData::from_region(&region, self.chunk_size)
+    }
+
+    // Keeps only the cells inside the bounds.
+    pub fn crop(&self, bounds: Bounds) -> TerrainData {
+        match self.bounds().and_then(|own| own.intersection(&bounds)) {
+            Some(bounds) => TerrainData::from_region(&self.region(bounds), self.chunk_size),
+            None => TerrainData::zeros(self.chunk_size),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two chunks side by side, every cell with its own height.
+    fn numbered() -> TerrainData {
+        let mut data = TerrainData::zeros(16);
+        for y in 0..16 {
+            for x in 0..32 {
+                data[(x, y)] = (x + 100 * y) as f32;
+            }
+        }
+        data
+    }
+
+    fn same(a: &TerrainData, b: &TerrainData) -> bool {
+        a.bounds() == b.bounds()
+            && a.region(a.bounds().unwrap()).data == b.region(b.bounds().unwrap()).data
+    }
+
+    #[test]
+    fn full_turns_and_double_flips_change_nothing() {
+        let data = numbered();
+        assert!(same(&data.rotate(4), &data));
+        assert!(same(&data.rotate(1).rotate(1).rotate(1).rotate(1), &data));
+        assert!(same(&data.rotate(-1), &data.rotate(3)));
+        for axis in [Axis::X, Axis::Y] {
+            assert!(same(&data.flip(axis).flip(axis), &data));
+        }
+        assert_eq!(data.flip(Axis::X)[(31, 5)], data[(0, 5)]);
+        assert_eq!(data.flip(Axis::Y)[(3, 15)], data[(3, 0)]);
+    }
+
+    #[test]
+    fn quarter_turn_moves_cells_across_chunks() {
+        let rotated = numbered().rotate(1);
+        // 32 by 16 turns into 16 by 32, with +x going to +y.
+        assert_eq!(
+            rotated.bounds(),
+            Some(Bounds {
+                min: (0, 0),
+                max: (16, 32)
+            })
+        );
+        assert_eq!(rotated[(12, 20)], numbered()[(20, 3)]);
+        assert_eq!(rotated[(15, 0)], numbered()[(0, 0)]);
+    }
+
+    #[test]
+    fn translate_keeps_every_value() {
+        let data = numbered();
+        let moved = data.translate((5, -3));
+        for y in 0..16 {
+            for x in 0..32 {
+                assert_eq!(moved[(x + 5, y - 3)], data[(x, y)]);
+            }
+        }
+    }
+
+    #[test]
+    fn crop_keeps_just_the_bounds() {
+        let data = numbered();
+        let bounds = Bounds {
+            min: (3, 2),
+            max: (20, 9),
+        };
+        let cropped = data.crop(bounds);
+        let covered = cropped.bounds().unwrap();
+        assert_eq!(
+            covered,
+            Bounds {
+                min: (0, 0),
+                max: (32, 16)
+            }
+        );
+        for y in covered.min.1..covered.max.1 {
+            for x in covered.min.0..covered.max.0 {
+                let expected = if bounds.contains((x, y)) {
+                    data[(x, y)]
+                } else {
+                    0.0
+                };
+                assert_eq!(cropped[(x, y)], expected);
+            }
+        }
+    }
+
+    #[test]
+    fn resample_by_one_changes_nothing() {
+        let data = numbered();
+        for interpolation in [Interpolation::Nearest, Interpolation::Bilinear] {
+            assert!(same(
+                &data.resample(1.0, interpolation, EdgePolicy::Clamp),
+                &data
+            ));
+        }
+        let doubled = data.resample(2.0, Interpolation::Nearest, EdgePolicy::Clamp);
+        assert_eq!(doubled[(41, 7)], data[(20, 3)]);
+    }
+}
@@ -0,0 +1,614 @@
+use crate::petra::cursor::Region;
+use crate::petra::terrain::Bounds;
+use bevy::math::{vec2, Vec2};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+// Lines and areas traced from the terrain, in world x and z, for exporting to map formats.
+
+pub enum Geometry {
+    LineString(Vec<Vec2>),
+    // Outer ring first, then any holes. Rings are closed, with the first point repeated at the end.
+    Polygon(Vec<Vec<Vec2>>),
+}
+
+pub struct Feature {
+    pub geometry: Geometry,
+    pub properties: Vec<(&'static str, f64)>,
+    // Text to write along the feature in SVG.
+    pub label: Option<String>,
+}
+
+impl Feature {
+    pub fn new(geometry: Geometry) -> Self {
+        Self {
+            geometry,
+            properties: Vec::new(),
+            label: None,
+        }
+    }
+
+    pub fn with(mut self, name: &'static str, value: f64) -> Self {
+        self.properties.push((name, value));
+        self
+    }
+
+    pub fn labelled(mut self, label: String) -> Self {
+        self.label = Some(label);
+        self
+    }
+}
+
+// For text in XML.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn json_points(points: &[Vec2]) -> String {
+    let points: Vec<String> = points
+        .iter()
+        // GeoJSON has y pointing north, which is -z.
+        .map(|point| format!("[{},{}]", point.x, -point.y))
+        .collect();
+    format!("[{}]", points.join(","))
+}
+
+// FeatureCollection in plain x and y, without a coordinate reference system.
+pub fn write_geojson(features: &[Feature], path: &str) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(writer, "{{\"type\":\"FeatureCollection\",\"features\":[")?;
+    for (i, feature) in features.iter().enumerate() {
+        let geometry = match &feature.geometry {
+            Geometry::LineString(points) => format!(
+                "{{\"type\":\"LineString\",\"coordinates\":{}}}",
+                json_points(points)
+            ),
+            Geometry::Polygon(rings) => {
+                // Flipping z turns the rings around, and GeoJSON wants outer rings counterclockwise.
+                let rings: Vec<String> = rings
+                    .iter()
+                    .map(|ring| {
+                        let mut ring = ring.clone();
+                        ring.reverse();
+                        json_points(&ring)
+                    })
+                    .collect();
+                format!(
+                    "{{\"type\":\"Polygon\",\"coordinates\":[{}]}}",
+                    rings.join(",")
+                )
+            }
+        };
+        let mut properties: Vec<String> = feature
+            .properties
+            .iter()
+            .map(|(name, value)| format!("\"{}\":{}", name, value))
+            .collect();
+        if let Some(label) = &feature.label {
+            properties.push(format!("\"label\":{:?}", label));
+        }
+        writeln!(
+            writer,
+            "{{\"type\":\"Feature\",\"geometry\":{},\"properties\":{{{}}}}}{}",
+            geometry,
+            properties.join(","),
+            if i + 1 < features.len() { "," } else { "" }
+        )?;
+    }
+    writeln!(writer, "]}}")?;
+    writer.flush()
+}
+
+// Path data through the points, for the d attribute.
+pub fn svg_path(points: &[Vec2]) -> String {
+    let points: Vec<String> = points
+        .iter()
+        .map(|point| format!("{} {}", point.x, point.y))
+        .collect();
+    format!("M{}", points.join(" L"))
+}
+
+// Middle of a line by length, and the direction of the line there.
+fn midpoint(points: &[Vec2]) -> (Vec2, Vec2) {
+    let half = points
+        .windows(2)
+        .map(|pair| pair[0].distance(pair[1]))
+        .sum::<f32>()
+        / 2.0;
+    let mut travelled = 0.0;
+    for pair in points.windows(2) {
+        let length = pair[0].distance(pair[1]);
+        if travelled + length >= half && length > 0.0 {
+            let direction = (pair[1] - pair[0]) / length;
+            return (pair[0] + direction * (half - travelled), direction);
+        }
+        travelled += length;
+    }
+    (points[0], Vec2::X)
+}
+
+// SVG in world x and z, which puts north at the top, framed around all of the features. Every
+// feature is a path with the attributes style gives it, like fill and stroke. Labels are written
+// at the middle of lines and turned to follow them, the right way up, over a white halo.
+pub fn write_svg(
+    features: &[Feature],
+    style: impl Fn(&Feature) -> String,
+    path: &str,
+) -> io::Result<()> {
+    let (min, max) = features
+        .iter()
+        .flat_map(|feature| match &feature.geometry {
+            Geometry::LineString(points) => points.iter(),
+            Geometry::Polygon(rings) => rings[0].iter(),
+        })
+        .fold(
+            (Vec2::splat(f32::INFINITY), Vec2::splat(f32::NEG_INFINITY)),
+            |(min, max), point| (min.min(*point), max.max(*point)),
+        );
+    let (min, size) = if min.x <= max.x {
+        (min, max - min)
+    } else {
+        (Vec2::ZERO, Vec2::ONE)
+    };
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(
+        writer,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"{} {} {} {}\" width=\"{}\" height=\"{}\">",
+        min.x, min.y, size.x, size.y, size.x, size.y
+    )?;
+    for feature in features {
+        let d = match &feature.geometry {
+            Geometry::LineString(points) => svg_path(points),
+            Geometry::Polygon(rings) => {
+                let rings: Vec<String> = rings
+                    .iter()
+                    .map(|ring| format!("{}Z", svg_path(ring)))
+                    .collect();
+                rings.join(" ")
+            }
+        };
+        writeln!(
+            writer,
+            "<path d=\"{}\" fill-rule=\"evenodd\" {}/>",
+            d,
+            style(feature)
+        )?;
+    }
+    let font_size = size.x.max(size.y) / 100.0;
+    for feature in features {
+        let (label, points) = match (&feature.label, &feature.geometry) {
+            (Some(label), Geometry::LineString(points)) => (label, points),
+            (Some(label), Geometry::Polygon(rings)) => (label, &rings[0]),
+            _ => continue,
+        };
+        let (position, direction) = midpoint(points);
+        let mut angle = direction.y.atan2(direction.x).to_degrees();
+        if angle > 90.0 {
+            angle -= 180.0;
+        } else if angle <= -90.0 {
+            angle += 180.0;
+        }
+        writeln!(
+            writer,
+            concat!(
+                "<text x=\"{x}\" y=\"{y}\" transform=\"rotate({angle} {x} {y})\" ",
+                "font-size=\"{size}\" font-family=\"sans-serif\" text-anchor=\"middle\" ",
+                "dominant-baseline=\"middle\" stroke=\"white\" stroke-width=\"{halo}\" ",
+                "paint-order=\"stroke\">{label}</text>"
+            ),
+            x = position.x,
+            y = position.y,
+            angle = angle,
+            size = font_size,
+            halo = font_size / 4.0,
+            label = escape(label)
+        )?;
+    }
+    writeln!(writer, "</svg>")?;
+    writer.flush()
+}
+
+// Twice the signed area of a closed ring, positive when it goes clockwise on screen (x right, z
+// down), like the rings from outline_labels.
+pub fn signed_area(ring: &[Vec2]) -> f32 {
+    ring.windows(2).map(|pair| pair[0].perp_dot(pair[1])).sum()
+}
+
+pub fn contains(ring: &[Vec2], point: Vec2) -> bool {
+    let mut inside = false;
+    for pair in ring.windows(2) {
+        let (a, b) = (pair[0], pair[1]);
+        if (a.y > point.y) != (b.y > point.y)
+            && point.x < a.x + (point.y - a.y) / (b.y - a.y) * (b.x - a.x)
+        {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+// Outlines of every labelled area in a grid of labels over the bounds, along the edges of the
+// cells, in world units. Label 0 is left out. Areas that only touch at a corner get separate
+// polygons, each made of an outer ring and its holes.
+pub fn outline_labels(
+    labels: &[u32],
+    bounds: Bounds,
+    cell_size: f32,
+) -> BTreeMap<u32, Vec<Vec<Vec<Vec2>>>> {
+    let width = bounds.width();
+    let label_at = |x: i32, y: i32| {
+        if x < 0 || y < 0 || x >= width || y >= bounds.height() {
+            0
+        } else {
+            labels[(y * width + x) as usize]
+        }
+    };
+
+    // Edges between a cell and a neighbour with another label, going clockwise around the cell.
+    // Corner (x, y) is the top left corner of cell (x, y).
+    type Edge = ((i32, i32), (i32, i32), u32);
+    let mut edges: Vec<Edge> = Vec::new();
+    for y in 0..bounds.height() {
+        for x in 0..width {
+            let label = label_at(x, y);
+            if label == 0 {
+                continue;
+            }
+            if label_at(x, y - 1) != label {
+                edges.push(((x, y), (x + 1, y), label));
+            }
+            if label_at(x + 1, y) != label {
+                edges.push(((x + 1, y), (x + 1, y + 1), label));
+            }
+            if label_at(x, y + 1) != label {
+                edges.push(((x + 1, y + 1), (x, y + 1), label));
+            }
+            if label_at(x - 1, y) != label {
+                edges.push(((x, y + 1), (x, y), label));
+            }
+        }
+    }
+    let mut starting: HashMap<(u32, (i32, i32)), Vec<usize>> = HashMap::new();
+    for (i, (from, _, label)) in edges.iter().enumerate() {
+        starting.entry((*label, *from)).or_default().push(i);
+    }
+
+    let corner = |(x, y): (i32, i32)| {
+        vec2(
+            (bounds.min.0 + x) as f32 - 0.5,
+            (bounds.min.1 + y) as f32 - 0.5,
+        ) * cell_size
+    };
+    let mut used = vec![false; edges.len()];
+    let mut rings: BTreeMap<u32, Vec<Vec<Vec2>>> = BTreeMap::new();
+    for first in 0..edges.len() {
+        if used[first] {
+            continue;
+        }
+        let (start, _, label) = edges[first];
+        let mut corners = vec![start];
+        let mut current = first;
+        loop {
+            used[current] = true;
+            let (from, to, _) = edges[current];
+            if to == start {
+                break;
+            }
+            corners.push(to);
+            // Where two cells touch at a corner, turn into the cell the ring came along, so the
+            // ring doesn't cross over to the other one.
+            let direction = (to.0 - from.0, to.1 - from.1);
+            current = *starting[&(label, to)]
+                .iter()
+                .filter(|edge| !used[**edge])
+                .max_by_key(|edge| {
+                    let (next_from, next_to, _) = edges[**edge];
+                    let next = (next_to.0 - next_from.0, next_to.1 - next_from.1);
+                    direction.0 * next.1 - direction.1 * next.0
+                })
+                .unwrap();
+        }
+        // Only the corners where the ring turns.
+        let count = corners.len();
+        let mut ring: Vec<Vec2> = (0..count)
+            .filter(|i| {
+                let (previous, here, next) = (
+                    corners[(i + count - 1) % count],
+                    corners[*i],
+                    corners[(i + 1) % count],
+                );
+                (here.0 - previous.0) * (next.1 - here.1)
+                    != (here.1 - previous.1) * (next.0 - here.0)
+            })
+            .map(|i| corner(corners[i]))
+            .collect();
+        ring.push(ring[0]);
+        rings.entry(label).or_default().push(ring);
+    }
+
+    rings
+        .into_iter()
+        .map(|(label, rings)| (label, polygons(rings)))
+        .collect()
+}
+
+// Sorts closed rings into polygons. Clockwise rings are outlines, counterclockwise ones are holes
+// in the smallest outline around them.
+pub fn polygons(rings: Vec<Vec<Vec2>>) -> Vec<Vec<Vec<Vec2>>> {
+    let (outers, holes): (Vec<_>, Vec<_>) =
+        rings.into_iter().partition(|ring| signed_area(ring) > 0.0);
+    let mut polygons: Vec<Vec<Vec<Vec2>>> = outers.into_iter().map(|outer| vec![outer]).collect();
+    for hole in holes {
+        let inside = hole[0] + (hole[1] - hole[0]) / 2.0;
+        if let Some(polygon) = polygons
+            .iter_mut()
+            .filter(|polygon| contains(&polygon[0], inside))
+            .min_by(|a, b| signed_area(&a[0]).total_cmp(&signed_area(&b[0])))
+        {
+            polygon.push(hole);
+        }
+    }
+    polygons
+}
+
+// Closed rings around everywhere the heights are above the level, with marching squares between
+// cell centres, in world units. Past the edge of the heights counts as below the level, so rings
+// close half a cell outside the last cells. Rings go clockwise around high ground and
+// counterclockwise around low ground inside it, like polygons() wants.
+pub fn marching_squares(heights: &Region, level: f32, cell_size: f32) -> Vec<Vec<Vec2>> {
+    isolines(heights, level, cell_size, true)
+}
+
+// Lines where the heights cross the level, with high ground on their right. Lines that reach the
+// edge of the heights stop there, the rest are closed.
+pub fn contour_lines(heights: &Region, level: f32, cell_size: f32) -> Vec<Vec<Vec2>> {
+    isolines(heights, level, cell_size, false)
+}
+
+fn isolines(heights: &Region, level: f32, cell_size: f32, close_at_edges: bool) -> Vec<Vec<Vec2>> {
+    let bounds = heights.bounds;
+    let height = |cell: (i32, i32)| heights.get(cell);
+    let above = |value: Option<f32>| matches!(value, Some(value) if value > level);
+    // Crossings are keyed by the cell at the top or left end of their edge, and whether the edge
+    // goes along x (0) or z (1).
+    let crossing = |a: (i32, i32), b: (i32, i32)| {
+        let t = match (height(a), height(b)) {
+            (Some(a), Some(b)) => (level - a) / (b - a),
+            _ => 0.5,
+        };
+        let point = vec2(
+            a.0 as f32 + (b.0 - a.0) as f32 * t,
+            a.1 as f32 + (b.1 - a.1) as f32 * t,
+        ) * cell_size;
+        let key = if a.0 == b.0 {
+            (a.0, a.1.min(b.1), 1)
+        } else {
+            (a.0.min(b.0), a.1, 0)
+        };
+        (key, point)
+    };
+
+    let mut next: HashMap<(i32, i32, i32), (i32, i32, i32)> = HashMap::new();
+    let mut points: HashMap<(i32, i32, i32), Vec2> = HashMap::new();
+    for y in bounds.min.1 - 1..bounds.max.1 {
+        for x in bounds.min.0 - 1..bounds.max.0 {
+            // Corners clockwise from the top left, and the edges after each of them.
+            let corners = [(x, y), (x + 1, y), (x + 1, y + 1), (x, y + 1)];
+            let values = corners.map(height);
+            if !close_at_edges && values.contains(&None) {
+                continue;
+            }
+            let high = values.map(above);
+            let crossed: Vec<usize> = (0..4).filter(|i| high[*i] != high[(i + 1) % 4]).collect();
+            // Pairs of edges that cut off the corner between them.
+            let cut_corners: Vec<usize> = match crossed.len() {
+                2 if (crossed[1] - crossed[0]) % 2 == 1 => {
+                    vec![if crossed[0] == 0 && crossed[1] == 3 {
+                        0
+                    } else {
+                        crossed[1]
+                    }]
+                }
+                // Straight through the square, between the two halves.
+                2 => {
+                    let (a, b) = (crossed[0], crossed[1]);
+                    // The corner after a is on the left going from a to b.
+                    let (from, to) = if high[(a + 1) % 4] { (b, a) } else { (a, b) };
+                    let from = crossing(corners[from], corners[(from + 1) % 4]);
+                    let to = crossing(corners[to], corners[(to + 1) % 4]);
+                    next.insert(from.0, to.0);
+                    points.insert(from.0, from.1);
+                    points.insert(to.0, to.1);
+                    continue;
+                }
+                // Saddle, decided by the middle of the square.
+                4 => {
+                    let center = values
+                        .iter()
+                        .map(|value| value.unwrap_or(level))
+                        .sum::<f32>()
+                        / 4.0;
+                    if (center > level) == high[0] {
+                        vec![1, 3]
+                    } else {
+                        vec![0, 2]
+                    }
+                }
+                _ => continue,
+            };
+            for corner in cut_corners {
+                // Going from the edge before the corner to the edge after it has the corner on the
+                // left, and the high side has to be on the right.
+                let before = (corner + 3) % 4;
+                let into = crossing(corners[before], corners[corner]);
+                let out = crossing(corners[corner], corners[(corner + 1) % 4]);
+                let (from, to) = if high[corner] {
+                    (out, into)
+                } else {
+                    (into, out)
+                };
+                next.insert(from.0, to.0);
+                points.insert(from.0, from.1);
+                points.insert(to.0, to.1);
+            }
+        }
+    }
+
+    // Open lines first, from the ends nothing leads into, then what's left are rings.
+    let mut lines = Vec::new();
+    let ends: HashSet<_> = next.values().copied().collect();
+    let mut starts: Vec<_> = next.keys().copied().collect();
+    starts.sort_by_key(|start| ends.contains(start));
+    for start in starts {
+        if !next.contains_key(&start) {
+            continue;
+        }
+        let mut line = vec![points[&start]];
+        let mut key = start;
+        while let Some(following) = next.remove(&key) {
+            line.push(points[&following]);
+            key = following;
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+// Douglas-Peucker simplification, keeping every point further than the tolerance from the
+// simplified line. Closed lines stay closed.
+pub fn simplify_line(points: &[Vec2], tolerance: f32) -> Vec<Vec2> {
+    if points.len() < 3 {
+        return points.to_vec();
+    }
+    let last = points.len() - 1;
+    if points[0] == points[last] {
+        // Split at the point furthest from the start, since a closed line has no chord to measure
+        // from.
+        let furthest = (1..last)
+            .max_by(|a, b| {
+                points[*a]
+                    .distance_squared(points[0])
+                    .total_cmp(&points[*b].distance_squared(points[0]))
+            })
+            .unwrap();
+        let mut simplified = simplify_line(&points[..=furthest], tolerance);
+        simplified.pop();
+        simplified.extend(simplify_line(&points[furthest..], tolerance));
+        return simplified;
+    }
+    let mut keep = vec![false; points.len()];
+    keep[0] = true;
+    keep[last] = true;
+    let mut stack = vec![(0, last)];
+    while let Some((first, last)) = stack.pop() {
+        let (a, b) = (points[first], points[last]);
+        let chord = b - a;
+        let distance = |point: Vec2| {
+            if chord == Vec2::ZERO {
+                point.distance(a)
+            } else {
+                chord.perp_dot(point - a).abs() / chord.length()
+            }
+        };
+        if let Some((i, furthest)) = (first + 1..last)
+            .map(|i| (i, distance(points[i])))
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+        {
+            if furthest > tolerance {
+                keep[i] = true;
+                stack.push((first, i));
+                stack.push((i, last));
+            }
+        }
+    }
+    points
+        .iter()
+        .zip(keep)
+        .filter(|(_, keep)| *keep)
+        .map(|(point, _)| *point)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Heights from a function of the cell, over a square of cells from 0 to size.
+    fn heights(size: i32, height: impl Fn(i32, i32) -> f32) -> Region {
+        let mut heights = Region::new(Bounds {
+            min: (0, 0),
+            max: (size, size),
+        });
+        for y in 0..size {
+            for x in 0..size {
+                *heights.get_mut((x, y)).unwrap() = height(x, y);
+            }
+        }
+        heights
+    }
+
+    fn closed(ring: &[Vec2]) -> bool {
+        ring.len() > 3 && ring.first() == ring.last()
+    }
+
+    #[test]
+    fn peak_is_one_clockwise_ring() {
+        let peak = heights(5, |x, y| if (x, y) == (2, 2) { 1.0 } else { 0.0 });
+        let rings = marching_squares(&peak, 0.5, 2.0);
+        assert_eq!(rings.len(), 1);
+        assert!(closed(&rings[0]));
+        assert!(signed_area(&rings[0]) > 0.0);
+        assert!(contains(&rings[0], vec2(4.0, 4.0)));
+        assert!(!contains(&rings[0], vec2(2.0, 4.0)));
+    }
+
+    #[test]
+    fn rings_close_past_the_edge() {
+        let plateau = heights(3, |_, _| 1.0);
+        let rings = marching_squares(&plateau, 0.5, 1.0);
+        assert_eq!(rings.len(), 1);
+        assert!(closed(&rings[0]));
+        assert!(signed_area(&rings[0]) > 0.0);
+        for point in &rings[0] {
+            assert!(point.min_element() >= -0.5 && point.max_element() <= 2.5);
+        }
+        assert!(contains(&rings[0], vec2(0.0, 0.0)));
+        assert!(contains(&rings[0], vec2(2.0, 2.0)));
+    }
+
+    #[test]
+    fn island_in_a_hole_is_a_polygon_of_its_own() {
+        // A square wall two cells thick around a moat, with an island in the middle.
+        let walled = heights(9, |x, y| {
+            let distance = (x - 4).abs().max((y - 4).abs());
+            if distance == 0 || distance == 2 || distance == 3 {
+                1.0
+            } else {
+                0.0
+            }
+        });
+        let rings = marching_squares(&walled, 0.5, 1.0);
+        assert_eq!(rings.len(), 3);
+        assert!(rings.iter().all(|ring| closed(ring)));
+        // The wall's outline and the island go clockwise, the moat counterclockwise.
+        let clockwise = rings.iter().filter(|ring| signed_area(ring) > 0.0).count();
+        assert_eq!(clockwise, 2);
+
+        let mut polygons = polygons(rings);
+        polygons.sort_by_key(|polygon| polygon.len());
+        assert_eq!(polygons.len(), 2);
+        let (island, wall) = (&polygons[0], &polygons[1]);
+        assert_eq!(island.len(), 1);
+        assert!(contains(&island[0], vec2(4.0, 4.0)));
+        assert_eq!(wall.len(), 2);
+        assert!(signed_area(&wall[1]) < 0.0);
+        // The island is in the hole, and the hole in the wall.
+        assert!(contains(&wall[1], vec2(4.0, 4.0)));
+        assert!(contains(&wall[0], vec2(4.0, 5.0)));
+        assert!(!contains(&wall[1], vec2(4.0, 6.0)));
+    }
+}